@@ -0,0 +1,13 @@
+ALTER TABLE blog_admin_users
+  ADD COLUMN role text NOT NULL DEFAULT 'owner'
+    CHECK (role IN ('owner', 'editor', 'author')),
+  ADD COLUMN disabled boolean NOT NULL DEFAULT false,
+  ADD COLUMN created_at timestamp NOT NULL DEFAULT now();
+
+-- New users default to the least privileged role; existing rows above keep
+-- 'owner' so nobody is locked out by the migration.
+ALTER TABLE blog_admin_users ALTER COLUMN role SET DEFAULT 'author';
+
+ALTER TABLE blog_posts
+  ADD COLUMN author_id text
+    REFERENCES blog_admin_users (id) ON DELETE SET NULL;
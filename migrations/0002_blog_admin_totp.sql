@@ -0,0 +1,5 @@
+ALTER TABLE blog_admin_users
+  ADD COLUMN totp_secret text,
+  ADD COLUMN totp_enabled boolean NOT NULL DEFAULT false,
+  -- argon2 hashes of the unused recovery codes.
+  ADD COLUMN totp_recovery_codes text[] NOT NULL DEFAULT '{}';
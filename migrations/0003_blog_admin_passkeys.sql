@@ -0,0 +1,12 @@
+CREATE TABLE blog_admin_passkeys (
+  -- Base64url credential id, as sent by the authenticator.
+  id text PRIMARY KEY,
+  user_id text NOT NULL REFERENCES blog_admin_users (id) ON DELETE CASCADE,
+  name text,
+  -- Serialized webauthn-rs `Passkey` (public key, counter, flags).
+  passkey jsonb NOT NULL,
+  created_at timestamp NOT NULL DEFAULT now(),
+  last_used_at timestamp
+);
+
+CREATE INDEX blog_admin_passkeys_user_id_idx ON blog_admin_passkeys (user_id);
@@ -0,0 +1,19 @@
+CREATE TABLE blog_comments (
+  id text PRIMARY KEY DEFAULT id_generator(),
+  post_id text NOT NULL REFERENCES blog_posts (id) ON DELETE CASCADE,
+  -- Replies point at the comment they answer; removing it removes the thread.
+  parent_id text REFERENCES blog_comments (id) ON DELETE CASCADE,
+  author_name text NOT NULL,
+  author_email text,
+  body text NOT NULL,
+  status text NOT NULL DEFAULT 'pending'
+    CHECK (status IN ('pending', 'approved', 'rejected')),
+  ip text,
+  user_agent text,
+  created_at timestamp NOT NULL DEFAULT now(),
+  moderated_at timestamp,
+  moderated_by text REFERENCES blog_admin_users (id) ON DELETE SET NULL
+);
+
+CREATE INDEX blog_comments_post_id_status_idx ON blog_comments (post_id, status);
+CREATE INDEX blog_comments_status_created_at_idx ON blog_comments (status, created_at);
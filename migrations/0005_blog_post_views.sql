@@ -0,0 +1,9 @@
+-- Daily unique-visitor view counts, flushed in from Valkey by the API.
+CREATE TABLE blog_post_views (
+  post_id text NOT NULL REFERENCES blog_posts (id) ON DELETE CASCADE,
+  day date NOT NULL,
+  views integer NOT NULL DEFAULT 0,
+  PRIMARY KEY (post_id, day)
+);
+
+CREATE INDEX blog_post_views_day_idx ON blog_post_views (day);
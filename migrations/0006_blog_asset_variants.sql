@@ -0,0 +1,6 @@
+-- Pixel dimensions of image assets, and the widths responsive variants were
+-- generated at (stored as blog/assets/{hash}/{width}.{avif,webp}).
+ALTER TABLE blog_assets
+  ADD COLUMN width integer,
+  ADD COLUMN height integer,
+  ADD COLUMN variants integer[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,5 @@
+-- When a post was moved to the trash. Trashed posts are purged, along with
+-- their assets, once BLOG_TRASH_RETENTION_DAYS have passed.
+ALTER TABLE blog_posts ADD COLUMN deleted_at timestamp;
+
+UPDATE blog_posts SET deleted_at = now() WHERE visibility = 'deleted';
@@ -0,0 +1,9 @@
+-- Slugs a post was previously published under, so old links can redirect
+-- to the current one.
+CREATE TABLE blog_post_slugs (
+  slug text PRIMARY KEY,
+  post_id text NOT NULL REFERENCES blog_posts (id) ON DELETE CASCADE,
+  created_at timestamp NOT NULL DEFAULT now()
+);
+
+CREATE INDEX blog_post_slugs_post_id_idx ON blog_post_slugs (post_id);
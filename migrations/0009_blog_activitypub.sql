@@ -0,0 +1,16 @@
+-- Signing key for the blog's ActivityPub actor. Single row, created on
+-- first use.
+CREATE TABLE blog_activitypub_keys (
+  id integer PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+  private_key text NOT NULL,
+  public_key text NOT NULL,
+  created_at timestamp NOT NULL DEFAULT now()
+);
+
+-- Remote actors following the blog, and where to deliver new posts.
+CREATE TABLE blog_followers (
+  actor text PRIMARY KEY,
+  inbox text NOT NULL,
+  shared_inbox text,
+  created_at timestamp NOT NULL DEFAULT now()
+);
@@ -0,0 +1,47 @@
+-- Photos move out of the `photography_albums.items` JSON array into their
+-- own rows, so single photos can be queried and edited without rewriting
+-- the whole album.
+CREATE TABLE photography_photos (
+  album_slug text NOT NULL
+    REFERENCES photography_albums (slug) ON DELETE CASCADE ON UPDATE CASCADE,
+  name text NOT NULL,
+  position integer NOT NULL,
+  caption text,
+  instagram text,
+  frame jsonb,
+  exif jsonb,
+  width integer,
+  height integer,
+  aspect_ratio double precision,
+  variants jsonb NOT NULL DEFAULT '[]'::jsonb,
+  blurhash text,
+  lqip text,
+  uploaded_at timestamp NOT NULL DEFAULT now(),
+  PRIMARY KEY (album_slug, name)
+);
+
+CREATE INDEX photography_photos_position_idx
+  ON photography_photos (album_slug, position);
+
+INSERT INTO photography_photos (
+  album_slug, name, position, caption, instagram, frame, exif, width,
+  height, aspect_ratio, variants, blurhash, lqip
+)
+SELECT
+  a.slug,
+  item ->> 'name',
+  (t.ordinality - 1)::integer,
+  item ->> 'caption',
+  item ->> 'instagram',
+  NULLIF(item -> 'frame', 'null'::jsonb),
+  NULLIF(item -> 'exif', 'null'::jsonb),
+  (item ->> 'width')::integer,
+  (item ->> 'height')::integer,
+  (item ->> 'aspect_ratio')::double precision,
+  COALESCE(NULLIF(item -> 'variants', 'null'::jsonb), '[]'::jsonb),
+  item ->> 'blurhash',
+  item ->> 'lqip'
+FROM photography_albums a,
+  jsonb_array_elements(a.items::jsonb) WITH ORDINALITY AS t (item, ordinality);
+
+ALTER TABLE photography_albums DROP COLUMN items;
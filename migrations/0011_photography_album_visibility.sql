@@ -0,0 +1,18 @@
+-- Albums get an explicit visibility instead of being hidden whenever they
+-- have no photos. `password` albums keep an argon2 hash of their password
+-- and are only shown once unlocked.
+ALTER TABLE photography_albums
+  ADD COLUMN visibility text NOT NULL DEFAULT 'public'
+    CHECK (visibility IN ('draft', 'unlisted', 'public', 'password')),
+  ADD COLUMN password text,
+  ADD CONSTRAINT photography_albums_password_check
+    CHECK (visibility <> 'password' OR password IS NOT NULL);
+
+-- Empty albums were hidden from the public listing; keep them that way.
+UPDATE photography_albums a SET visibility = 'draft'
+WHERE NOT EXISTS (
+  SELECT 1 FROM photography_photos p WHERE p.album_slug = a.slug
+);
+
+-- New albums start out as drafts.
+ALTER TABLE photography_albums ALTER COLUMN visibility SET DEFAULT 'draft';
@@ -0,0 +1,10 @@
+-- Free-form keywords describing what's in a photo, and tags grouping
+-- photos across albums. Both are searched by `/photography/search`.
+ALTER TABLE photography_photos
+  ADD COLUMN keywords text[] NOT NULL DEFAULT '{}',
+  ADD COLUMN tags text[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX photography_photos_keywords_idx
+  ON photography_photos USING gin (keywords);
+CREATE INDEX photography_photos_tags_idx
+  ON photography_photos USING gin (tags);
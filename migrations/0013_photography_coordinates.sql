@@ -0,0 +1,19 @@
+-- Coordinates for albums and photos, in decimal degrees. Photos get them
+-- from EXIF GPS on upload; either can also be set by hand.
+ALTER TABLE photography_albums
+  ADD COLUMN latitude double precision,
+  ADD COLUMN longitude double precision,
+  ADD CONSTRAINT photography_albums_coordinates_check
+    CHECK ((latitude IS NULL) = (longitude IS NULL));
+
+ALTER TABLE photography_photos
+  ADD COLUMN latitude double precision,
+  ADD COLUMN longitude double precision,
+  ADD CONSTRAINT photography_photos_coordinates_check
+    CHECK ((latitude IS NULL) = (longitude IS NULL));
+
+UPDATE photography_photos SET
+  latitude = (exif -> 'gps' ->> 'latitude')::double precision,
+  longitude = (exif -> 'gps' ->> 'longitude')::double precision
+WHERE exif -> 'gps' ->> 'latitude' IS NOT NULL
+  AND exif -> 'gps' ->> 'longitude' IS NOT NULL;
@@ -0,0 +1,29 @@
+-- Uploads sent in chunks, each chunk becoming a part of an S3 multipart
+-- upload, so files larger than a single request can hold can be resumed
+-- after a dropped connection.
+CREATE TABLE resumable_uploads (
+  id text PRIMARY KEY,
+  -- What the file becomes once complete: a CDN image or file, a photo in
+  -- an album, or a blog post asset.
+  kind text NOT NULL
+    CHECK (kind IN ('images', 'files', 'photography', 'blog')),
+  -- Album slug for photos, post id for blog assets.
+  target text,
+  file_name text,
+  -- Blog admin who started a blog asset upload; management uploads have
+  -- none.
+  owner_id text,
+  size bigint NOT NULL CHECK (size > 0),
+  received bigint NOT NULL DEFAULT 0,
+  -- Sniffed from the first chunk, which is also when the S3 upload starts.
+  content_type text,
+  s3_upload_id text,
+  etags text[] NOT NULL DEFAULT '{}',
+  created_at timestamp NOT NULL DEFAULT now(),
+  updated_at timestamp NOT NULL DEFAULT now(),
+  CHECK (received <= size),
+  CHECK ((kind IN ('photography', 'blog')) = (target IS NOT NULL))
+);
+
+CREATE INDEX resumable_uploads_updated_at_idx
+  ON resumable_uploads (updated_at);
@@ -0,0 +1,7 @@
+-- Completing an upload runs outside of any transaction, so the row records
+-- that it is under way, and whether the photo it becomes has been added
+-- yet, for a retry to pick up from.
+ALTER TABLE resumable_uploads
+  ADD COLUMN state text NOT NULL DEFAULT 'receiving'
+    CHECK (state IN ('receiving', 'completing')),
+  ADD COLUMN handed_off boolean NOT NULL DEFAULT false;
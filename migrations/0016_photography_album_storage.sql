@@ -0,0 +1,9 @@
+-- Where under `gallery/albums/` an album's files are kept. Public and
+-- unlisted albums use their slug; draft and password albums are moved to a
+-- directory with a random suffix, so their files can't be fetched from the
+-- CDN by guessing the slug. Existing albums are moved by the server.
+ALTER TABLE photography_albums ADD COLUMN storage_dir text;
+UPDATE photography_albums SET storage_dir = slug;
+ALTER TABLE photography_albums
+  ALTER COLUMN storage_dir SET NOT NULL,
+  ADD CONSTRAINT photography_albums_storage_dir_key UNIQUE (storage_dir);
@@ -0,0 +1,5 @@
+-- `/posts/popular` lists the most viewed posts, so a post can't be
+-- reached under that slug. Move any that has it out of the way.
+UPDATE blog_posts SET slug = 'popular-' || id WHERE slug = 'popular';
+
+DELETE FROM blog_post_slugs WHERE slug = 'popular';
@@ -14,6 +14,12 @@ pub struct Config {
   #[envconfig(from = "METRICS_LISTEN_PORT", default = "8081")]
   pub metrics_listen_port: u16,
 
+  /// Comma separated addresses or CIDR ranges of reverse proxies whose
+  /// `X-Forwarded-For` is trusted for the client address. Everything else
+  /// is identified by its peer address.
+  #[envconfig(from = "TRUSTED_PROXIES", default = "")]
+  pub trusted_proxies: String,
+
   #[envconfig(
     from = "SPOTIFY_CLIENT_ID",
     default = "01ba26764aca4594a26f4cc59cd3f01f"
@@ -71,6 +77,25 @@ pub struct Config {
   #[envconfig(from = "S3_FILES_ALIAS", default = "files.dstn.to")]
   pub s3_files_alias: String,
 
+  /// Largest file a resumable upload can be started for. Finished uploads
+  /// are moved into place with a single S3 copy, which tops out at 5 GiB.
+  #[envconfig(from = "UPLOAD_MAX_SIZE", default = "5368709120")]
+  pub upload_max_size: i64,
+
+  /// Largest chunk a single request of a resumable upload can carry.
+  #[envconfig(from = "UPLOAD_MAX_CHUNK_SIZE", default = "67108864")]
+  pub upload_max_chunk_size: usize,
+
+  /// Largest resumable upload that is read back into memory to be decoded
+  /// once complete: photos, and blog assets that are images.
+  #[envconfig(from = "UPLOAD_MAX_DECODE_SIZE", default = "268435456")]
+  pub upload_max_decode_size: i64,
+
+  /// Hours a resumable upload can go without a new chunk before it is
+  /// cleaned up.
+  #[envconfig(from = "UPLOAD_ABANDON_HOURS", default = "24")]
+  pub upload_abandon_hours: i32,
+
   #[envconfig(from = "GITHUB_PAT", default = "")]
   pub github_pat: String,
 
@@ -109,4 +134,70 @@ pub struct Config {
 
   #[envconfig(from = "RIDERR_API_TOKEN", default = "")]
   pub riderr_api_token: String,
+
+  /// Domain in the blog's fediverse handle (`@{username}@{domain}`).
+  /// `/.well-known/webfinger` on this domain has to reach the API.
+  #[envconfig(from = "BLOG_ACTIVITYPUB_DOMAIN", default = "dstn.to")]
+  pub blog_activitypub_domain: String,
+
+  #[envconfig(from = "BLOG_ACTIVITYPUB_USERNAME", default = "blog")]
+  pub blog_activitypub_username: String,
+
+  #[envconfig(from = "BLOG_ACTIVITYPUB_NAME", default = "dstn.to")]
+  pub blog_activitypub_name: String,
+
+  /// Public URL of the `/v2/blog/activitypub` routes. Actor, post and
+  /// activity ids are built from it.
+  #[envconfig(
+    from = "BLOG_ACTIVITYPUB_URL",
+    default = "https://api.dstn.to/v2/blog/activitypub"
+  )]
+  pub blog_activitypub_url: String,
+
+  /// Public URL of the blog itself; posts live at `{BLOG_URL}/{slug}`.
+  #[envconfig(from = "BLOG_URL", default = "https://dstn.to/blog")]
+  pub blog_url: String,
+
+  /// Public base URL the `gallery/albums/` prefix of the CDN bucket is
+  /// served from.
+  #[envconfig(
+    from = "PHOTOGRAPHY_URL",
+    default = "https://cdn.dstn.to/gallery/albums"
+  )]
+  pub photography_url: String,
+
+  /// Key unlock tokens for password-protected albums are signed with.
+  /// Albums can't be unlocked while it's empty.
+  #[envconfig(from = "PHOTOGRAPHY_UNLOCK_SECRET", default = "")]
+  pub photography_unlock_secret: String,
+
+  /// Decimal places photo and album coordinates are rounded to publicly.
+  /// 3 is roughly 100m.
+  #[envconfig(from = "PHOTOGRAPHY_COORDINATE_PRECISION", default = "3")]
+  pub photography_coordinate_precision: i32,
+
+  /// Public base URL the `blog/assets/` prefix of the CDN bucket is served
+  /// from.
+  #[envconfig(
+    from = "BLOG_ASSETS_URL",
+    default = "https://cdn.dstn.to/blog/assets"
+  )]
+  pub blog_assets_url: String,
+
+  /// Seconds of inactivity before a blog admin session expires.
+  #[envconfig(from = "BLOG_SESSION_TTL", default = "1209600")]
+  pub blog_session_ttl: u64,
+
+  /// Days a deleted post stays in the trash before it is purged.
+  #[envconfig(from = "BLOG_TRASH_RETENTION_DAYS", default = "30")]
+  pub blog_trash_retention_days: i32,
+
+  #[envconfig(from = "BLOG_TOTP_ISSUER", default = "dstn.to")]
+  pub blog_totp_issuer: String,
+
+  #[envconfig(from = "BLOG_WEBAUTHN_RP_ID", default = "dstn.to")]
+  pub blog_webauthn_rp_id: String,
+
+  #[envconfig(from = "BLOG_WEBAUTHN_ORIGIN", default = "https://dstn.to")]
+  pub blog_webauthn_origin: String,
 }
@@ -20,6 +20,11 @@ lazy_static! {
     &["command", "action"]
   )
   .expect("metric can be created");
+  pub static ref AUTH_FAILURES: IntCounterVec = IntCounterVec::new(
+    Opts::new("dstn_api_auth_failures", "Failed Authentication Attempts"),
+    &["scope"]
+  )
+  .expect("metric can be created");
   pub static ref RESPONSE_TIME_COLLECTOR: Histogram =
     Histogram::with_opts(HistogramOpts::new(
       "dstn_api_response_time",
@@ -38,6 +43,10 @@ impl ApiMetrics {
       .register(Box::new(INCOMING_REQUESTS.clone()))
       .expect("collector can be registered");
 
+    REGISTRY
+      .register(Box::new(AUTH_FAILURES.clone()))
+      .expect("collector can be registered");
+
     REGISTRY
       .register(Box::new(RESPONSE_TIME_COLLECTOR.clone()))
       .expect("collector can be registered");
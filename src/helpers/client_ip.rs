@@ -0,0 +1,148 @@
+use std::net::IpAddr;
+
+use actix_web::HttpRequest;
+use envconfig::Envconfig;
+
+use crate::config::Config;
+
+/// Whether `ip` is one of the comma separated addresses or CIDR ranges in
+/// `trusted`.
+fn is_trusted(ip: IpAddr, trusted: &str) -> bool {
+  trusted
+    .split(',')
+    .map(str::trim)
+    .filter(|entry| !entry.is_empty())
+    .any(|entry| in_range(ip, entry))
+}
+
+fn in_range(ip: IpAddr, range: &str) -> bool {
+  let (network, prefix) = match range.split_once('/') {
+    Some((network, prefix)) => match prefix.parse::<u32>() {
+      Ok(prefix) => (network, Some(prefix)),
+      Err(_) => return false,
+    },
+    None => (range, None),
+  };
+  let network = match network.parse::<IpAddr>() {
+    Ok(network) => network.to_canonical(),
+    Err(_) => return false,
+  };
+
+  match (ip, network) {
+    (IpAddr::V4(ip), IpAddr::V4(network)) => {
+      let prefix = prefix.unwrap_or(32).min(32);
+      let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+      u32::from(ip) & mask == u32::from(network) & mask
+    }
+    (IpAddr::V6(ip), IpAddr::V6(network)) => {
+      let prefix = prefix.unwrap_or(128).min(128);
+      let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+      u128::from(ip) & mask == u128::from(network) & mask
+    }
+    _ => false,
+  }
+}
+
+/// Client address for a connection from `peer`. `X-Forwarded-For` is only
+/// read when the peer is a trusted proxy; each proxy appends the address
+/// it saw, so hops are walked back from the nearest one until an address
+/// that isn't a trusted proxy is found.
+fn forwarded_client(
+  peer: IpAddr,
+  forwarded: &[&str],
+  trusted: &str,
+) -> IpAddr {
+  let mut client = peer.to_canonical();
+
+  for hop in forwarded.iter().rev() {
+    if !is_trusted(client, trusted) {
+      break;
+    }
+
+    match hop.trim().parse::<IpAddr>() {
+      Ok(ip) => client = ip.to_canonical(),
+      Err(_) => break,
+    }
+  }
+
+  client
+}
+
+/// Address of the client that made a request, for rate limits and
+/// lockouts. Unlike `ConnectionInfo::realip_remote_addr`, forwarding
+/// headers are ignored unless the connection comes from one of
+/// `TRUSTED_PROXIES`, so callers can't pick their own address.
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+  let config = Config::init_from_env().unwrap();
+  let peer = req.peer_addr()?.ip();
+
+  let forwarded: Vec<&str> = req
+    .headers()
+    .get_all("x-forwarded-for")
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .collect();
+
+  Some(
+    forwarded_client(peer, &forwarded, &config.trusted_proxies)
+      .to_string(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ip(value: &str) -> IpAddr {
+    value.parse().unwrap()
+  }
+
+  #[test]
+  fn matches_addresses_and_ranges() {
+    assert!(is_trusted(ip("10.0.0.1"), "10.0.0.1"));
+    assert!(is_trusted(ip("10.1.2.3"), "127.0.0.1, 10.0.0.0/8"));
+    assert!(!is_trusted(ip("11.0.0.1"), "10.0.0.0/8"));
+    assert!(is_trusted(ip("fd00::1"), "fd00::/8"));
+    assert!(!is_trusted(ip("fe80::1"), "fd00::/8"));
+    assert!(is_trusted(ip("203.0.113.9"), "0.0.0.0/0"));
+    assert!(!is_trusted(ip("10.0.0.1"), ""));
+    assert!(!is_trusted(ip("10.0.0.1"), "10.0.0.0/x"));
+  }
+
+  #[test]
+  fn ignores_forwarded_for_from_untrusted_peers() {
+    let client = forwarded_client(ip("203.0.113.9"), &["1.2.3.4"], "");
+    assert_eq!(client, ip("203.0.113.9"));
+
+    let client =
+      forwarded_client(ip("203.0.113.9"), &["1.2.3.4"], "10.0.0.0/8");
+    assert_eq!(client, ip("203.0.113.9"));
+  }
+
+  #[test]
+  fn walks_back_through_trusted_proxies() {
+    let trusted = "10.0.0.0/8";
+
+    let client =
+      forwarded_client(ip("10.0.0.2"), &["198.51.100.7"], trusted);
+    assert_eq!(client, ip("198.51.100.7"));
+
+    // A spoofed leading entry is never reached past the real client.
+    let client = forwarded_client(
+      ip("10.0.0.2"),
+      &["1.2.3.4", " 198.51.100.7", " 10.0.0.5"],
+      trusted,
+    );
+    assert_eq!(client, ip("198.51.100.7"));
+
+    let client = forwarded_client(ip("10.0.0.2"), &["garbage"], trusted);
+    assert_eq!(client, ip("10.0.0.2"));
+
+    let client = forwarded_client(
+      ip("::ffff:10.0.0.2"),
+      &["::ffff:198.51.100.7"],
+      trusted,
+    );
+    assert_eq!(client, ip("198.51.100.7"));
+  }
+}
@@ -0,0 +1,584 @@
+use chrono::NaiveDateTime;
+use exif::{Exif, In, Reader, Tag, Value};
+use std::io::Cursor;
+
+use crate::structs::photography::{GpsPosition, PhotoExif};
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+  match &exif.get_field(tag, In::PRIMARY)?.value {
+    Value::Ascii(values) => {
+      let value = String::from_utf8_lossy(values.first()?)
+        .trim_matches(char::from(0))
+        .trim()
+        .to_string();
+      (!value.is_empty()).then_some(value)
+    }
+    _ => None,
+  }
+}
+
+fn rationals(exif: &Exif, tag: Tag) -> Option<Vec<f64>> {
+  match &exif.get_field(tag, In::PRIMARY)?.value {
+    Value::Rational(values) => {
+      Some(values.iter().map(|value| value.to_f64()).collect())
+    }
+    _ => None,
+  }
+}
+
+fn rational(exif: &Exif, tag: Tag) -> Option<f64> {
+  rationals(exif, tag)?
+    .first()
+    .copied()
+    .filter(|value| value.is_finite() && *value > 0.0)
+}
+
+/// Degrees, minutes and seconds plus an N/S or E/W reference, as signed
+/// decimal degrees.
+fn coordinate(exif: &Exif, tag: Tag, reference: Tag) -> Option<f64> {
+  let parts = rationals(exif, tag)?;
+  if parts.len() < 3 || parts.iter().any(|part| !part.is_finite()) {
+    return None;
+  }
+
+  let degrees = parts[0] + parts[1] / 60.0 + parts[2] / 3600.0;
+  match ascii(exif, reference).as_deref() {
+    Some("S") | Some("W") => Some(-degrees),
+    _ => Some(degrees),
+  }
+}
+
+fn gps(exif: &Exif) -> Option<GpsPosition> {
+  let latitude = coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef)?;
+  let longitude =
+    coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef)?;
+
+  // An altitude reference of 1 means below sea level.
+  let below_sea_level = exif
+    .get_field(Tag::GPSAltitudeRef, In::PRIMARY)
+    .and_then(|field| field.value.get_uint(0))
+    == Some(1);
+  let altitude = rationals(exif, Tag::GPSAltitude)
+    .and_then(|values| values.first().copied())
+    .filter(|altitude| altitude.is_finite())
+    .map(|altitude| if below_sea_level { -altitude } else { altitude });
+
+  Some(GpsPosition {
+    latitude,
+    longitude,
+    altitude,
+  })
+}
+
+fn shutter(seconds: f64) -> String {
+  if seconds >= 1.0 {
+    format!("{}s", (seconds * 10.0).round() / 10.0)
+  } else {
+    format!("1/{}", (1.0 / seconds).round())
+  }
+}
+
+/// Read camera settings out of an image's EXIF block. Returns `None` when
+/// the file has no EXIF data at all.
+pub fn extract_exif(data: &[u8]) -> Option<PhotoExif> {
+  let exif = Reader::new()
+    .read_from_container(&mut Cursor::new(data))
+    .ok()?;
+
+  // Most bodies repeat the make in the model ("Canon" / "Canon EOS R5").
+  let camera = match (ascii(&exif, Tag::Make), ascii(&exif, Tag::Model)) {
+    (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+    (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+    (make, model) => model.or(make),
+  };
+
+  let taken_at = ascii(&exif, Tag::DateTimeOriginal)
+    .or_else(|| ascii(&exif, Tag::DateTime))
+    .and_then(|taken_at| {
+      NaiveDateTime::parse_from_str(&taken_at, "%Y:%m:%d %H:%M:%S").ok()
+    });
+
+  let iso = exif
+    .get_field(Tag::PhotographicSensitivity, In::PRIMARY)
+    .and_then(|field| field.value.get_uint(0));
+
+  Some(PhotoExif {
+    camera,
+    lens: ascii(&exif, Tag::LensModel),
+    focal_length: rational(&exif, Tag::FocalLength),
+    aperture: rational(&exif, Tag::FNumber),
+    shutter: rational(&exif, Tag::ExposureTime).map(shutter),
+    iso,
+    taken_at,
+    gps: gps(&exif),
+  })
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const GPS_IFD_POINTER: u16 = 0x8825;
+const XMP_GPS_PREFIX: &[u8] = b"exif:GPS";
+
+fn read_u16(
+  tiff: &[u8],
+  offset: usize,
+  little_endian: bool,
+) -> Option<u16> {
+  let bytes = tiff.get(offset..offset + 2)?.try_into().ok()?;
+  Some(if little_endian {
+    u16::from_le_bytes(bytes)
+  } else {
+    u16::from_be_bytes(bytes)
+  })
+}
+
+fn read_u32(
+  tiff: &[u8],
+  offset: usize,
+  little_endian: bool,
+) -> Option<u32> {
+  let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+  Some(if little_endian {
+    u32::from_le_bytes(bytes)
+  } else {
+    u32::from_be_bytes(bytes)
+  })
+}
+
+/// Bytes per value of a TIFF field type, or 0 for types this doesn't know.
+fn type_size(field_type: u16) -> usize {
+  match field_type {
+    1 | 2 | 6 | 7 => 1,
+    3 | 8 => 2,
+    4 | 9 | 11 => 4,
+    5 | 10 | 12 => 8,
+    _ => 0,
+  }
+}
+
+/// Zero every entry of the GPS IFD in a TIFF block, along with the values
+/// stored outside it, and mark the IFD empty. Everything is overwritten in
+/// place so no other offset in the file moves. Returns whether there was
+/// anything to remove.
+fn blank_gps_ifd(tiff: &mut [u8]) -> bool {
+  let little_endian = match tiff.get(..2) {
+    Some(b"II") => true,
+    Some(b"MM") => false,
+    _ => return false,
+  };
+
+  let Some(ifd0) = read_u32(tiff, 4, little_endian) else {
+    return false;
+  };
+  let ifd0 = ifd0 as usize;
+  let Some(entries) = read_u16(tiff, ifd0, little_endian) else {
+    return false;
+  };
+
+  let gps = (0..entries as usize)
+    .map(|index| ifd0 + 2 + index * 12)
+    .find(|&entry| {
+      read_u16(tiff, entry, little_endian) == Some(GPS_IFD_POINTER)
+    })
+    .and_then(|entry| read_u32(tiff, entry + 8, little_endian));
+  let Some(gps) = gps.map(|gps| gps as usize) else {
+    return false;
+  };
+  let Some(entries) = read_u16(tiff, gps, little_endian) else {
+    return false;
+  };
+
+  for index in 0..entries as usize {
+    let entry = gps + 2 + index * 12;
+    if entry + 12 > tiff.len() {
+      break;
+    }
+
+    let field_type = read_u16(tiff, entry + 2, little_endian).unwrap_or(0);
+    let count = read_u32(tiff, entry + 4, little_endian).unwrap_or(0);
+    let size = type_size(field_type).saturating_mul(count as usize);
+    if size > 4 {
+      let offset =
+        read_u32(tiff, entry + 8, little_endian).unwrap_or(0) as usize;
+      if let Some(value) = offset
+        .checked_add(size)
+        .and_then(|end| tiff.get_mut(offset..end))
+      {
+        value.fill(0);
+      }
+    }
+
+    tiff[entry..entry + 12].fill(0);
+  }
+
+  // An empty IFD's next-IFD offset sits where the first entry was, which
+  // is now zero: no further IFDs.
+  tiff[gps..gps + 2].fill(0);
+  entries > 0
+}
+
+/// Blank `exif:GPS*` properties out of XMP packets, written either as
+/// attributes or as elements, with spaces so the XML stays well formed
+/// and the file keeps its length. Returns whether anything was removed.
+fn blank_xmp_gps(data: &mut [u8]) -> bool {
+  let is_name = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_';
+  let mut removed = false;
+  let mut from = 0;
+
+  while let Some(found) = data[from..]
+    .windows(XMP_GPS_PREFIX.len())
+    .position(|window| window == XMP_GPS_PREFIX)
+  {
+    let start = from + found;
+    let name_end = start
+      + 5
+      + data[start + 5..]
+        .iter()
+        .position(|&byte| !is_name(byte))
+        .unwrap_or(data.len() - start - 5);
+    let name = &data[start..name_end];
+    from = name_end;
+
+    let end = if start > 0 && data[start - 1] == b'<' {
+      let mut closing = b"</".to_vec();
+      closing.extend_from_slice(name);
+      closing.push(b'>');
+      data[name_end..]
+        .windows(closing.len())
+        .position(|window| window == closing.as_slice())
+        .map(|position| (start - 1, name_end + position + closing.len()))
+    } else if data.get(name_end) == Some(&b'=') {
+      data
+        .get(name_end + 1)
+        .filter(|&&quote| quote == b'"' || quote == b'\'')
+        .and_then(|&quote| {
+          data[name_end + 2..]
+            .iter()
+            .position(|&byte| byte == quote)
+            .map(|position| (start, name_end + 2 + position + 1))
+        })
+    } else {
+      None
+    };
+
+    if let Some((start, end)) = end {
+      data[start..end].fill(b' ');
+      from = end;
+      removed = true;
+    }
+  }
+
+  removed
+}
+
+/// Recompute the CRC of every chunk of a PNG after its data was edited.
+fn fix_png_crcs(data: &mut [u8]) {
+  let mut offset = PNG_SIGNATURE.len();
+  while let Some(length) = read_u32(data, offset, false) {
+    let end = offset + 8 + length as usize;
+    if end + 4 > data.len() {
+      break;
+    }
+
+    let crc = crc32fast::hash(&data[offset + 4..end]);
+    data[end..end + 4].copy_from_slice(&crc.to_be_bytes());
+    offset = end + 4;
+  }
+}
+
+/// Copy of an image with its location removed: the GPS block of its EXIF
+/// data and any GPS properties in its XMP. Originals are served as they
+/// were uploaded, so this is what keeps exact coordinates off the CDN.
+/// Returns `None` when the image has no location to remove.
+pub fn strip_gps(data: &[u8]) -> Option<Vec<u8>> {
+  let mut stripped = data.to_vec();
+  let mut removed = false;
+
+  if let Ok(exif) =
+    Reader::new().read_from_container(&mut Cursor::new(data))
+  {
+    let tiff = exif.buf();
+    if let Some(start) =
+      data.windows(tiff.len()).position(|window| window == tiff)
+    {
+      removed |= blank_gps_ifd(&mut stripped[start..start + tiff.len()]);
+    }
+  }
+  removed |= blank_xmp_gps(&mut stripped);
+
+  if !removed {
+    return None;
+  }
+  if stripped.starts_with(PNG_SIGNATURE) {
+    fix_png_crcs(&mut stripped);
+  }
+  Some(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use image::{DynamicImage, ImageFormat};
+
+  type Entry = (u16, u16, u32, Vec<u8>);
+
+  fn ascii_entry(tag: u16, value: &str) -> Entry {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.push(0);
+    (tag, 2, bytes.len() as u32, bytes)
+  }
+
+  fn rational_entry(tag: u16, values: &[(u32, u32)]) -> Entry {
+    let bytes = values
+      .iter()
+      .flat_map(|(numerator, denominator)| {
+        [numerator.to_le_bytes(), denominator.to_le_bytes()].concat()
+      })
+      .collect();
+    (tag, 5, values.len() as u32, bytes)
+  }
+
+  fn long_entry(tag: u16, value: u32) -> Entry {
+    (tag, 4, 1, value.to_le_bytes().to_vec())
+  }
+
+  fn ifd_len(entries: &[Entry]) -> usize {
+    2 + entries.len() * 12
+      + 4
+      + entries
+        .iter()
+        .filter(|entry| entry.3.len() > 4)
+        .map(|entry| entry.3.len())
+        .sum::<usize>()
+  }
+
+  /// A little-endian IFD starting at `start` in the TIFF block, with its
+  /// out-of-line values right after it.
+  fn ifd(start: usize, entries: &[Entry]) -> Vec<u8> {
+    let mut data = start + 2 + entries.len() * 12 + 4;
+    let mut table = (entries.len() as u16).to_le_bytes().to_vec();
+    let mut values = Vec::new();
+
+    for (tag, field_type, count, bytes) in entries {
+      table.extend_from_slice(&tag.to_le_bytes());
+      table.extend_from_slice(&field_type.to_le_bytes());
+      table.extend_from_slice(&count.to_le_bytes());
+      if bytes.len() > 4 {
+        table.extend_from_slice(&(data as u32).to_le_bytes());
+        values.extend_from_slice(bytes);
+        data += bytes.len();
+      } else {
+        let mut inline = bytes.clone();
+        inline.resize(4, 0);
+        table.extend_from_slice(&inline);
+      }
+    }
+
+    table.extend_from_slice(&0u32.to_le_bytes());
+    table.extend_from_slice(&values);
+    table
+  }
+
+  /// The TIFF block of a photo taken with a Canon EOS R5 at 51°30'N,
+  /// 0°7'30"W, 35m up.
+  fn tiff() -> Vec<u8> {
+    tiff_with("Canon EOS R5", 0)
+  }
+
+  /// The same photo with a different model and altitude reference.
+  fn tiff_with(model: &str, altitude_ref: u8) -> Vec<u8> {
+    let exif = vec![
+      rational_entry(0x829A, &[(1, 250)]),
+      rational_entry(0x829D, &[(28, 10)]),
+      (0x8827, 3, 1, 400u16.to_le_bytes().to_vec()),
+      ascii_entry(0x9003, "2024:05:04 13:37:00"),
+      rational_entry(0x920A, &[(50, 1)]),
+      ascii_entry(0xA434, "RF50mm F1.2 L USM"),
+    ];
+    let gps = vec![
+      ascii_entry(0x0001, "N"),
+      rational_entry(0x0002, &[(51, 1), (30, 1), (0, 1)]),
+      ascii_entry(0x0003, "W"),
+      rational_entry(0x0004, &[(0, 1), (7, 1), (30, 1)]),
+      (0x0005, 1, 1, vec![altitude_ref]),
+      rational_entry(0x0006, &[(35, 1)]),
+    ];
+    let primary = |exif_at: u32, gps_at: u32| {
+      vec![
+        ascii_entry(0x010F, "Canon"),
+        ascii_entry(0x0110, model),
+        long_entry(0x8769, exif_at),
+        long_entry(GPS_IFD_POINTER, gps_at),
+      ]
+    };
+
+    let exif_at = 8 + ifd_len(&primary(0, 0));
+    let gps_at = exif_at + ifd_len(&exif);
+
+    let mut tiff = b"II*\0".to_vec();
+    tiff.extend_from_slice(&8u32.to_le_bytes());
+    tiff.extend(ifd(8, &primary(exif_at as u32, gps_at as u32)));
+    tiff.extend(ifd(exif_at, &exif));
+    tiff.extend(ifd(gps_at, &gps));
+    tiff
+  }
+
+  fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+    let mut segment = vec![0xFF, marker];
+    segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    segment.extend_from_slice(payload);
+    segment
+  }
+
+  fn image(format: ImageFormat) -> Vec<u8> {
+    let mut data = Cursor::new(Vec::new());
+    DynamicImage::new_rgb8(8, 8)
+      .write_to(&mut data, format)
+      .unwrap();
+    data.into_inner()
+  }
+
+  /// A JPEG with `extra` APP segments placed right after its SOI marker.
+  fn jpeg(extra: &[Vec<u8>]) -> Vec<u8> {
+    let encoded = image(ImageFormat::Jpeg);
+    let mut jpeg = encoded[..2].to_vec();
+    extra
+      .iter()
+      .for_each(|segment| jpeg.extend_from_slice(segment));
+    jpeg.extend_from_slice(&encoded[2..]);
+    jpeg
+  }
+
+  fn exif_segment() -> Vec<u8> {
+    exif_segment_for(&tiff())
+  }
+
+  fn exif_segment_for(tiff: &[u8]) -> Vec<u8> {
+    segment(0xE1, &[b"Exif\0\0".as_slice(), tiff].concat())
+  }
+
+  #[test]
+  fn extracts_camera_settings_from_jpegs() {
+    let exif = extract_exif(&jpeg(&[exif_segment()])).unwrap();
+
+    assert_eq!(exif.camera.as_deref(), Some("Canon EOS R5"));
+    assert_eq!(exif.lens.as_deref(), Some("RF50mm F1.2 L USM"));
+    assert_eq!(exif.focal_length, Some(50.0));
+    assert_eq!(exif.aperture, Some(2.8));
+    assert_eq!(exif.shutter.as_deref(), Some("1/250"));
+    assert_eq!(exif.iso, Some(400));
+    assert_eq!(
+      exif.taken_at,
+      NaiveDateTime::parse_from_str("2024-05-04 13:37:00", "%F %T").ok()
+    );
+  }
+
+  #[test]
+  fn joins_make_and_model_unless_repeated() {
+    let exif =
+      extract_exif(&jpeg(&[exif_segment_for(&tiff_with("EOS R5", 0))]))
+        .unwrap();
+    assert_eq!(exif.camera.as_deref(), Some("Canon EOS R5"));
+  }
+
+  #[test]
+  fn signs_gps_coordinates_by_reference() {
+    let gps = extract_exif(&jpeg(&[exif_segment()])).unwrap().gps.unwrap();
+    assert_eq!(gps.latitude, 51.5);
+    assert_eq!(gps.longitude, -0.125);
+    assert_eq!(gps.altitude, Some(35.0));
+
+    let below =
+      extract_exif(&jpeg(&[exif_segment_for(&tiff_with("EOS R5", 1))]))
+        .unwrap();
+    assert_eq!(below.gps.unwrap().altitude, Some(-35.0));
+  }
+
+  #[test]
+  fn formats_shutter_speeds() {
+    assert_eq!(shutter(1.0 / 4000.0), "1/4000");
+    assert_eq!(shutter(0.3), "1/3");
+    assert_eq!(shutter(2.5), "2.5s");
+    assert_eq!(shutter(30.0), "30s");
+  }
+
+  #[test]
+  fn has_no_exif_without_an_exif_block() {
+    assert!(extract_exif(&image(ImageFormat::Jpeg)).is_none());
+    assert!(extract_exif(b"not an image").is_none());
+  }
+
+  const XMP: &str = concat!(
+    "http://ns.adobe.com/xap/1.0/\0",
+    "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF><rdf:Description ",
+    "exif:GPSLatitude=\"51,30.0N\" xmp:Rating=\"5\">",
+    "<exif:GPSLongitude>0,7.5W</exif:GPSLongitude>",
+    "</rdf:Description></rdf:RDF></x:xmpmeta>"
+  );
+
+  #[test]
+  fn strips_exif_gps_from_jpegs() {
+    let original = jpeg(&[exif_segment()]);
+    assert!(extract_exif(&original).unwrap().gps.is_some());
+
+    let stripped = strip_gps(&original).unwrap();
+    assert_eq!(stripped.len(), original.len());
+
+    let exif = extract_exif(&stripped).unwrap();
+    assert!(exif.gps.is_none());
+    assert_eq!(exif.camera.as_deref(), Some("Canon EOS R5"));
+    assert_eq!(exif.iso, Some(400));
+    image::load_from_memory(&stripped).unwrap();
+
+    let tiff = Reader::new()
+      .read_from_container(&mut Cursor::new(&stripped))
+      .unwrap();
+    assert!(tiff
+      .fields()
+      .all(|field| field.tag.context() != exif::Context::Gps));
+  }
+
+  #[test]
+  fn strips_xmp_gps() {
+    let xmp = segment(0xE1, XMP.as_bytes());
+    let stripped = strip_gps(&jpeg(&[xmp])).unwrap();
+
+    let text = String::from_utf8_lossy(&stripped);
+    assert!(!text.contains("GPS"));
+    assert!(!text.contains("51,30.0N"));
+    assert!(!text.contains("0,7.5W"));
+    assert!(text.contains("xmp:Rating=\"5\""));
+    assert!(text.contains("<rdf:Description"));
+    image::load_from_memory(&stripped).unwrap();
+  }
+
+  #[test]
+  fn strips_exif_gps_from_pngs() {
+    let encoded = image(ImageFormat::Png);
+    let tiff = tiff();
+    let mut chunk = (tiff.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(b"eXIf");
+    chunk.extend_from_slice(&tiff);
+    chunk.extend_from_slice(&crc32fast::hash(&chunk[4..]).to_be_bytes());
+
+    // eXIf goes after IHDR, which is 25 bytes long.
+    let at = PNG_SIGNATURE.len() + 25;
+    let original = [&encoded[..at], &chunk, &encoded[at..]].concat();
+    assert!(extract_exif(&original).unwrap().gps.is_some());
+
+    let stripped = strip_gps(&original).unwrap();
+    assert!(extract_exif(&stripped).unwrap().gps.is_none());
+
+    let exif = at + 8 + tiff.len();
+    assert_eq!(
+      stripped[exif..exif + 4],
+      crc32fast::hash(&stripped[at + 4..exif]).to_be_bytes()
+    );
+    image::load_from_memory(&stripped).unwrap();
+  }
+
+  #[test]
+  fn leaves_images_without_a_location_alone() {
+    assert!(strip_gps(&image(ImageFormat::Jpeg)).is_none());
+    assert!(strip_gps(b"not an image").is_none());
+  }
+}
@@ -0,0 +1,12 @@
+use envconfig::Envconfig;
+
+use crate::config::Config;
+
+/// Round a latitude or longitude to `PHOTOGRAPHY_COORDINATE_PRECISION`
+/// decimal places before it is shown publicly.
+pub fn round_coordinate(value: f64) -> f64 {
+  let config = Config::init_from_env().unwrap();
+  let scale = 10f64.powi(config.photography_coordinate_precision.min(8));
+
+  (value * scale).round() / scale
+}
@@ -0,0 +1,206 @@
+use actix_web::{http::header::HeaderMap, HttpRequest};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use rsa::{
+  pkcs1::DecodeRsaPublicKey,
+  pkcs1v15::{Signature, SigningKey, VerifyingKey},
+  pkcs8::DecodePublicKey,
+  signature::{SignatureEncoding, Signer, Verifier},
+  RsaPrivateKey, RsaPublicKey,
+};
+use sha2::{Digest, Sha256};
+
+/// How far a signed request's `Date` may drift from our clock.
+const MAX_CLOCK_SKEW: i64 = 12 * 60 * 60;
+
+/// The parts of a `Signature` header (draft-cavage-http-signatures) needed
+/// to verify it.
+pub struct SignatureHeader {
+  pub key_id: String,
+  pub headers: Vec<String>,
+  pub signature: Vec<u8>,
+}
+
+impl SignatureHeader {
+  pub fn parse(value: &str) -> Option<Self> {
+    let mut key_id = None;
+    let mut headers = None;
+    let mut signature = None;
+
+    for param in value.split(',') {
+      let (name, value) = param.trim().split_once('=')?;
+      let value = value.trim_matches('"');
+
+      match name {
+        "keyId" => key_id = Some(value.to_string()),
+        "headers" => {
+          headers = Some(
+            value
+              .split_whitespace()
+              .map(|header| header.to_lowercase())
+              .collect(),
+          )
+        }
+        "signature" => signature = STANDARD.decode(value).ok(),
+        _ => {}
+      }
+    }
+
+    Some(Self {
+      key_id: key_id?,
+      // The spec falls back to just the date when no list is given.
+      headers: headers.unwrap_or_else(|| vec!["date".to_string()]),
+      signature: signature?,
+    })
+  }
+}
+
+pub fn digest_header(body: &[u8]) -> String {
+  format!("SHA-256={}", STANDARD.encode(Sha256::digest(body)))
+}
+
+fn header_value<'a>(
+  headers: &'a HeaderMap,
+  name: &str,
+) -> Option<&'a str> {
+  headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+pub fn parse_public_key(pem: &str) -> Option<RsaPublicKey> {
+  RsaPublicKey::from_public_key_pem(pem)
+    .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+    .ok()
+}
+
+/// Check the signature on an incoming request against the sender's public
+/// key. The request target, host and date must be covered, and so must the
+/// digest when there is a body, which is checked against the body as
+/// received.
+pub fn verify_request(
+  req: &HttpRequest,
+  body: &[u8],
+  signature: &SignatureHeader,
+  public_key: RsaPublicKey,
+) -> bool {
+  let headers = req.headers();
+
+  for required in ["(request-target)", "host", "date"] {
+    if !signature.headers.iter().any(|header| header == required) {
+      return false;
+    }
+  }
+
+  let date = header_value(headers, "date")
+    .and_then(|date| DateTime::parse_from_rfc2822(date).ok());
+  match date {
+    Some(date)
+      if (Utc::now().timestamp() - date.timestamp()).abs()
+        <= MAX_CLOCK_SKEW => {}
+    _ => return false,
+  }
+
+  if !body.is_empty() {
+    if !signature.headers.iter().any(|header| header == "digest") {
+      return false;
+    }
+    if header_value(headers, "digest") != Some(&digest_header(body)) {
+      return false;
+    }
+  }
+
+  let mut lines = Vec::new();
+  for name in &signature.headers {
+    let value = match name.as_str() {
+      "(request-target)" => format!(
+        "{} {}",
+        req.method().as_str().to_lowercase(),
+        req
+          .uri()
+          .path_and_query()
+          .map(|path| path.as_str())
+          .unwrap_or(req.path())
+      ),
+      _ => match header_value(headers, name) {
+        Some(value) => value.to_string(),
+        None => return false,
+      },
+    };
+    lines.push(format!("{}: {}", name, value));
+  }
+
+  let signature = match Signature::try_from(signature.signature.as_slice())
+  {
+    Ok(signature) => signature,
+    Err(_) => return false,
+  };
+
+  VerifyingKey::<Sha256>::new(public_key)
+    .verify(lines.join("\n").as_bytes(), &signature)
+    .is_ok()
+}
+
+/// Headers for an outgoing signed request, covering the request target,
+/// host and date, plus the digest of the body when there is one.
+pub fn sign_request(
+  private_key: RsaPrivateKey,
+  key_id: &str,
+  method: &str,
+  url: &Url,
+  body: Option<&[u8]>,
+) -> Vec<(&'static str, String)> {
+  let host = match url.port() {
+    Some(port) => {
+      format!("{}:{}", url.host_str().unwrap_or_default(), port)
+    }
+    None => url.host_str().unwrap_or_default().to_string(),
+  };
+  let target = match url.query() {
+    Some(query) => format!("{}?{}", url.path(), query),
+    None => url.path().to_string(),
+  };
+
+  let mut headers = vec![
+    ("host", host),
+    (
+      "date",
+      Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+    ),
+  ];
+  if let Some(body) = body {
+    headers.push(("digest", digest_header(body)));
+  }
+
+  let mut lines = vec![format!(
+    "(request-target): {} {}",
+    method.to_lowercase(),
+    target
+  )];
+  lines.extend(
+    headers
+      .iter()
+      .map(|(name, value)| format!("{}: {}", name, value)),
+  );
+
+  let signature = SigningKey::<Sha256>::new(private_key)
+    .sign(lines.join("\n").as_bytes())
+    .to_bytes();
+
+  let names = headers
+    .iter()
+    .map(|(name, _)| *name)
+    .collect::<Vec<&str>>()
+    .join(" ");
+
+  headers.push((
+    "signature",
+    format!(
+      "keyId=\"{}\",algorithm=\"rsa-sha256\",headers=\"(request-target) {}\",signature=\"{}\"",
+      key_id,
+      names,
+      STANDARD.encode(signature)
+    ),
+  ));
+
+  headers
+}
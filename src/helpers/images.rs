@@ -0,0 +1,187 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{
+  codecs::avif::AvifEncoder, imageops::FilterType, metadata::Orientation,
+  DynamicImage, ImageDecoder, ImageReader,
+};
+use std::io::Cursor;
+
+/// Widths responsive variants are generated at. Widths at or above the
+/// original are skipped rather than upscaled.
+pub const VARIANT_WIDTHS: [u32; 4] = [320, 640, 1280, 1920];
+
+/// Formats every variant is encoded in, as `(extension, mime)`.
+pub const VARIANT_FORMATS: [(&str, &str); 2] =
+  [("avif", "image/avif"), ("webp", "image/webp")];
+
+/// Named sizes photography variants are generated at, as a bound on the
+/// longer edge. Photos already within a bound are kept at their size.
+pub const PHOTO_SIZES: [(&str, u32); 3] =
+  [("thumbnail", 400), ("medium", 1200), ("large", 2400)];
+
+/// Longest edge an image is shrunk to before BlurHash encoding. The hash
+/// only keeps the lowest frequencies, so more pixels just cost time.
+const BLURHASH_SOURCE_SIZE: u32 = 64;
+/// Longest edge of the inline low-quality preview.
+const LQIP_SIZE: u32 = 16;
+const LQIP_QUALITY: f32 = 50.0;
+
+const WEBP_QUALITY: f32 = 80.0;
+const AVIF_QUALITY: u8 = 60;
+/// 1 (slowest, smallest) to 10 (fastest). Uploads are encoded inline, so
+/// lean towards speed.
+const AVIF_SPEED: u8 = 8;
+
+pub struct ImageVariant {
+  pub width: u32,
+  pub height: u32,
+  pub ext: &'static str,
+  pub mime: &'static str,
+  pub data: Vec<u8>,
+}
+
+/// Placeholders shown while a photo loads: a BlurHash string and a tiny
+/// WebP as a `data:` URI.
+pub struct Placeholders {
+  pub blurhash: String,
+  pub lqip: String,
+}
+
+/// Decode an uploaded image, sniffing the format from its contents.
+pub fn decode_image(data: &[u8]) -> Option<DynamicImage> {
+  ImageReader::new(Cursor::new(data))
+    .with_guessed_format()
+    .ok()?
+    .decode()
+    .ok()
+}
+
+/// Decode a photo and rotate it upright according to its EXIF
+/// orientation, so dimensions and variants match how it is displayed.
+pub fn decode_oriented_image(data: &[u8]) -> Option<DynamicImage> {
+  let mut decoder = ImageReader::new(Cursor::new(data))
+    .with_guessed_format()
+    .ok()?
+    .into_decoder()
+    .ok()?;
+  let orientation =
+    decoder.orientation().unwrap_or(Orientation::NoTransforms);
+
+  let mut image = DynamicImage::from_decoder(decoder).ok()?;
+  image.apply_orientation(orientation);
+  Some(image)
+}
+
+pub fn encode_webp(image: &DynamicImage) -> Vec<u8> {
+  let rgba = image.to_rgba8();
+  webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+    .encode(WEBP_QUALITY)
+    .to_vec()
+}
+
+pub fn encode_avif(image: &DynamicImage) -> Option<Vec<u8>> {
+  let mut data = Vec::new();
+  let encoder = AvifEncoder::new_with_speed_quality(
+    &mut data,
+    AVIF_SPEED,
+    AVIF_QUALITY,
+  );
+  image.to_rgba8().write_with_encoder(encoder).ok()?;
+  Some(data)
+}
+
+/// Resize to each of `VARIANT_WIDTHS` narrower than the original and
+/// encode every size in each of `VARIANT_FORMATS`. A size that fails to
+/// encode in any format is left out entirely, since srcsets offer every
+/// format of a width. CPU heavy, so callers should run this off the async
+/// runtime.
+pub fn generate_variants(image: &DynamicImage) -> Vec<ImageVariant> {
+  let mut variants = Vec::new();
+
+  for width in VARIANT_WIDTHS {
+    if width >= image.width() {
+      continue;
+    }
+
+    let resized = image.resize(width, u32::MAX, FilterType::Lanczos3);
+
+    let encoded: Option<Vec<ImageVariant>> = VARIANT_FORMATS
+      .into_iter()
+      .map(|(ext, mime)| {
+        let data = match ext {
+          "webp" => Some(encode_webp(&resized)),
+          _ => encode_avif(&resized),
+        }?;
+
+        Some(ImageVariant {
+          width: resized.width(),
+          height: resized.height(),
+          ext,
+          mime,
+          data,
+        })
+      })
+      .collect();
+
+    variants.extend(encoded.unwrap_or_default());
+  }
+
+  variants
+}
+
+/// Encode a WebP at each of `PHOTO_SIZES`. CPU heavy, so callers should
+/// run this off the async runtime.
+pub fn generate_photo_variants(
+  image: &DynamicImage,
+) -> Vec<(&'static str, ImageVariant)> {
+  PHOTO_SIZES
+    .iter()
+    .map(|(size, bound)| {
+      let resized;
+      let source = if image.width().max(image.height()) > *bound {
+        resized = image.resize(*bound, *bound, FilterType::Lanczos3);
+        &resized
+      } else {
+        image
+      };
+
+      let variant = ImageVariant {
+        width: source.width(),
+        height: source.height(),
+        ext: "webp",
+        mime: "image/webp",
+        data: encode_webp(source),
+      };
+      (*size, variant)
+    })
+    .collect()
+}
+
+pub fn generate_placeholders(
+  image: &DynamicImage,
+) -> Option<Placeholders> {
+  let small = image
+    .thumbnail(BLURHASH_SOURCE_SIZE, BLURHASH_SOURCE_SIZE)
+    .to_rgba8();
+  let (components_x, components_y) = if small.width() >= small.height() {
+    (4, 3)
+  } else {
+    (3, 4)
+  };
+  let blurhash = blurhash::encode(
+    components_x,
+    components_y,
+    small.width(),
+    small.height(),
+    small.as_raw(),
+  )
+  .ok()?;
+
+  let tiny = image.thumbnail(LQIP_SIZE, LQIP_SIZE).to_rgba8();
+  let lqip = webp::Encoder::from_rgba(&tiny, tiny.width(), tiny.height())
+    .encode(LQIP_QUALITY);
+
+  Some(Placeholders {
+    blurhash,
+    lqip: format!("data:image/webp;base64,{}", STANDARD.encode(&*lqip)),
+  })
+}
@@ -0,0 +1,211 @@
+use actix_web::{http::header, HttpResponse};
+use redis::aio::ConnectionManager;
+use serde_json::json;
+
+use crate::connectivity::{metrics::AUTH_FAILURES, valkey::ValkeyManager};
+
+/// Failures allowed inside the window before lockouts kick in.
+const FREE_ATTEMPTS: u64 = 5;
+/// How long failed attempts are remembered for.
+const ATTEMPT_WINDOW: u64 = 24 * 60 * 60;
+const BASE_LOCKOUT: u64 = 30;
+const MAX_LOCKOUT: u64 = 60 * 60;
+/// How long an address a user signed in from is remembered as theirs.
+const USUAL_IP_WINDOW: u64 = 30 * 24 * 60 * 60;
+
+/// Lockout after `attempts` failures inside the window, if any. Every
+/// failure past the free attempts doubles it, capped at an hour.
+fn lockout_for(attempts: u64) -> Option<u64> {
+  if attempts < FREE_ATTEMPTS {
+    return None;
+  }
+
+  let exponent = (attempts - FREE_ATTEMPTS).min(16) as u32;
+  Some((BASE_LOCKOUT * 2u64.pow(exponent)).min(MAX_LOCKOUT))
+}
+
+/// Valkey-backed attempt counter for authentication endpoints. Failures
+/// are counted against the caller's IP and, where there is one, the
+/// username being tried. A locked out IP is turned away outright; a locked
+/// out username only slows down guesses from addresses its owner hasn't
+/// signed in from, so nobody can lock an admin out of their own account.
+pub struct AuthLimiter {
+  scope: &'static str,
+  ip: Option<String>,
+  username: Option<String>,
+}
+
+impl AuthLimiter {
+  pub fn new(scope: &'static str) -> Self {
+    Self {
+      scope,
+      ip: None,
+      username: None,
+    }
+  }
+
+  pub fn ip(mut self, ip: Option<&str>) -> Self {
+    self.ip = ip.map(str::to_string);
+    self
+  }
+
+  pub fn username(mut self, username: &str) -> Self {
+    self.username = Some(username.trim().to_lowercase());
+    self
+  }
+
+  fn subjects(&self) -> Vec<String> {
+    let ip = self.ip.iter().map(|ip| format!("ip/{}", ip));
+    let username =
+      self.username.iter().map(|name| format!("user/{}", name));
+    ip.chain(username).collect()
+  }
+
+  fn usual_ip_key(&self) -> Option<String> {
+    match (&self.username, &self.ip) {
+      (Some(username), Some(ip)) => {
+        Some(format!("auth_usual_ip/{}/{}/{}", self.scope, username, ip))
+      }
+      _ => None,
+    }
+  }
+
+  async fn lockout_ttl(
+    &self,
+    valkey: &mut ValkeyManager,
+    subject: &str,
+  ) -> Option<u64> {
+    let ttl = redis::cmd("TTL")
+      .arg(format!("auth_lockout/{}/{}", self.scope, subject))
+      .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+      .await
+      .unwrap_or(-2);
+
+    (ttl > 0).then_some(ttl as u64)
+  }
+
+  /// Seconds until the caller can try again, if they are locked out.
+  pub async fn locked_for(
+    &self,
+    valkey: &mut ValkeyManager,
+  ) -> Option<u64> {
+    let mut locked_for = None;
+
+    if let Some(ip) = &self.ip {
+      locked_for = self.lockout_ttl(valkey, &format!("ip/{}", ip)).await;
+    }
+
+    if let Some(username) = &self.username {
+      let usual_ip = match self.usual_ip_key() {
+        Some(key) => redis::cmd("EXISTS")
+          .arg(key)
+          .query_async::<ConnectionManager, bool>(&mut valkey.cm)
+          .await
+          .unwrap_or(false),
+        None => false,
+      };
+
+      if !usual_ip {
+        let ttl = self
+          .lockout_ttl(valkey, &format!("user/{}", username))
+          .await;
+        locked_for = locked_for.max(ttl);
+      }
+    }
+
+    locked_for
+  }
+
+  pub async fn record_failure(&self, valkey: &mut ValkeyManager) {
+    AUTH_FAILURES.with_label_values(&[self.scope]).inc();
+
+    for subject in self.subjects() {
+      let attempts_key =
+        format!("auth_attempts/{}/{}", self.scope, subject);
+
+      let attempts = redis::cmd("INCR")
+        .arg(&attempts_key)
+        .query_async::<ConnectionManager, u64>(&mut valkey.cm)
+        .await
+        .unwrap_or(0);
+
+      let _ = redis::cmd("EXPIRE")
+        .arg(&attempts_key)
+        .arg(ATTEMPT_WINDOW)
+        .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+        .await;
+
+      if let Some(lockout) = lockout_for(attempts) {
+        let _ = redis::cmd("SET")
+          .arg(format!("auth_lockout/{}/{}", self.scope, subject))
+          .arg(attempts)
+          .arg("EX")
+          .arg(lockout)
+          .query_async::<ConnectionManager, String>(&mut valkey.cm)
+          .await;
+      }
+    }
+  }
+
+  /// Record a successful sign in. Only the username's failures are
+  /// forgotten, and the IP is remembered as one its owner uses. The IP
+  /// keeps its count, so signing in to one account doesn't buy more
+  /// guesses at others.
+  pub async fn record_success(&self, valkey: &mut ValkeyManager) {
+    let Some(username) = &self.username else {
+      return;
+    };
+
+    let _ = redis::cmd("DEL")
+      .arg(format!("auth_attempts/{}/user/{}", self.scope, username))
+      .arg(format!("auth_lockout/{}/user/{}", self.scope, username))
+      .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+      .await;
+
+    if let Some(key) = self.usual_ip_key() {
+      let _ = redis::cmd("SET")
+        .arg(key)
+        .arg(1)
+        .arg("EX")
+        .arg(USUAL_IP_WINDOW)
+        .query_async::<ConnectionManager, String>(&mut valkey.cm)
+        .await;
+    }
+  }
+}
+
+/// Response for a caller that is currently locked out.
+pub fn locked_out_response(retry_after: u64) -> HttpResponse {
+  HttpResponse::TooManyRequests()
+    .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+    .json(json!({"code": "too_many_attempts", "retry_after": retry_after}))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lockouts_double_past_the_free_attempts() {
+    assert_eq!(lockout_for(0), None);
+    assert_eq!(lockout_for(FREE_ATTEMPTS - 1), None);
+    assert_eq!(lockout_for(FREE_ATTEMPTS), Some(BASE_LOCKOUT));
+    assert_eq!(lockout_for(FREE_ATTEMPTS + 1), Some(BASE_LOCKOUT * 2));
+    assert_eq!(lockout_for(FREE_ATTEMPTS + 3), Some(BASE_LOCKOUT * 8));
+    assert_eq!(lockout_for(FREE_ATTEMPTS + 100), Some(MAX_LOCKOUT));
+  }
+
+  #[test]
+  fn tracks_ip_and_normalised_username() {
+    let limiter = AuthLimiter::new("blog")
+      .ip(Some("203.0.113.9"))
+      .username(" Admin ");
+
+    assert_eq!(limiter.subjects(), ["ip/203.0.113.9", "user/admin"]);
+    assert_eq!(
+      limiter.usual_ip_key().as_deref(),
+      Some("auth_usual_ip/blog/admin/203.0.113.9")
+    );
+    assert!(AuthLimiter::new("management").usual_ip_key().is_none());
+  }
+}
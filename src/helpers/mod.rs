@@ -1,2 +1,11 @@
 pub mod authentication;
+pub mod client_ip;
+pub mod exif;
+pub mod geo;
+pub mod http_signatures;
+pub mod images;
+pub mod limiter;
+pub mod og_image;
 pub mod riderr;
+pub mod totp;
+pub mod zip_stream;
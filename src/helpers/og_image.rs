@@ -0,0 +1,185 @@
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use image::{ImageFormat, Rgba, RgbaImage};
+use std::io::Cursor;
+
+/// Bitstream Vera license, see `assets/fonts/LICENSE-DejaVu`.
+static FONT: &[u8] =
+  include_bytes!("../../assets/fonts/DejaVuSans-Bold.ttf");
+
+pub const OG_WIDTH: u32 = 1200;
+pub const OG_HEIGHT: u32 = 630;
+
+const PADDING: f32 = 80.0;
+const TITLE_SIZE: f32 = 68.0;
+const TITLE_MAX_LINES: usize = 4;
+const TAGS_SIZE: f32 = 30.0;
+
+const BACKGROUND: Rgba<u8> = Rgba([17, 17, 17, 255]);
+const TITLE_COLOR: Rgba<u8> = Rgba([245, 245, 245, 255]);
+const TAGS_COLOR: Rgba<u8> = Rgba([150, 150, 150, 255]);
+
+fn text_width(font: &FontRef, scale: PxScale, text: &str) -> f32 {
+  let font = font.as_scaled(scale);
+  let mut width = 0.0;
+  let mut previous = None;
+
+  for char in text.chars() {
+    let glyph = font.glyph_id(char);
+    if let Some(previous) = previous {
+      width += font.kern(previous, glyph);
+    }
+    width += font.h_advance(glyph);
+    previous = Some(glyph);
+  }
+
+  width
+}
+
+/// Greedy word wrap to `max_width`. Anything past `max_lines` is cut and
+/// the last line ends in an ellipsis.
+fn wrap_text(
+  font: &FontRef,
+  scale: PxScale,
+  text: &str,
+  max_width: f32,
+  max_lines: usize,
+) -> Vec<String> {
+  let mut lines: Vec<String> = Vec::new();
+  let mut current = String::new();
+
+  for word in text.split_whitespace() {
+    let candidate = if current.is_empty() {
+      word.to_string()
+    } else {
+      format!("{current} {word}")
+    };
+
+    if current.is_empty()
+      || text_width(font, scale, &candidate) <= max_width
+    {
+      current = candidate;
+    } else {
+      lines.push(std::mem::replace(&mut current, word.to_string()));
+    }
+  }
+
+  if !current.is_empty() {
+    lines.push(current);
+  }
+
+  if lines.len() > max_lines {
+    lines.truncate(max_lines);
+    let last = lines.last_mut().unwrap();
+    while !last.is_empty()
+      && text_width(font, scale, &format!("{last}…")) > max_width
+    {
+      last.pop();
+    }
+    last.push('…');
+  }
+
+  lines
+}
+
+fn draw_text(
+  canvas: &mut RgbaImage,
+  font: &FontRef,
+  scale: PxScale,
+  color: Rgba<u8>,
+  x: f32,
+  baseline: f32,
+  text: &str,
+) {
+  let scaled = font.as_scaled(scale);
+  let mut caret = x;
+  let mut previous = None;
+
+  for char in text.chars() {
+    let glyph_id = scaled.glyph_id(char);
+    if let Some(previous) = previous {
+      caret += scaled.kern(previous, glyph_id);
+    }
+
+    let glyph = glyph_id
+      .with_scale_and_position(scale, ab_glyph::point(caret, baseline));
+    caret += scaled.h_advance(glyph_id);
+    previous = Some(glyph_id);
+
+    let outline = match font.outline_glyph(glyph) {
+      Some(outline) => outline,
+      None => continue,
+    };
+
+    let bounds = outline.px_bounds();
+    outline.draw(|gx, gy, coverage| {
+      let px = bounds.min.x as i32 + gx as i32;
+      let py = bounds.min.y as i32 + gy as i32;
+      if px < 0
+        || py < 0
+        || px >= canvas.width() as i32
+        || py >= canvas.height() as i32
+      {
+        return;
+      }
+
+      let pixel = canvas.get_pixel_mut(px as u32, py as u32);
+      for channel in 0..3 {
+        pixel[channel] = (pixel[channel] as f32 * (1.0 - coverage)
+          + color[channel] as f32 * coverage)
+          as u8;
+      }
+    });
+  }
+}
+
+/// Render a 1200x630 Open Graph card with the post title and its tags.
+pub fn render_og_image(title: &str, tags: &[String]) -> Option<Vec<u8>> {
+  let font = FontRef::try_from_slice(FONT).ok()?;
+  let mut canvas = RgbaImage::from_pixel(OG_WIDTH, OG_HEIGHT, BACKGROUND);
+  let max_width = OG_WIDTH as f32 - PADDING * 2.0;
+
+  let title_scale = PxScale::from(TITLE_SIZE);
+  let line_height = TITLE_SIZE * 1.2;
+  let lines =
+    wrap_text(&font, title_scale, title, max_width, TITLE_MAX_LINES);
+
+  for (index, line) in lines.iter().enumerate() {
+    let baseline = PADDING + TITLE_SIZE + index as f32 * line_height;
+    draw_text(
+      &mut canvas,
+      &font,
+      title_scale,
+      TITLE_COLOR,
+      PADDING,
+      baseline,
+      line,
+    );
+  }
+
+  if !tags.is_empty() {
+    let tags_scale = PxScale::from(TAGS_SIZE);
+    let tags = tags
+      .iter()
+      .map(|tag| format!("#{tag}"))
+      .collect::<Vec<String>>()
+      .join(" ");
+    let tags = wrap_text(&font, tags_scale, &tags, max_width, 1);
+
+    draw_text(
+      &mut canvas,
+      &font,
+      tags_scale,
+      TAGS_COLOR,
+      PADDING,
+      OG_HEIGHT as f32 - PADDING,
+      tags.first().map(String::as_str).unwrap_or_default(),
+    );
+  }
+
+  let mut data = Vec::new();
+  canvas
+    .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+    .ok()?;
+
+  Some(data)
+}
@@ -30,3 +30,21 @@ pub async fn send_riderr_event(rabbit: &mut RabbitManager) {
 
   rabbit.publish_ride_state(&state).await;
 }
+
+/// Current stats from the Riderr API, or `None` if it couldn't be reached.
+pub async fn fetch_user_stats() -> Option<RiderrUserStats> {
+  let config = Config::init_from_env().unwrap();
+
+  reqwest::Client::new()
+    .get(format!("{}/v1/users/stats", config.riderr_api_endpoint))
+    .header(
+      "Authorization",
+      format!("ApiKey {}", config.riderr_api_token),
+    )
+    .send()
+    .await
+    .ok()?
+    .json::<RiderrUserStats>()
+    .await
+    .ok()
+}
@@ -0,0 +1,277 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use crc32fast::Hasher;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP64_END: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+const END: u32 = 0x0605_4b50;
+
+/// Checksums and sizes follow the contents (bit 3), and names
+/// are UTF-8 (bit 11).
+const FLAGS: u16 = 0x0808;
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+const ZIP64_EXTRA: u16 = 0x0001;
+
+struct Entry {
+  name: String,
+  time: u16,
+  date: u16,
+  crc: u32,
+  size: u64,
+  offset: u64,
+}
+
+/// Builds a zip archive piece by piece, so it can be streamed to a client
+/// without being held in memory. Files are stored uncompressed, since
+/// photos don't compress, and their checksums and sizes go in a data
+/// descriptor after the contents so nothing has to be known up front.
+/// Each file has to be under 4 GiB, which `write` enforces; the archive as
+/// a whole can be larger.
+#[derive(Default)]
+pub struct ZipStream {
+  entries: Vec<Entry>,
+  current: Option<(Entry, Hasher)>,
+  offset: u64,
+}
+
+/// MS-DOS time and date, which is all zip headers have room for.
+fn dos_datetime(modified: NaiveDateTime) -> (u16, u16) {
+  let time = (modified.hour() << 11)
+    | (modified.minute() << 5)
+    | (modified.second() / 2);
+  let date = ((modified.year().clamp(1980, 2107) - 1980) as u32) << 9
+    | (modified.month() << 5)
+    | modified.day();
+
+  (time as u16, date as u16)
+}
+
+impl ZipStream {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Header for a new file. Its contents go through `write`, followed by
+  /// `finish_file`.
+  pub fn start_file(
+    &mut self,
+    name: &str,
+    modified: NaiveDateTime,
+  ) -> Bytes {
+    let (time, date) = dos_datetime(modified);
+    let mut header = BytesMut::with_capacity(30 + name.len());
+
+    header.put_u32_le(LOCAL_HEADER);
+    header.put_u16_le(VERSION);
+    header.put_u16_le(FLAGS);
+    header.put_u16_le(0); // stored
+    header.put_u16_le(time);
+    header.put_u16_le(date);
+    // The checksum and sizes follow in the data descriptor.
+    header.put_u32_le(0);
+    header.put_u32_le(0);
+    header.put_u32_le(0);
+    header.put_u16_le(name.len() as u16);
+    header.put_u16_le(0);
+    header.put_slice(name.as_bytes());
+
+    let entry = Entry {
+      name: name.to_string(),
+      time,
+      date,
+      crc: 0,
+      size: 0,
+      offset: self.offset,
+    };
+    self.current = Some((entry, Hasher::new()));
+    self.offset += header.len() as u64;
+
+    header.freeze()
+  }
+
+  /// Pass a chunk of the current file's contents through. Errors once the
+  /// file reaches 4 GiB, since its size would no longer fit the data
+  /// descriptor and the archive would be corrupt.
+  pub fn write(&mut self, data: Bytes) -> Result<Bytes, &'static str> {
+    if let Some((entry, hasher)) = self.current.as_mut() {
+      if entry.size + data.len() as u64 >= u32::MAX as u64 {
+        return Err("file_too_large");
+      }
+
+      hasher.update(&data);
+      entry.size += data.len() as u64;
+    }
+    self.offset += data.len() as u64;
+
+    Ok(data)
+  }
+
+  /// Data descriptor closing the current file.
+  pub fn finish_file(&mut self) -> Bytes {
+    let (mut entry, hasher) = match self.current.take() {
+      Some(current) => current,
+      None => return Bytes::new(),
+    };
+    entry.crc = hasher.finalize();
+
+    let mut descriptor = BytesMut::with_capacity(16);
+    descriptor.put_u32_le(DATA_DESCRIPTOR);
+    descriptor.put_u32_le(entry.crc);
+    descriptor.put_u32_le(entry.size as u32);
+    descriptor.put_u32_le(entry.size as u32);
+
+    self.offset += descriptor.len() as u64;
+    self.entries.push(entry);
+
+    descriptor.freeze()
+  }
+
+  /// Central directory and end records. Past 4 GiB or 65535 files, the
+  /// ZIP64 versions of the end records are written as well.
+  pub fn finish(mut self) -> Bytes {
+    if self.current.is_some() {
+      self.finish_file();
+    }
+
+    let directory_offset = self.offset;
+    let mut out = BytesMut::new();
+
+    for entry in &self.entries {
+      let zip64 = entry.offset >= u32::MAX as u64;
+
+      out.put_u32_le(CENTRAL_HEADER);
+      out.put_u16_le(VERSION_ZIP64);
+      out.put_u16_le(if zip64 { VERSION_ZIP64 } else { VERSION });
+      out.put_u16_le(FLAGS);
+      out.put_u16_le(0);
+      out.put_u16_le(entry.time);
+      out.put_u16_le(entry.date);
+      out.put_u32_le(entry.crc);
+      out.put_u32_le(entry.size as u32);
+      out.put_u32_le(entry.size as u32);
+      out.put_u16_le(entry.name.len() as u16);
+      out.put_u16_le(if zip64 { 12 } else { 0 });
+      out.put_u16_le(0); // comment
+      out.put_u16_le(0); // disk
+      out.put_u16_le(0); // internal attributes
+      out.put_u32_le(0); // external attributes
+      out.put_u32_le(if zip64 { u32::MAX } else { entry.offset as u32 });
+      out.put_slice(entry.name.as_bytes());
+
+      if zip64 {
+        out.put_u16_le(ZIP64_EXTRA);
+        out.put_u16_le(8);
+        out.put_u64_le(entry.offset);
+      }
+    }
+
+    let directory_size = out.len() as u64;
+    let count = self.entries.len() as u64;
+    let zip64 = count >= u16::MAX as u64
+      || directory_offset >= u32::MAX as u64
+      || directory_size >= u32::MAX as u64;
+
+    if zip64 {
+      let end_offset = directory_offset + directory_size;
+
+      out.put_u32_le(ZIP64_END);
+      out.put_u64_le(44);
+      out.put_u16_le(VERSION_ZIP64);
+      out.put_u16_le(VERSION_ZIP64);
+      out.put_u32_le(0);
+      out.put_u32_le(0);
+      out.put_u64_le(count);
+      out.put_u64_le(count);
+      out.put_u64_le(directory_size);
+      out.put_u64_le(directory_offset);
+
+      out.put_u32_le(ZIP64_LOCATOR);
+      out.put_u32_le(0);
+      out.put_u64_le(end_offset);
+      out.put_u32_le(1);
+    }
+
+    out.put_u32_le(END);
+    out.put_u16_le(0);
+    out.put_u16_le(0);
+    out.put_u16_le(count.min(u16::MAX as u64) as u16);
+    out.put_u16_le(count.min(u16::MAX as u64) as u16);
+    out.put_u32_le(directory_size.min(u32::MAX as u64) as u32);
+    out.put_u32_le(directory_offset.min(u32::MAX as u64) as u32);
+    out.put_u16_le(0);
+
+    out.freeze()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{Cursor, Read};
+  use zip::ZipArchive;
+
+  fn modified() -> NaiveDateTime {
+    chrono::NaiveDate::from_ymd_opt(2024, 5, 17)
+      .unwrap()
+      .and_hms_opt(13, 45, 30)
+      .unwrap()
+  }
+
+  #[test]
+  fn streamed_archive_reads_back() {
+    let files: [(&str, Vec<Vec<u8>>); 3] = [
+      (
+        "album/first.jpg",
+        vec![b"first ".to_vec(), b"photo".to_vec()],
+      ),
+      ("album/empty.txt", vec![]),
+      (
+        "album/ünïcode.json",
+        vec![vec![7u8; 100_000], b"{}".to_vec()],
+      ),
+    ];
+
+    let mut zip = ZipStream::new();
+    let mut archive = Vec::new();
+    for (name, chunks) in &files {
+      archive.extend_from_slice(&zip.start_file(name, modified()));
+      for chunk in chunks {
+        archive
+          .extend_from_slice(&zip.write(chunk.clone().into()).unwrap());
+      }
+      archive.extend_from_slice(&zip.finish_file());
+    }
+    archive.extend_from_slice(&zip.finish());
+
+    let mut archive = ZipArchive::new(Cursor::new(archive)).unwrap();
+    assert_eq!(archive.len(), files.len());
+
+    for (index, (name, chunks)) in files.iter().enumerate() {
+      let expected = chunks.concat();
+      let mut file = archive.by_index(index).unwrap();
+
+      assert_eq!(file.name(), *name);
+      assert_eq!(file.size(), expected.len() as u64);
+      assert_eq!(file.crc32(), crc32fast::hash(&expected));
+
+      // Reading to the end also has the zip crate check the checksum.
+      let mut contents = Vec::new();
+      file.read_to_end(&mut contents).unwrap();
+      assert_eq!(contents, expected);
+    }
+  }
+
+  #[test]
+  fn rejects_files_of_4_gib() {
+    let mut zip = ZipStream::new();
+    zip.start_file("huge.bin", modified());
+    zip.current.as_mut().unwrap().0.size = u32::MAX as u64 - 2;
+
+    assert!(zip.write(Bytes::from_static(b"a")).is_ok());
+    assert_eq!(zip.write(Bytes::from_static(b"b")), Err("file_too_large"));
+  }
+}
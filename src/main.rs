@@ -67,6 +67,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
     tracing::info!("Running in DEV mode");
   }
 
+  if config.photography_unlock_secret.is_empty() {
+    tracing::warn!(
+      "PHOTOGRAPHY_UNLOCK_SECRET is not set, password-protected albums \
+       can't be unlocked"
+    );
+  }
+
   let data = web::Data::new(ServerState {
     valkey,
     rabbit,
@@ -76,6 +83,62 @@ async fn main() -> Result<(), Box<dyn Error>> {
     influxdb,
   });
   let data_http = web::Data::clone(&data);
+  let data_views = web::Data::clone(&data);
+  let data_trash = web::Data::clone(&data);
+  let data_uploads = web::Data::clone(&data);
+  let data_albums = web::Data::clone(&data);
+
+  // Sessions issued before the per-user index need adding to it once.
+  services::blog::sessions::index_legacy_sessions(
+    &mut data.valkey.clone(),
+  )
+  .await;
+
+  // Flush buffered blog post views to postgres every minute.
+  let mut views_interval = time::interval(Duration::from_secs(60));
+  tokio::spawn(async move {
+    views_interval.tick().await;
+    loop {
+      views_interval.tick().await;
+      tokio::spawn(modules::blog_views::flush_blog_views(web::Data::clone(
+        &data_views,
+      )));
+    }
+  });
+
+  // Purge blog posts whose trash retention has passed every hour.
+  let mut trash_interval = time::interval(Duration::from_secs(60 * 60));
+  tokio::spawn(async move {
+    loop {
+      trash_interval.tick().await;
+      tokio::spawn(modules::blog_trash::purge_expired_posts(
+        web::Data::clone(&data_trash),
+      ));
+    }
+  });
+
+  // Clean up abandoned resumable uploads every hour.
+  let mut uploads_interval = time::interval(Duration::from_secs(60 * 60));
+  tokio::spawn(async move {
+    loop {
+      uploads_interval.tick().await;
+      tokio::spawn(modules::resumable_uploads::clean_abandoned_uploads(
+        web::Data::clone(&data_uploads),
+      ));
+    }
+  });
+
+  // Move the files of hidden albums out of reach of their slug every hour,
+  // starting with any left from before albums had their own directories.
+  let mut albums_interval = time::interval(Duration::from_secs(60 * 60));
+  tokio::spawn(async move {
+    loop {
+      albums_interval.tick().await;
+      tokio::spawn(modules::photography_storage::relocate_albums(
+        web::Data::clone(&data_albums),
+      ));
+    }
+  });
 
   // Fetch spotify current playing every second.
   if config.env != "dev" {
@@ -121,6 +184,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
       .wrap(connectivity::middleware::ResponseMeta)
       .default_service(web::to(services::base::index))
       .service(services::base::health)
+      .service(services::blog::activitypub::webfinger)
       .service(
         web::scope("/v2")
           .service(services::base::health)
@@ -0,0 +1,31 @@
+use actix_web::web;
+use envconfig::Envconfig;
+
+use crate::{
+  config::Config, services::blog::trash::purge_post,
+  structs::blog::BlogPost, ServerState,
+};
+
+/// Purge posts that have been in the trash longer than
+/// `BLOG_TRASH_RETENTION_DAYS`.
+pub(crate) async fn purge_expired_posts(data: web::Data<ServerState>) {
+  let config = Config::init_from_env().unwrap();
+
+  let posts = sqlx::query_as::<_, BlogPost>(
+    "SELECT * FROM blog_posts WHERE visibility = 'deleted' \
+     AND deleted_at < now() - make_interval(days => $1)",
+  )
+  .bind(config.blog_trash_retention_days)
+  .fetch_all(&data.db)
+  .await
+  .unwrap_or_default();
+
+  for post in posts {
+    match purge_post(&data, &post).await {
+      Ok(()) => tracing::info!("Purged trashed blog post {}", post.id),
+      Err(code) => {
+        tracing::warn!("Failed to purge blog post {}: {}", post.id, code)
+      }
+    }
+  }
+}
@@ -0,0 +1,92 @@
+use actix_web::web;
+use redis::aio::ConnectionManager;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{services::blog::views::VIEW_BUFFER_KEY, ServerState};
+
+const FLUSHING_KEY: &str = "blog_post_view_buffer/flushing";
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Move buffered view counts from Valkey into `blog_post_views`, unless a
+/// flush is already running.
+///
+/// The buffer is renamed before being read so views recorded during the
+/// flush land in a fresh hash. A batch left behind by a failed flush is
+/// retried before a new one is taken.
+pub(crate) async fn flush_blog_views(data: web::Data<ServerState>) {
+  if RUNNING.swap(true, Ordering::SeqCst) {
+    return;
+  }
+
+  flush_buffered_views(&data).await;
+  RUNNING.store(false, Ordering::SeqCst);
+}
+
+async fn flush_buffered_views(data: &ServerState) {
+  let valkey = &mut data.valkey.clone();
+
+  let _ = redis::cmd("RENAMENX")
+    .arg(VIEW_BUFFER_KEY)
+    .arg(FLUSHING_KEY)
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await;
+
+  let buffered = match redis::cmd("HGETALL")
+    .arg(FLUSHING_KEY)
+    .query_async::<ConnectionManager, Vec<(String, i64)>>(&mut valkey.cm)
+    .await
+  {
+    Ok(buffered) if !buffered.is_empty() => buffered,
+    _ => return,
+  };
+
+  for (field, views) in buffered {
+    let (post_id, day) = match field.rsplit_once('/') {
+      Some(parts) => parts,
+      None => continue,
+    };
+
+    // Each field is claimed by removing it before its INSERT, so a flush
+    // running elsewhere that read the same batch can't write it twice.
+    let claimed = redis::cmd("HDEL")
+      .arg(FLUSHING_KEY)
+      .arg(&field)
+      .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+      .await
+      .unwrap_or(0);
+
+    if claimed == 0 {
+      continue;
+    }
+
+    let result = sqlx::query(
+      "INSERT INTO blog_post_views (post_id, day, views) \
+       VALUES ($1, $2::date, $3) ON CONFLICT (post_id, day) \
+       DO UPDATE SET views = blog_post_views.views + EXCLUDED.views",
+    )
+    .bind(post_id)
+    .bind(day)
+    .bind(views as i32)
+    .execute(&data.db)
+    .await;
+
+    // Views of posts deleted since they were counted can never be written,
+    // so they are dropped. Anything else is put back for the next flush.
+    match result {
+      Ok(_) => {}
+      Err(sqlx::Error::Database(error))
+        if error.is_foreign_key_violation() => {}
+      Err(error) => {
+        tracing::warn!("Failed to flush views for {}: {}", field, error);
+
+        let _ = redis::cmd("HINCRBY")
+          .arg(FLUSHING_KEY)
+          .arg(&field)
+          .arg(views)
+          .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+          .await;
+      }
+    }
+  }
+}
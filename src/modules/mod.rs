@@ -1 +1,6 @@
+pub mod blog_trash;
+pub mod blog_views;
+pub mod photography_backfill;
+pub mod photography_storage;
+pub mod resumable_uploads;
 pub mod spotify;
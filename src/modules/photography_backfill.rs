@@ -0,0 +1,88 @@
+use actix_web::web;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+  services::photography::{
+    storage::{album_dir, original_path},
+    variants::{process_photo, save_processed_photo},
+  },
+  structs::photography::Photo,
+  ServerState,
+};
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Start generating variants and placeholders for photos missing them,
+/// unless a backfill is already running. Returns whether one was started.
+pub(crate) fn start_photo_backfill(data: web::Data<ServerState>) -> bool {
+  if RUNNING.swap(true, Ordering::SeqCst) {
+    return false;
+  }
+
+  tokio::spawn(async move {
+    backfill_photos(&data).await;
+    RUNNING.store(false, Ordering::SeqCst);
+  });
+
+  true
+}
+
+async fn backfill_photos(data: &ServerState) {
+  let photos = sqlx::query_as::<_, Photo>(
+    "SELECT * FROM photography_photos \
+     WHERE variants = '[]'::jsonb OR blurhash IS NULL \
+     ORDER BY album_slug, position",
+  )
+  .fetch_all(&data.db)
+  .await
+  .unwrap_or_default();
+
+  let mut processed = 0;
+  let mut failed = 0;
+
+  for photo in photos {
+    let path = match album_dir(&data.db, &photo.album_slug).await {
+      Ok(dir) => original_path(&dir, &photo.name),
+      Err(_) => {
+        failed += 1;
+        continue;
+      }
+    };
+    let original = match data.s3.cdn_bucket.get_object(&path).await {
+      Ok(original) if original.status_code() == 200 => original,
+      _ => {
+        tracing::warn!("Skipping photo {} missing from S3", path);
+        failed += 1;
+        continue;
+      }
+    };
+
+    let result = process_photo(
+      data,
+      &photo.album_slug,
+      &photo.name,
+      original.bytes().clone(),
+      photo.variants.is_empty(),
+    )
+    .await;
+    let saved = match result {
+      Some(result) => {
+        save_processed_photo(data, &photo.album_slug, &photo.name, result)
+          .await
+      }
+      None => false,
+    };
+
+    if saved {
+      processed += 1;
+    } else {
+      failed += 1;
+    }
+  }
+
+  tracing::info!(
+    "Photo backfill finished: {} processed, {} failed",
+    processed,
+    failed
+  );
+}
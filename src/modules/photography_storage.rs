@@ -0,0 +1,25 @@
+use actix_web::web;
+
+use crate::{
+  services::photography::storage::relocate_album,
+  structs::photography::Album, ServerState,
+};
+
+/// Move the files of albums stored in the wrong kind of directory for
+/// their visibility: ones hidden before albums had their own directories,
+/// and ones whose move failed when they were edited.
+pub(crate) async fn relocate_albums(data: web::Data<ServerState>) {
+  let albums = sqlx::query_as::<_, Album>(
+    "SELECT * FROM photography_albums \
+     WHERE (visibility IN ('draft', 'password')) = (storage_dir = slug)",
+  )
+  .fetch_all(&data.db)
+  .await
+  .unwrap_or_default();
+
+  for album in albums {
+    if !relocate_album(&data, &album).await {
+      tracing::warn!("Failed to move files of album {}", album.slug);
+    }
+  }
+}
@@ -0,0 +1,76 @@
+use std::collections::HashSet;
+
+use actix_web::web;
+use chrono::{DateTime, Duration, Utc};
+use envconfig::Envconfig;
+
+use crate::{
+  config::Config, services::uploads::resumable::discard_upload_objects,
+  structs::uploads::ResumableUpload, ServerState,
+};
+
+/// Drop resumable uploads that haven't had a chunk in
+/// `UPLOAD_ABANDON_HOURS`, along with their S3 multipart uploads. S3
+/// uploads left without a row, from a request that died between starting
+/// one and saving it, are aborted too.
+pub(crate) async fn clean_abandoned_uploads(data: web::Data<ServerState>) {
+  let config = Config::init_from_env().unwrap();
+
+  let uploads = sqlx::query_as::<_, ResumableUpload>(
+    "DELETE FROM resumable_uploads \
+     WHERE updated_at < now() - make_interval(hours => $1) RETURNING *",
+  )
+  .bind(config.upload_abandon_hours)
+  .fetch_all(&data.db)
+  .await
+  .unwrap_or_default();
+
+  for upload in uploads {
+    discard_upload_objects(&data, &upload).await;
+    tracing::info!("Cleaned up abandoned upload {}", upload.id);
+  }
+
+  let known: HashSet<String> =
+    sqlx::query_scalar::<_, String>("SELECT id FROM resumable_uploads")
+      .fetch_all(&data.db)
+      .await
+      .unwrap_or_default()
+      .into_iter()
+      .collect();
+
+  let listings = data
+    .s3
+    .cdn_bucket
+    .list_multiparts_uploads(Some("uploads/pending/"), None)
+    .await
+    .unwrap_or_default();
+  let cutoff =
+    Utc::now() - Duration::hours(config.upload_abandon_hours as i64);
+
+  for upload in listings.into_iter().flat_map(|listing| listing.uploads) {
+    let id = upload.key.trim_start_matches("uploads/pending/");
+    let initiated = DateTime::parse_from_rfc3339(&upload.initiated)
+      .map(|initiated| initiated.with_timezone(&Utc));
+    if known.contains(id) || !initiated.is_ok_and(|at| at < cutoff) {
+      continue;
+    }
+
+    match data
+      .s3
+      .cdn_bucket
+      .abort_upload(&upload.key, &upload.id)
+      .await
+    {
+      Ok(()) => {
+        tracing::info!("Aborted orphaned S3 upload {}", upload.key)
+      }
+      Err(error) => {
+        tracing::warn!(
+          "Failed to abort S3 upload {}: {}",
+          upload.key,
+          error
+        )
+      }
+    }
+  }
+}
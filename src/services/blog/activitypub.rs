@@ -0,0 +1,885 @@
+use actix_web::{get, http::Error, post, web, HttpRequest, HttpResponse};
+use envconfig::Envconfig;
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::{header, Url};
+use rsa::{
+  pkcs8::{
+    DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding,
+  },
+  RsaPrivateKey,
+};
+use serde_json::json;
+use std::{
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+  time::Duration,
+};
+
+use crate::{
+  config::Config,
+  helpers::http_signatures::{
+    parse_public_key, sign_request, verify_request, SignatureHeader,
+  },
+  structs::blog::{BlogActivityPubKey, BlogPost, BlogWebFingerQuery},
+  ServerState,
+};
+
+const ACTIVITY_JSON: &str = "application/activity+json";
+const ACTIVITY_STREAMS: &str = "https://www.w3.org/ns/activitystreams";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+const KEY_SIZE: usize = 2048;
+const OUTBOX_SIZE: i64 = 20;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest remote actor document that will be read.
+const MAX_ACTOR_SIZE: usize = 1024 * 1024;
+
+fn actor_id(config: &Config) -> String {
+  format!("{}/actor", config.blog_activitypub_url)
+}
+
+fn key_id(config: &Config) -> String {
+  format!("{}#main-key", actor_id(config))
+}
+
+fn followers_id(config: &Config) -> String {
+  format!("{}/followers", config.blog_activitypub_url)
+}
+
+fn activity_response(body: serde_json::Value) -> HttpResponse {
+  HttpResponse::Ok().content_type(ACTIVITY_JSON).json(body)
+}
+
+fn escape_html(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// The actor's key pair, generated and stored the first time it's needed.
+async fn actor_key(state: &ServerState) -> Option<BlogActivityPubKey> {
+  let existing = sqlx::query_as::<_, BlogActivityPubKey>(
+    "SELECT * FROM blog_activitypub_keys WHERE id = 1",
+  )
+  .fetch_optional(&state.db)
+  .await
+  .ok()?;
+
+  if existing.is_some() {
+    return existing;
+  }
+
+  let generated = web::block(|| -> Option<(String, String)> {
+    let key =
+      RsaPrivateKey::new(&mut rand::thread_rng(), KEY_SIZE).ok()?;
+    let private_key = key.to_pkcs8_pem(LineEnding::LF).ok()?.to_string();
+    let public_key =
+      key.to_public_key().to_public_key_pem(LineEnding::LF).ok()?;
+
+    Some((private_key, public_key))
+  })
+  .await
+  .ok()
+  .flatten()?;
+
+  // If another request got there first, its key is kept and ours dropped.
+  sqlx::query(
+    "INSERT INTO blog_activitypub_keys (id, private_key, public_key) \
+     VALUES (1, $1, $2) ON CONFLICT (id) DO NOTHING",
+  )
+  .bind(generated.0)
+  .bind(generated.1)
+  .execute(&state.db)
+  .await
+  .ok()?;
+
+  sqlx::query_as::<_, BlogActivityPubKey>(
+    "SELECT * FROM blog_activitypub_keys WHERE id = 1",
+  )
+  .fetch_one(&state.db)
+  .await
+  .ok()
+}
+
+async fn signing_key(state: &ServerState) -> Option<RsaPrivateKey> {
+  let key = actor_key(state).await?;
+  RsaPrivateKey::from_pkcs8_pem(&key.private_key).ok()
+}
+
+/// Whether an address is reachable from the public internet. Remote
+/// servers choose the URLs that get fetched and delivered to, so anything
+/// internal (loopback, private ranges, link-local including cloud metadata
+/// endpoints) is off limits.
+fn is_public_address(ip: IpAddr) -> bool {
+  let ip = match ip.to_canonical() {
+    IpAddr::V6(ip) => match embedded_ipv4(ip) {
+      Some(embedded) => IpAddr::V4(embedded),
+      None => IpAddr::V6(ip),
+    },
+    ip => ip,
+  };
+
+  match ip {
+    IpAddr::V4(ip) => {
+      let [first, second, ..] = ip.octets();
+      !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || first == 0
+        // Carrier-grade NAT, 100.64.0.0/10.
+        || (first == 100 && second & 0xc0 == 64))
+    }
+    IpAddr::V6(ip) => {
+      let first = ip.segments()[0];
+      !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local, fc00::/7.
+        || first & 0xfe00 == 0xfc00
+        // Link-local, fe80::/10.
+        || first & 0xffc0 == 0xfe80
+        // Teredo, 2001::/32, which tunnels to an address that can't be
+        // checked.
+        || (first == 0x2001 && ip.segments()[1] == 0))
+    }
+  }
+}
+
+/// The IPv4 address an IPv6 address is a translation of, so the range
+/// checks apply to where it really leads: NAT64 (`64:ff9b::/96` and
+/// `64:ff9b:1::/48`), 6to4 (`2002::/16`) and the old IPv4-compatible form
+/// (`::/96`).
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+  let segments = ip.segments();
+  let [.., high, low] = segments;
+  let last = Ipv4Addr::from(((high as u32) << 16) | low as u32);
+
+  match segments {
+    [0x64, 0xff9b, 0, 0, 0, 0, ..] | [0x64, 0xff9b, 1, ..] => Some(last),
+    [0x2002, high, low, ..] => {
+      Some(Ipv4Addr::from(((high as u32) << 16) | low as u32))
+    }
+    [0, 0, 0, 0, 0, 0, ..]
+      if !ip.is_loopback() && !ip.is_unspecified() =>
+    {
+      Some(last)
+    }
+    _ => None,
+  }
+}
+
+/// Client for a request to a remote server, or `None` if `url` isn't an
+/// https URL whose host resolves only to public addresses. The client is
+/// pinned to the checked address so DNS can't hand out a different one
+/// for the request itself, and doesn't follow redirects, which would skip
+/// the check.
+async fn remote_client(url: &Url) -> Option<reqwest::Client> {
+  if url.scheme() != "https" {
+    return None;
+  }
+
+  let host = url.host_str()?;
+  let port = url.port_or_known_default()?;
+  let addresses: Vec<SocketAddr> =
+    tokio::net::lookup_host((host, port)).await.ok()?.collect();
+
+  if addresses.is_empty()
+    || !addresses
+      .iter()
+      .all(|address| is_public_address(address.ip()))
+  {
+    return None;
+  }
+
+  reqwest::Client::builder()
+    .resolve(host, addresses[0])
+    .redirect(reqwest::redirect::Policy::none())
+    .build()
+    .ok()
+}
+
+/// Fetch a remote actor (or key) document. The request is signed so that
+/// servers requiring authorized fetch answer it too.
+async fn fetch_actor(
+  state: &ServerState,
+  id: &str,
+) -> Option<serde_json::Value> {
+  let config = Config::init_from_env().unwrap();
+  let url = Url::parse(id).ok()?;
+  let client = remote_client(&url).await?;
+
+  let mut request = client
+    .get(url.clone())
+    .header(header::ACCEPT, ACTIVITY_JSON)
+    .timeout(REQUEST_TIMEOUT);
+  if let Some(key) = signing_key(state).await {
+    for (name, value) in
+      sign_request(key, &key_id(&config), "GET", &url, None)
+    {
+      request = request.header(name, value);
+    }
+  }
+
+  let mut response = request.send().await.ok()?.error_for_status().ok()?;
+  if response
+    .content_length()
+    .is_some_and(|length| length > MAX_ACTOR_SIZE as u64)
+  {
+    return None;
+  }
+
+  // Read in chunks so a server that lies about, or leaves out, its length
+  // still can't send more than the cap.
+  let mut body = Vec::new();
+  while let Some(chunk) = response.chunk().await.ok()? {
+    if body.len() + chunk.len() > MAX_ACTOR_SIZE {
+      return None;
+    }
+    body.extend_from_slice(&chunk);
+  }
+
+  serde_json::from_slice(&body).ok()
+}
+
+/// POST a signed activity to a remote inbox.
+async fn deliver(
+  state: &ServerState,
+  inbox: &str,
+  activity: &serde_json::Value,
+) -> bool {
+  let config = Config::init_from_env().unwrap();
+
+  let url = match Url::parse(inbox) {
+    Ok(url) => url,
+    Err(_) => {
+      tracing::warn!("Skipping delivery to invalid inbox {}", inbox);
+      return false;
+    }
+  };
+  let client = match remote_client(&url).await {
+    Some(client) => client,
+    None => {
+      tracing::warn!("Skipping delivery to non-public inbox {}", inbox);
+      return false;
+    }
+  };
+  let key = match signing_key(state).await {
+    Some(key) => key,
+    None => {
+      tracing::warn!("No ActivityPub key available, skipping delivery");
+      return false;
+    }
+  };
+
+  send_activity(&client, &url, key, &key_id(&config), activity).await
+}
+
+/// Sign and POST an activity with a client from `remote_client`.
+async fn send_activity(
+  client: &reqwest::Client,
+  url: &Url,
+  key: RsaPrivateKey,
+  key_id: &str,
+  activity: &serde_json::Value,
+) -> bool {
+  let body = serde_json::to_vec(activity).unwrap_or_default();
+  let mut request = client
+    .post(url.clone())
+    .header(header::CONTENT_TYPE, ACTIVITY_JSON)
+    .timeout(REQUEST_TIMEOUT);
+  for (name, value) in sign_request(key, key_id, "POST", url, Some(&body))
+  {
+    request = request.header(name, value);
+  }
+
+  match request.body(body).send().await {
+    Ok(response) if response.status().is_success() => true,
+    Ok(response) => {
+      tracing::warn!(
+        "Delivery to {} failed with status {}",
+        url,
+        response.status()
+      );
+      false
+    }
+    Err(error) => {
+      tracing::warn!("Delivery to {} failed: {}", url, error);
+      false
+    }
+  }
+}
+
+/// A post as an `Article`. Remote servers show the title and link, so the
+/// HTML content is kept to the description; the Markdown goes in `source`.
+fn post_object(config: &Config, post: &BlogPost) -> serde_json::Value {
+  let url = format!("{}/{}", config.blog_url, post.slug);
+
+  let mut content = String::new();
+  if let Some(description) = &post.description {
+    content.push_str(&format!("<p>{}</p>", escape_html(description)));
+  }
+  content.push_str(&format!(
+    "<p><a href=\"{}\">{}</a></p>",
+    escape_html(&url),
+    escape_html(&url)
+  ));
+
+  let tags: Vec<serde_json::Value> = post
+    .tags
+    .iter()
+    .map(|tag| json!({"type": "Hashtag", "name": format!("#{tag}")}))
+    .collect();
+
+  json!({
+    "id": format!("{}/posts/{}", config.blog_activitypub_url, post.id),
+    "type": "Article",
+    "attributedTo": actor_id(config),
+    "name": post.title,
+    "summary": post.description,
+    "content": content,
+    "mediaType": "text/html",
+    "source": {
+      "content": post.body.as_deref().unwrap_or_default(),
+      "mediaType": "text/markdown",
+    },
+    "url": url,
+    "image": post.image.as_ref().map(|image| json!({
+      "type": "Image",
+      "url": image,
+    })),
+    "tag": tags,
+    "published": post
+      .published_at
+      .unwrap_or(post.created_at)
+      .format("%Y-%m-%dT%H:%M:%SZ")
+      .to_string(),
+    "to": [PUBLIC],
+    "cc": [followers_id(config)],
+  })
+}
+
+fn create_activity(config: &Config, post: &BlogPost) -> serde_json::Value {
+  let object = post_object(config, post);
+
+  json!({
+    "id": format!("{}/activity", object["id"].as_str().unwrap_or_default()),
+    "type": "Create",
+    "actor": actor_id(config),
+    "published": object["published"],
+    "to": [PUBLIC],
+    "cc": [followers_id(config)],
+    "object": object,
+  })
+}
+
+/// Send a newly published post to every follower, once per shared inbox.
+pub(crate) async fn deliver_post(
+  state: web::Data<ServerState>,
+  post: BlogPost,
+) {
+  let config = Config::init_from_env().unwrap();
+
+  let mut activity = create_activity(&config, &post);
+  activity["@context"] = json!(ACTIVITY_STREAMS);
+
+  let inboxes = sqlx::query_as::<_, (String,)>(
+    "SELECT DISTINCT COALESCE(shared_inbox, inbox) FROM blog_followers",
+  )
+  .fetch_all(&state.db)
+  .await
+  .unwrap_or_default();
+
+  let mut delivered = 0;
+  for (inbox,) in &inboxes {
+    if deliver(&state, inbox, &activity).await {
+      delivered += 1;
+    }
+  }
+
+  tracing::info!(
+    "Delivered blog post {} to {}/{} inboxes",
+    post.id,
+    delivered,
+    inboxes.len()
+  );
+}
+
+#[get("/.well-known/webfinger")]
+async fn webfinger(
+  query: web::Query<BlogWebFingerQuery>,
+) -> Result<HttpResponse, Error> {
+  let config = Config::init_from_env().unwrap();
+  let subject = format!(
+    "acct:{}@{}",
+    config.blog_activitypub_username, config.blog_activitypub_domain
+  );
+  let actor = actor_id(&config);
+
+  if !query.resource.eq_ignore_ascii_case(&subject)
+    && query.resource != actor
+  {
+    return Ok(
+      HttpResponse::NotFound().json(json!({"code": "resource_not_found"})),
+    );
+  }
+
+  Ok(
+    HttpResponse::Ok()
+      .content_type("application/jrd+json")
+      .json(json!({
+        "subject": subject,
+        "aliases": [actor, config.blog_url],
+        "links": [
+          {"rel": "self", "type": ACTIVITY_JSON, "href": actor},
+          {
+            "rel": "http://webfinger.net/rel/profile-page",
+            "type": "text/html",
+            "href": config.blog_url,
+          },
+        ],
+      })),
+  )
+}
+
+#[get("/activitypub/actor")]
+async fn get_actor(
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let config = Config::init_from_env().unwrap();
+
+  let key = match actor_key(&state).await {
+    Some(key) => key,
+    None => {
+      return Ok(
+        HttpResponse::InternalServerError()
+          .json(json!({"code": "failed_to_load_key"})),
+      );
+    }
+  };
+
+  let actor = actor_id(&config);
+  Ok(activity_response(json!({
+    "@context": [ACTIVITY_STREAMS, "https://w3id.org/security/v1"],
+    "id": actor,
+    "type": "Person",
+    "preferredUsername": config.blog_activitypub_username,
+    "name": config.blog_activitypub_name,
+    "url": config.blog_url,
+    "inbox": format!("{}/inbox", config.blog_activitypub_url),
+    "outbox": format!("{}/outbox", config.blog_activitypub_url),
+    "followers": followers_id(&config),
+    "manuallyApprovesFollowers": false,
+    "discoverable": true,
+    "publicKey": {
+      "id": key_id(&config),
+      "owner": actor,
+      "publicKeyPem": key.public_key,
+    },
+  })))
+}
+
+#[get("/activitypub/outbox")]
+async fn get_outbox(
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let config = Config::init_from_env().unwrap();
+
+  let posts = sqlx::query_as::<_, BlogPost>(
+    "SELECT * FROM blog_posts WHERE visibility = 'public' \
+     ORDER BY published_at DESC NULLS LAST LIMIT $1",
+  )
+  .bind(OUTBOX_SIZE)
+  .fetch_all(&state.db)
+  .await;
+  let total = sqlx::query_as::<_, (i64,)>(
+    "SELECT COUNT(*) FROM blog_posts WHERE visibility = 'public'",
+  )
+  .fetch_one(&state.db)
+  .await;
+
+  match (posts, total) {
+    (Ok(posts), Ok((total,))) => {
+      let items: Vec<serde_json::Value> = posts
+        .iter()
+        .map(|post| create_activity(&config, post))
+        .collect();
+
+      Ok(activity_response(json!({
+        "@context": ACTIVITY_STREAMS,
+        "id": format!("{}/outbox", config.blog_activitypub_url),
+        "type": "OrderedCollection",
+        "totalItems": total,
+        "orderedItems": items,
+      })))
+    }
+    _ => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_lookup_posts"})),
+    ),
+  }
+}
+
+#[get("/activitypub/followers")]
+async fn get_followers(
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let config = Config::init_from_env().unwrap();
+
+  // Only the count is public, not who the followers are.
+  let total =
+    sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM blog_followers")
+      .fetch_one(&state.db)
+      .await;
+
+  match total {
+    Ok((total,)) => Ok(activity_response(json!({
+      "@context": ACTIVITY_STREAMS,
+      "id": followers_id(&config),
+      "type": "OrderedCollection",
+      "totalItems": total,
+    }))),
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_lookup_followers"})),
+    ),
+  }
+}
+
+#[get("/activitypub/posts/{id}")]
+async fn get_post_object(
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let config = Config::init_from_env().unwrap();
+
+  let post = sqlx::query_as::<_, BlogPost>(
+    "SELECT * FROM blog_posts WHERE id = $1 AND visibility = 'public' \
+     LIMIT 1",
+  )
+  .bind(id.to_string())
+  .fetch_optional(&state.db)
+  .await;
+
+  match post {
+    Ok(Some(post)) => {
+      let mut object = post_object(&config, &post);
+      object["@context"] = json!(ACTIVITY_STREAMS);
+      Ok(activity_response(object))
+    }
+    _ => {
+      Ok(HttpResponse::NotFound().json(json!({"code": "post_not_found"})))
+    }
+  }
+}
+
+/// An activity's `object` as an id, whether it was sent inline or as a
+/// reference.
+fn object_id(value: &serde_json::Value) -> Option<&str> {
+  value.as_str().or_else(|| value["id"].as_str())
+}
+
+#[post("/activitypub/inbox")]
+async fn receive_activity(
+  req: HttpRequest,
+  body: web::Bytes,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let config = Config::init_from_env().unwrap();
+
+  let activity = match serde_json::from_slice::<serde_json::Value>(&body) {
+    Ok(activity) => activity,
+    Err(_) => {
+      return Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "invalid_activity"})),
+      );
+    }
+  };
+  let actor = match activity["actor"].as_str() {
+    Some(actor) => actor.to_string(),
+    None => {
+      return Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "invalid_activity"})),
+      );
+    }
+  };
+
+  let signature = req
+    .headers()
+    .get("signature")
+    .and_then(|value| value.to_str().ok())
+    .and_then(SignatureHeader::parse);
+  let signature = match signature {
+    Some(signature) => signature,
+    None => {
+      return Ok(
+        HttpResponse::Unauthorized()
+          .json(json!({"code": "invalid_signature"})),
+      );
+    }
+  };
+
+  // The key has to belong to the actor the activity claims to be from,
+  // and be served from the same host.
+  let key_url = signature.key_id.split('#').next().unwrap_or_default();
+  let same_host = match (Url::parse(key_url), Url::parse(&actor)) {
+    (Ok(key_url), Ok(actor_url)) => {
+      key_url.host_str().is_some()
+        && key_url.host_str() == actor_url.host_str()
+    }
+    _ => false,
+  };
+  if !same_host {
+    return Ok(
+      HttpResponse::Unauthorized()
+        .json(json!({"code": "invalid_signature"})),
+    );
+  }
+
+  let key_document = fetch_actor(&state, key_url).await;
+  let public_key = key_document.as_ref().and_then(|document| {
+    let key = &document["publicKey"];
+    if key["id"].as_str() != Some(signature.key_id.as_str())
+      || key["owner"].as_str() != Some(actor.as_str())
+    {
+      return None;
+    }
+    key["publicKeyPem"].as_str().and_then(parse_public_key)
+  });
+
+  let verified = match public_key {
+    Some(public_key) => {
+      verify_request(&req, &body, &signature, public_key)
+    }
+    None => false,
+  };
+  if !verified {
+    return Ok(
+      HttpResponse::Unauthorized()
+        .json(json!({"code": "invalid_signature"})),
+    );
+  }
+
+  match activity["type"].as_str() {
+    Some("Follow") => {
+      if object_id(&activity["object"]) != Some(actor_id(&config).as_str())
+      {
+        return Ok(
+          HttpResponse::BadRequest()
+            .json(json!({"code": "unknown_object"})),
+        );
+      }
+
+      let sender = match key_document {
+        Some(document)
+          if document["id"].as_str() == Some(actor.as_str()) =>
+        {
+          Some(document)
+        }
+        _ => fetch_actor(&state, &actor).await,
+      };
+      let inbox = sender
+        .as_ref()
+        .and_then(|sender| sender["inbox"].as_str())
+        .map(str::to_string);
+      let shared_inbox = sender
+        .as_ref()
+        .and_then(|sender| sender["endpoints"]["sharedInbox"].as_str())
+        .map(str::to_string);
+
+      let inbox = match inbox {
+        Some(inbox) => inbox,
+        None => {
+          return Ok(
+            HttpResponse::BadRequest()
+              .json(json!({"code": "actor_has_no_inbox"})),
+          );
+        }
+      };
+
+      let saved = sqlx::query(
+        "INSERT INTO blog_followers (actor, inbox, shared_inbox) \
+         VALUES ($1, $2, $3) ON CONFLICT (actor) DO UPDATE \
+         SET inbox = EXCLUDED.inbox, shared_inbox = EXCLUDED.shared_inbox",
+      )
+      .bind(&actor)
+      .bind(&inbox)
+      .bind(&shared_inbox)
+      .execute(&state.db)
+      .await;
+
+      if saved.is_err() {
+        return Ok(
+          HttpResponse::InternalServerError()
+            .json(json!({"code": "failed_to_save_follower"})),
+        );
+      }
+
+      let accept_id: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+      let accept = json!({
+        "@context": ACTIVITY_STREAMS,
+        "id": format!("{}/accepts/{}", config.blog_activitypub_url, accept_id),
+        "type": "Accept",
+        "actor": actor_id(&config),
+        "object": activity,
+      });
+
+      tokio::spawn(async move {
+        deliver(&state, &inbox, &accept).await;
+      });
+    }
+    // Only the follower itself can undo its follow.
+    Some("Undo")
+      if activity["object"]["type"].as_str() == Some("Follow")
+        && activity["object"]["actor"].as_str()
+          == Some(actor.as_str()) =>
+    {
+      let _ = sqlx::query("DELETE FROM blog_followers WHERE actor = $1")
+        .bind(&actor)
+        .execute(&state.db)
+        .await;
+    }
+    // Everything else (likes, boosts, replies) is accepted and ignored.
+    _ => {}
+  }
+
+  Ok(HttpResponse::Accepted().finish())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use actix_web::{App, HttpServer};
+  use rsa::RsaPublicKey;
+  use std::sync::Mutex;
+
+  #[test]
+  fn only_public_addresses_are_allowed() {
+    for address in [
+      "93.184.215.14",
+      "2606:4700::1111",
+      "64:ff9b::93.184.215.14",
+      "2002:5db8:d70e::1",
+    ] {
+      assert!(is_public_address(address.parse().unwrap()), "{address}");
+    }
+
+    for address in [
+      "127.0.0.1",
+      "10.1.2.3",
+      "172.16.0.1",
+      "192.168.1.1",
+      "169.254.169.254",
+      "100.64.0.1",
+      "0.0.0.0",
+      "::1",
+      "::",
+      "fd00:ec2::254",
+      "fe80::1",
+      "::ffff:127.0.0.1",
+      "::ffff:10.0.0.1",
+      "::10.0.0.1",
+      "64:ff9b::a9fe:a9fe",
+      "64:ff9b::127.0.0.1",
+      "64:ff9b:1::10.0.0.1",
+      "2002:a00:1::1",
+      "2002:7f00:1::",
+      "2001:0:4136:e378::1",
+    ] {
+      assert!(!is_public_address(address.parse().unwrap()), "{address}");
+    }
+  }
+
+  #[actix_web::test]
+  async fn remote_requests_need_public_https_urls() {
+    for url in [
+      "http://example.com/inbox",
+      "https://127.0.0.1/inbox",
+      "https://localhost/inbox",
+      "https://169.254.169.254/latest/meta-data",
+      "https://10.0.0.1/inbox",
+    ] {
+      let url = Url::parse(url).unwrap();
+      assert!(remote_client(&url).await.is_none(), "{url}");
+    }
+  }
+
+  struct Inbox {
+    public_key: RsaPublicKey,
+    received: Mutex<Vec<(bool, serde_json::Value)>>,
+  }
+
+  async fn stand_in_inbox(
+    req: HttpRequest,
+    body: web::Bytes,
+    inbox: web::Data<Inbox>,
+  ) -> HttpResponse {
+    let verified = req
+      .headers()
+      .get("signature")
+      .and_then(|value| value.to_str().ok())
+      .and_then(SignatureHeader::parse)
+      .is_some_and(|signature| {
+        signature.key_id == "https://blog.example/actor#main-key"
+          && verify_request(
+            &req,
+            &body,
+            &signature,
+            inbox.public_key.clone(),
+          )
+      });
+    let activity = serde_json::from_slice(&body).unwrap_or_default();
+
+    inbox.received.lock().unwrap().push((verified, activity));
+    HttpResponse::Accepted().finish()
+  }
+
+  #[actix_web::test]
+  async fn delivers_signed_activities_to_an_inbox() {
+    let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    let inbox = web::Data::new(Inbox {
+      public_key: key.to_public_key(),
+      received: Mutex::new(Vec::new()),
+    });
+
+    let app_inbox = inbox.clone();
+    let server = HttpServer::new(move || {
+      App::new()
+        .app_data(app_inbox.clone())
+        .route("/inbox", web::post().to(stand_in_inbox))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let url =
+      Url::parse(&format!("http://{}/inbox", server.addrs()[0])).unwrap();
+    let server = server.run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    let activity =
+      json!({"type": "Create", "object": {"type": "Article"}});
+    let delivered = send_activity(
+      &reqwest::Client::new(),
+      &url,
+      key,
+      "https://blog.example/actor#main-key",
+      &activity,
+    )
+    .await;
+    handle.stop(true).await;
+
+    assert!(delivered);
+    assert_eq!(*inbox.received.lock().unwrap(), vec![(true, activity)]);
+  }
+}
@@ -1,76 +1,243 @@
 use actix_multipart::form::MultipartForm;
-use actix_web::{delete, get, http::Error, post, web, HttpResponse};
+use actix_web::{
+  delete, get, http::Error, post, web, HttpMessage, HttpRequest,
+  HttpResponse,
+};
+use envconfig::Envconfig;
 use serde_json::json;
 use sha1::{Digest, Sha1};
 
-use crate::{structs::blog::{BlogAsset, BlogAssetUpload}, ServerState};
+use crate::{
+  config::Config,
+  connectivity::s3::S3Manager,
+  helpers::images::{decode_image, generate_variants, VARIANT_FORMATS},
+  services::{
+    blog::posts::find_editable_post,
+    uploads::helpers::{detect_type, AssetType},
+  },
+  structs::blog::{BlogAdminUser, BlogAsset, BlogAssetUpload},
+  ServerState,
+};
 
-#[post("/posts/{id}/assets")]
-async fn upload_asset_for_post(
-  MultipartForm(form): MultipartForm<BlogAssetUpload>,
-  state: web::Data<ServerState>,
-  post_id: web::Path<String>,
-) -> Result<HttpResponse, Error> {
-  let s3 = &state.s3;
+/// Public URL of an asset's original upload.
+pub(crate) fn asset_url(asset: &BlogAsset) -> String {
+  let config = Config::init_from_env().unwrap();
+  format!(
+    "{}/{}.{}",
+    config.blog_assets_url.trim_end_matches('/'),
+    asset.hash,
+    asset.file_type
+  )
+}
 
-  let file = &mut form.files.first().unwrap();
+/// Asset details for API responses. Images with variants get a `srcset`
+/// string per format, ready to drop into `<source>` elements.
+pub(crate) fn asset_json(asset: &BlogAsset) -> serde_json::Value {
+  let config = Config::init_from_env().unwrap();
+  let base_url = config.blog_assets_url.trim_end_matches('/');
+  let hash = &asset.hash;
 
-  let type_match = crate::services::uploads::helpers::is_allowed_type(
-    file,
-    "files".to_string(),
-  )
-  .1;
+  let srcset: serde_json::Map<String, serde_json::Value> = VARIANT_FORMATS
+    .iter()
+    .filter(|_| !asset.variants.is_empty())
+    .map(|(ext, _)| {
+      let set = asset
+        .variants
+        .iter()
+        .map(|width| format!("{base_url}/{hash}/{width}.{ext} {width}w"))
+        .collect::<Vec<String>>()
+        .join(", ");
+      (ext.to_string(), json!(set))
+    })
+    .collect();
+
+  json!({
+    "hash": asset.hash,
+    "post_id": asset.post_id,
+    "file_type": asset.file_type,
+    "file_size": asset.file_size,
+    "upload_date": asset.upload_date,
+    "width": asset.width,
+    "height": asset.height,
+    "url": asset_url(asset),
+    "variants": asset.variants,
+    "srcset": srcset,
+  })
+}
+
+/// Remove an asset's original and variants from S3. Returns whether the
+/// original was deleted; variants are cleaned up on a best-effort basis.
+pub(crate) async fn delete_asset_objects(
+  s3: &S3Manager,
+  asset: &BlogAsset,
+) -> bool {
+  let hash = &asset.hash;
+  let file_type = &asset.file_type;
+
+  let deleted = s3
+    .cdn_bucket
+    .delete_object(format!("blog/assets/{hash}.{file_type}"))
+    .await
+    .is_ok_and(|response| response.status_code() == 204);
+
+  for width in &asset.variants {
+    for (ext, _) in VARIANT_FORMATS {
+      let _ = s3
+        .cdn_bucket
+        .delete_object(format!("blog/assets/{hash}/{width}.{ext}"))
+        .await;
+    }
+  }
+
+  deleted
+}
+
+/// Store an asset for a post: the original under `blog/assets/`, resized
+/// variants for images, and its `blog_assets` row. Errors are response
+/// codes.
+pub(crate) async fn store_asset(
+  state: &ServerState,
+  post_id: &str,
+  data: bytes::Bytes,
+) -> Result<BlogAsset, &'static str> {
+  let s3 = &state.s3;
+
+  let type_match = detect_type(&data);
 
   let mut hasher = Sha1::new();
-  hasher.update(&file.data);
+  hasher.update(&data);
   let result = hasher.finalize();
 
   let hash = hex::encode(result);
-  let ext = type_match.ext;
-  let path = format!("blog/assets/{hash}.{ext}");
+  let path = format!("blog/assets/{hash}.{}", type_match.ext);
 
-  let response = s3
+  let uploaded = s3
     .cdn_bucket
-    .put_object_with_content_type(&path, &file.data, &type_match.mime)
+    .put_object_with_content_type(&path, &data, &type_match.mime)
     .await
-    .unwrap();
+    .is_ok_and(|response| response.status_code() == 200);
 
-  if response.status_code() != 200 {
-    return Ok(
-      HttpResponse::BadRequest()
-        .json(json!({"code": "failed_upload_to_s3"})),
-    );
+  if !uploaded {
+    return Err("failed_upload_to_s3");
   }
 
-  let size = file.data.len() as i32;
+  let size = data.len() as i32;
+  record_asset(state, post_id, hash, type_match, size, Some(data)).await
+}
+
+/// Resized variants and the `blog_assets` row for an original already
+/// stored at `blog/assets/{hash}.{ext}`. Variants need the original's
+/// contents, so images passed without `data` go without. Errors are
+/// response codes.
+pub(crate) async fn record_asset(
+  state: &ServerState,
+  post_id: &str,
+  hash: String,
+  type_match: AssetType,
+  size: i32,
+  data: Option<bytes::Bytes>,
+) -> Result<BlogAsset, &'static str> {
+  let s3 = &state.s3;
+  let ext = type_match.ext;
+
+  // Images also get resized variants so posts can serve a srcset instead of
+  // the original.
+  let processed = match data {
+    Some(data) if type_match.mime.starts_with("image/") => {
+      web::block(move || {
+        decode_image(&data).map(|image| {
+          (image.width(), image.height(), generate_variants(&image))
+        })
+      })
+      .await
+      .ok()
+      .flatten()
+    }
+    _ => None,
+  };
+
+  let mut dimensions = None;
+  let mut variant_widths: Vec<i32> = Vec::new();
+
+  if let Some((width, height, variants)) = processed {
+    dimensions = Some((width as i32, height as i32));
+    let mut failed_widths = Vec::new();
+
+    for variant in &variants {
+      let variant_path =
+        format!("blog/assets/{hash}/{}.{}", variant.width, variant.ext);
+      let uploaded = s3
+        .cdn_bucket
+        .put_object_with_content_type(
+          &variant_path,
+          &variant.data,
+          variant.mime,
+        )
+        .await
+        .is_ok_and(|response| response.status_code() == 200);
+
+      let width = variant.width as i32;
+      if !uploaded {
+        failed_widths.push(width);
+      } else if !variant_widths.contains(&width) {
+        variant_widths.push(width);
+      }
+    }
+
+    // Only advertise widths that exist in every format.
+    variant_widths.retain(|width| !failed_widths.contains(width));
+  }
 
   let asset = sqlx::query_as::<_, BlogAsset>(
-    "INSERT INTO blog_assets (hash, post_id, file_type, file_size) \
-     VALUES ($1, $2, $3, $4) RETURNING *",
+    "INSERT INTO blog_assets \
+       (hash, post_id, file_type, file_size, width, height, variants) \
+     VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
   )
-  .bind(hash.clone())
-  .bind(post_id.to_string())
-  .bind(ext.clone())
+  .bind(hash)
+  .bind(post_id)
+  .bind(ext)
   .bind(size)
+  .bind(dimensions.map(|(width, _)| width))
+  .bind(dimensions.map(|(_, height)| height))
+  .bind(&variant_widths)
   .fetch_one(&state.db)
   .await;
 
   match asset {
-    Err(sqlx::Error::Database(error)) if error.is_unique_violation() => Ok(
-      HttpResponse::BadRequest()
-        .json(json!({"code": "asset_already_exists"})),
-    ),
-    Err(_) => Ok(
-      HttpResponse::BadRequest()
-        .json(json!({"code": "failed_to_create_asset"})),
-    ),
-    Ok(asset) => Ok(HttpResponse::Ok().json(
-      json!({"asset": { "hash": hash, "post_id": post_id.to_string(), "file_type": ext, "file_size": size, "upload_date": asset.upload_date }}),
-    )),
+    Ok(asset) => Ok(asset),
+    Err(sqlx::Error::Database(error)) if error.is_unique_violation() => {
+      Err("asset_already_exists")
+    }
+    Err(_) => Err("failed_to_create_asset"),
   }
 }
 
-#[get("/posts/{id}/assets")]
+#[post("/{id}/assets")]
+async fn upload_asset_for_post(
+  req: HttpRequest,
+  MultipartForm(form): MultipartForm<BlogAssetUpload>,
+  state: web::Data<ServerState>,
+  post_id: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+  if let Err(response) = find_editable_post(&state, &user, &post_id).await
+  {
+    return Ok(response);
+  }
+
+  let file = form.files.first().unwrap();
+
+  match store_asset(&state, &post_id, file.data.clone()).await {
+    Ok(asset) => {
+      Ok(HttpResponse::Ok().json(json!({"asset": asset_json(&asset)})))
+    }
+    Err(code) => {
+      Ok(HttpResponse::BadRequest().json(json!({"code": code})))
+    }
+  }
+}
+
+#[get("/{id}/assets")]
 async fn get_assets_for_post(
   post_id: web::Path<String>,
   state: web::Data<ServerState>,
@@ -83,30 +250,26 @@ async fn get_assets_for_post(
   .await
   .unwrap();
 
-  let assets: Vec<serde_json::Value> = assets
-    .iter()
-    .map(|post| {
-      json!({
-        "hash": post.hash,
-        "post_id": post.post_id,
-        "file_type": post.file_type,
-        "file_size": post.file_size,
-        "upload_date": post.upload_date,
-      })
-    })
-    .collect();
+  let assets: Vec<serde_json::Value> =
+    assets.iter().map(asset_json).collect();
 
   Ok(HttpResponse::Ok().json(json!({"assets": assets})))
 }
 
-#[delete("/posts/{id}/assets/{hash}")]
+#[delete("/{id}/assets/{hash}")]
 async fn delete_asset_for_post(
+  req: HttpRequest,
   state: web::Data<ServerState>,
   params: web::Path<Vec<String>>,
 ) -> Result<HttpResponse, Error> {
   let post_id = params.first().unwrap();
   let hash = params.last().unwrap();
 
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+  if let Err(response) = find_editable_post(&state, &user, post_id).await {
+    return Ok(response);
+  }
+
   let s3 = &state.s3;
 
   let asset = sqlx::query_as::<_, BlogAsset>(
@@ -119,14 +282,7 @@ async fn delete_asset_for_post(
 
   match asset {
     Ok(Some(asset)) => {
-      let file_type = asset.file_type;
-
-      let res = s3
-        .cdn_bucket
-        .delete_object(format!("blog/assets/{hash}.{file_type}"))
-        .await;
-
-      if res.unwrap().status_code() != 204 {
+      if !delete_asset_objects(s3, &asset).await {
         return Ok(HttpResponse::BadRequest().json(json!({
           "code": "failed_to_delete_from_s3"
         })));
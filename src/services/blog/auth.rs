@@ -1,4 +1,12 @@
 use crate::{
+  helpers::{
+    client_ip::client_ip,
+    limiter::{locked_out_response, AuthLimiter},
+  },
+  services::blog::{
+    sessions::{create_session, revoke_session, revoke_sessions},
+    two_factor::{create_login_challenge, LOGIN_CHALLENGE_TTL},
+  },
   structs::blog::{
     BlogAdminIntSession, BlogAdminUser, BlogLoginRequest, BlogUserMutate,
     BlogUserPasswordChange,
@@ -7,6 +15,7 @@ use crate::{
 };
 
 use argon2::{self, Config};
+use lazy_static::lazy_static;
 use rand::{distributions::Alphanumeric, Rng};
 
 use actix_web::{
@@ -16,11 +25,42 @@ use actix_web::{
   web::{self, Json},
   HttpMessage, HttpRequest, HttpResponse,
 };
-use redis::aio::ConnectionManager;
 use serde_json::json;
 
+lazy_static! {
+  /// Verified against when a login names an unknown user.
+  static ref DUMMY_HASH: String = hash_password("dummy-password");
+}
+
+/// Hash a password with a fresh random salt for storage in
+/// `blog_admin_users.password`.
+pub(crate) fn hash_password(password: &str) -> String {
+  let password_salt: String = rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(48)
+    .map(char::from)
+    .collect();
+
+  let config = Config::default();
+  argon2::hash_encoded(
+    password.as_bytes(),
+    password_salt.as_bytes(),
+    &config,
+  )
+  .unwrap()
+}
+
+/// Body returned once a login has fully succeeded.
+pub(crate) fn session_response(
+  user: &BlogAdminUser,
+  token: &str,
+) -> serde_json::Value {
+  json!({ "user": { "id": user.id, "username": user.username, "display_name": user.display_name, "role": user.role }, "session": { "token": token } })
+}
+
 #[post("/auth")]
 async fn login(
+  req: HttpRequest,
   body: web::Json<BlogLoginRequest>,
   state: web::Data<ServerState>,
 ) -> Result<HttpResponse, Error> {
@@ -28,6 +68,14 @@ async fn login(
 
   let valkey = &mut state.valkey.clone();
 
+  let limiter = AuthLimiter::new("blog")
+    .ip(client_ip(&req).as_deref())
+    .username(&username);
+
+  if let Some(retry_after) = limiter.locked_for(valkey).await {
+    return Ok(locked_out_response(retry_after));
+  }
+
   let user_lookup = sqlx::query_as::<_, BlogAdminUser>(
     "SELECT * FROM blog_admin_users WHERE username = $1 LIMIT 1",
   )
@@ -35,46 +83,79 @@ async fn login(
   .fetch_optional(&state.db)
   .await;
 
-  match user_lookup {
-    Ok(Some(user)) => {
-      let password = body.password.as_bytes();
-      let valid = argon2::verify_encoded(&user.password, password).unwrap();
+  let user = match user_lookup {
+    Ok(user) => user,
+    Err(_) => {
+      return Ok(
+        HttpResponse::Unauthorized()
+          .json(json!({"code": "failed_to_lookup_user"})),
+      );
+    }
+  };
 
-      if !valid {
-        return Ok(
-          HttpResponse::Unauthorized()
-            .json(json!({"code": "invalid_authentication"})),
-        );
-      }
+  // Unknown usernames still pay for a hash so response times don't reveal
+  // which accounts exist.
+  let password = body.password.as_bytes();
+  let user = match user {
+    Some(user)
+      if argon2::verify_encoded(&user.password, password).unwrap_or(false)
+        && !user.disabled =>
+    {
+      Some(user)
+    }
+    Some(_) => None,
+    None => {
+      let _ = argon2::verify_encoded(&DUMMY_HASH, password);
+      None
+    }
+  };
 
-      let session_token: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(96)
-        .map(char::from)
-        .collect();
+  let user = match user {
+    Some(user) => user,
+    None => {
+      limiter.record_failure(valkey).await;
+      return Ok(
+        HttpResponse::Unauthorized()
+          .json(json!({"code": "invalid_authentication"})),
+      );
+    }
+  };
 
-      let _ = redis::cmd("SET")
-        .arg(format!("blog_admin_session/{}", session_token))
-        .arg(json!({"user_id": user.id}).to_string())
-        .query_async::<ConnectionManager, String>(&mut valkey.cm)
-        .await;
+  // Enrolled users get a short-lived challenge instead of a session,
+  // completed at `/auth/challenge` with a TOTP or recovery code. Their
+  // failed attempts are only cleared once that step succeeds.
+  if user.totp_enabled {
+    return match create_login_challenge(valkey, &user.id).await {
+      Some(challenge) => Ok(HttpResponse::Ok().json(json!({
+        "challenge": {
+          "token": challenge,
+          "expires_in": LOGIN_CHALLENGE_TTL,
+          "methods": ["totp", "recovery_code"],
+        }
+      }))),
+      None => Ok(
+        HttpResponse::InternalServerError()
+          .json(json!({"code": "failed_to_create_challenge"})),
+      ),
+    };
+  }
 
-      let response = json!({ "user": { "id": user.id, "username": user.username, "display_name": user.display_name, }, "session": { "token": session_token } });
+  limiter.record_success(valkey).await;
 
-      Ok(HttpResponse::Ok().json(response))
+  let session_token = match create_session(valkey, &user.id, &req).await {
+    Some(token) => token,
+    None => {
+      return Ok(
+        HttpResponse::InternalServerError()
+          .json(json!({"code": "failed_to_create_session"})),
+      );
     }
-    Ok(None) => Ok(
-      HttpResponse::Unauthorized()
-        .json(json!({"code": "invalid_username"})),
-    ),
-    Err(_) => Ok(
-      HttpResponse::Unauthorized()
-        .json(json!({"code": "failed_to_lookup_user"})),
-    ),
-  }
+  };
+
+  Ok(HttpResponse::Ok().json(session_response(&user, &session_token)))
 }
 
-#[get("/me")]
+#[get("")]
 async fn get_user(req: HttpRequest) -> Result<HttpResponse, Error> {
   let exts = req.extensions_mut();
   let user = exts.get::<BlogAdminUser>().unwrap();
@@ -84,11 +165,12 @@ async fn get_user(req: HttpRequest) -> Result<HttpResponse, Error> {
           "id": user.id,
           "username": user.username,
           "display_name": user.display_name,
+          "role": user.role,
       }
   })))
 }
 
-#[patch("/me")]
+#[patch("")]
 async fn update_user(
   req: HttpRequest,
   state: web::Data<ServerState>,
@@ -123,11 +205,12 @@ async fn update_user(
           "id": user_record.id,
           "username": user_record.username,
           "display_name": user_record.display_name,
+          "role": user_record.role,
       }
   })))
 }
 
-#[patch("/auth")]
+#[patch("")]
 async fn change_password(
   req: HttpRequest,
   state: web::Data<ServerState>,
@@ -135,6 +218,7 @@ async fn change_password(
 ) -> Result<HttpResponse, Error> {
   let exts = req.extensions_mut();
   let user = exts.get::<BlogAdminUser>().unwrap();
+  let session = exts.get::<BlogAdminIntSession>().unwrap();
 
   if body.password == body.new_password {
     return Ok(
@@ -153,19 +237,7 @@ async fn change_password(
     );
   }
 
-  let password_salt: String = rand::thread_rng()
-    .sample_iter(&Alphanumeric)
-    .take(48)
-    .map(char::from)
-    .collect();
-
-  let config = Config::default();
-  let new_hash = argon2::hash_encoded(
-    body.new_password.as_bytes(),
-    password_salt.as_bytes(),
-    &config,
-  )
-  .unwrap();
+  let new_hash = hash_password(&body.new_password);
 
   let _ = sqlx::query(
     "UPDATE blog_admin_users SET password = $1 WHERE id = $2",
@@ -176,10 +248,14 @@ async fn change_password(
   .await
   .unwrap();
 
+  // Anyone holding an older session has to sign in with the new password.
+  let valkey = &mut state.valkey.clone();
+  revoke_sessions(valkey, &user.id, Some(&session.token)).await;
+
   Ok(HttpResponse::NoContent().finish())
 }
 
-#[delete("/auth")]
+#[delete("")]
 async fn logout(
   req: HttpRequest,
   state: web::Data<ServerState>,
@@ -189,10 +265,7 @@ async fn logout(
   let exts = req.extensions_mut();
   let session = exts.get::<BlogAdminIntSession>().unwrap();
 
-  let _ = redis::cmd("DEL")
-    .arg(format!("blog_admin_session/{}", session.token))
-    .query_async::<ConnectionManager, String>(&mut valkey.cm)
-    .await;
+  revoke_session(valkey, &session.user_id, &session.token).await;
 
   Ok(HttpResponse::NoContent().finish())
 }
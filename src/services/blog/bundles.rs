@@ -0,0 +1,414 @@
+use actix_multipart::form::MultipartForm;
+use actix_web::{
+  get, http::header, http::Error, post, web, HttpMessage, HttpRequest,
+  HttpResponse,
+};
+use chrono::Utc;
+use serde_json::json;
+use std::{
+  collections::HashMap,
+  io::{Cursor, Read, Write},
+};
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+use crate::{
+  services::blog::{
+    activitypub::deliver_post, assets::store_asset,
+    posts::record_slug_change,
+  },
+  structs::blog::{
+    BlogAdminUser, BlogAsset, BlogBundleUpload, BlogPost,
+    BlogPostFrontMatter, POST_VISIBILITIES, RESERVED_POST_SLUGS,
+  },
+  ServerState,
+};
+
+/// Upper bound on the uncompressed size of an imported bundle, so a small
+/// upload can't expand into something that exhausts memory. Also the
+/// upload limit of the import route, since exports can be this large.
+pub(crate) const MAX_BUNDLE_SIZE: u64 = 256 * 1024 * 1024;
+
+fn render_markdown(post: &BlogPost, assets: &[&BlogAsset]) -> String {
+  let front_matter = BlogPostFrontMatter {
+    id: Some(post.id.clone()),
+    slug: post.slug.clone(),
+    title: post.title.clone(),
+    description: post.description.clone(),
+    image: post.image.clone(),
+    tags: post.tags.clone(),
+    visibility: Some(post.visibility.clone()),
+    created_at: Some(post.created_at),
+    published_at: post.published_at,
+    assets: assets
+      .iter()
+      .map(|asset| format!("{}.{}", asset.hash, asset.file_type))
+      .collect(),
+  };
+
+  format!(
+    "---\n{}---\n\n{}\n",
+    serde_yaml::to_string(&front_matter).unwrap_or_default(),
+    post.body.as_deref().unwrap_or_default().trim_end()
+  )
+}
+
+fn parse_markdown(
+  contents: &str,
+) -> Option<(BlogPostFrontMatter, String)> {
+  let contents = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+  let rest = contents
+    .strip_prefix("---\n")
+    .or_else(|| contents.strip_prefix("---\r\n"))?;
+
+  let (yaml, body) = match rest.find("\n---") {
+    Some(end) => {
+      let after = &rest[end + 4..];
+      (&rest[..end], after.trim_start_matches(['\r', '\n']))
+    }
+    None => return None,
+  };
+
+  let front_matter =
+    serde_yaml::from_str::<BlogPostFrontMatter>(yaml).ok()?;
+
+  Some((front_matter, body.trim_end().to_string()))
+}
+
+#[get("")]
+async fn export_posts(
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let posts = sqlx::query_as::<_, BlogPost>(
+    "SELECT * FROM blog_posts WHERE visibility <> 'deleted' \
+     ORDER BY created_at ASC",
+  )
+  .fetch_all(&state.db)
+  .await;
+
+  let posts = match posts {
+    Ok(posts) => posts,
+    Err(_) => {
+      return Ok(
+        HttpResponse::InternalServerError()
+          .json(json!({"code": "failed_to_lookup_posts"})),
+      );
+    }
+  };
+
+  let post_ids: Vec<String> =
+    posts.iter().map(|post| post.id.clone()).collect();
+  let assets = sqlx::query_as::<_, BlogAsset>(
+    "SELECT * FROM blog_assets WHERE post_id = ANY($1) \
+     ORDER BY upload_date ASC",
+  )
+  .bind(&post_ids)
+  .fetch_all(&state.db)
+  .await
+  .unwrap_or_default();
+
+  let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+
+  for post in &posts {
+    let post_assets: Vec<&BlogAsset> = assets
+      .iter()
+      .filter(|asset| asset.post_id == post.id)
+      .collect();
+
+    files.push((
+      format!("posts/{}.md", post.slug),
+      render_markdown(post, &post_assets).into_bytes(),
+    ));
+  }
+
+  for asset in &assets {
+    let name = format!("{}.{}", asset.hash, asset.file_type);
+    let object = state
+      .s3
+      .cdn_bucket
+      .get_object(format!("blog/assets/{name}"))
+      .await;
+
+    match object {
+      Ok(object) if object.status_code() == 200 => {
+        files.push((format!("assets/{name}"), object.bytes().to_vec()));
+      }
+      _ => {
+        tracing::warn!("Skipping missing blog asset {} in export", name)
+      }
+    }
+  }
+
+  let bundle = web::block(move || -> zip::result::ZipResult<Vec<u8>> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+
+    for (name, data) in files {
+      writer.start_file(name, options)?;
+      writer.write_all(&data)?;
+    }
+
+    Ok(writer.finish()?.into_inner())
+  })
+  .await;
+
+  match bundle {
+    Ok(Ok(bundle)) => Ok(
+      HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header((
+          header::CONTENT_DISPOSITION,
+          format!(
+            "attachment; filename=\"blog-{}.zip\"",
+            Utc::now().format("%Y%m%d")
+          ),
+        ))
+        .body(bundle),
+    ),
+    _ => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_create_bundle"})),
+    ),
+  }
+}
+
+/// Markdown files and assets read out of an uploaded bundle.
+type BundleContents = (Vec<(String, String)>, HashMap<String, Vec<u8>>);
+
+fn read_bundle(data: &[u8]) -> Result<BundleContents, &'static str> {
+  let mut archive =
+    ZipArchive::new(Cursor::new(data)).map_err(|_| "invalid_bundle")?;
+
+  let mut markdown = Vec::new();
+  let mut assets = HashMap::new();
+  let mut total_size = 0;
+
+  for index in 0..archive.len() {
+    let mut file =
+      archive.by_index(index).map_err(|_| "invalid_bundle")?;
+    if file.is_dir() {
+      continue;
+    }
+
+    // Count what is actually inflated rather than trusting the sizes the
+    // archive declares.
+    let name = file.name().to_string();
+    let mut contents = Vec::new();
+    (&mut file)
+      .take(MAX_BUNDLE_SIZE - total_size + 1)
+      .read_to_end(&mut contents)
+      .map_err(|_| "invalid_bundle")?;
+
+    total_size += contents.len() as u64;
+    if total_size > MAX_BUNDLE_SIZE {
+      return Err("bundle_too_large");
+    }
+
+    if let Some(asset) = name.strip_prefix("assets/") {
+      assets.insert(asset.to_string(), contents);
+    } else if name.ends_with(".md") {
+      markdown
+        .push((name, String::from_utf8_lossy(&contents).to_string()));
+    }
+  }
+
+  Ok((markdown, assets))
+}
+
+/// Find the post a bundle entry refers to: by id when it still exists,
+/// otherwise by slug.
+async fn find_import_target(
+  state: &ServerState,
+  front_matter: &BlogPostFrontMatter,
+) -> Option<BlogPost> {
+  if let Some(id) = &front_matter.id {
+    let post = sqlx::query_as::<_, BlogPost>(
+      "SELECT * FROM blog_posts WHERE id = $1 LIMIT 1",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .ok()
+    .flatten();
+
+    if post.is_some() {
+      return post;
+    }
+  }
+
+  sqlx::query_as::<_, BlogPost>(
+    "SELECT * FROM blog_posts WHERE slug = $1 LIMIT 1",
+  )
+  .bind(&front_matter.slug)
+  .fetch_optional(&state.db)
+  .await
+  .ok()
+  .flatten()
+}
+
+#[post("")]
+async fn import_posts(
+  req: HttpRequest,
+  MultipartForm(form): MultipartForm<BlogBundleUpload>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+
+  let data = form.file.data;
+  let contents = web::block(move || read_bundle(&data)).await;
+
+  let (markdown, mut assets) = match contents {
+    Ok(Ok(contents)) => contents,
+    Ok(Err(code)) => {
+      return Ok(HttpResponse::BadRequest().json(json!({"code": code})));
+    }
+    Err(_) => {
+      return Ok(
+        HttpResponse::InternalServerError()
+          .json(json!({"code": "failed_to_read_bundle"})),
+      );
+    }
+  };
+
+  let mut created = 0;
+  let mut updated = 0;
+  let mut uploaded = 0;
+  let mut errors: Vec<serde_json::Value> = Vec::new();
+
+  for (file, contents) in markdown {
+    let (front_matter, body) = match parse_markdown(&contents) {
+      Some(parsed) => parsed,
+      None => {
+        errors.push(json!({"file": file, "code": "invalid_front_matter"}));
+        continue;
+      }
+    };
+
+    if front_matter
+      .visibility
+      .as_deref()
+      .is_some_and(|visibility| !POST_VISIBILITIES.contains(&visibility))
+    {
+      errors.push(json!({"file": file, "code": "invalid_visibility"}));
+      continue;
+    }
+
+    if RESERVED_POST_SLUGS.contains(&front_matter.slug.as_str()) {
+      errors.push(json!({"file": file, "code": "reserved_slug"}));
+      continue;
+    }
+
+    let existing = find_import_target(&state, &front_matter).await;
+    let previous_slug = existing.as_ref().map(|post| post.slug.clone());
+    // Posts that arrive with a publish date went out when they were first
+    // published, so only ones published by this import are federated.
+    let was_published = front_matter.published_at.is_some()
+      || existing
+        .as_ref()
+        .is_some_and(|post| post.published_at.is_some());
+
+    // A trashed post brought back without a visibility comes back as a
+    // draft, rather than staying `deleted` with no date to be purged at.
+    let post = match existing {
+      Some(existing) => sqlx::query_as::<_, BlogPost>(
+        "UPDATE blog_posts SET \
+             slug = $1, title = $2, description = $3, image = $4, \
+             tags = $5, visibility = COALESCE($6, \
+               CASE WHEN visibility = 'deleted' THEN 'draft' \
+                 ELSE visibility END), \
+             body = $7, \
+             published_at = COALESCE($8, published_at, \
+               CASE WHEN COALESCE($6, visibility) = 'public' THEN now() END), \
+             deleted_at = NULL \
+           WHERE id = $9 RETURNING *",
+      )
+      .bind(&front_matter.slug)
+      .bind(&front_matter.title)
+      .bind(&front_matter.description)
+      .bind(&front_matter.image)
+      .bind(&front_matter.tags)
+      .bind(&front_matter.visibility)
+      .bind(&body)
+      .bind(front_matter.published_at)
+      .bind(&existing.id)
+      .fetch_one(&state.db)
+      .await
+      .inspect(|_| updated += 1),
+      None => sqlx::query_as::<_, BlogPost>(
+        "INSERT INTO blog_posts \
+             (slug, title, description, image, tags, visibility, body, \
+              created_at, published_at, author_id) \
+           VALUES ($1, $2, $3, $4, $5, COALESCE($6, 'draft'), $7, \
+             COALESCE($8, now()), \
+             COALESCE($9, CASE WHEN $6 = 'public' THEN now() END), $10) \
+           RETURNING *",
+      )
+      .bind(&front_matter.slug)
+      .bind(&front_matter.title)
+      .bind(&front_matter.description)
+      .bind(&front_matter.image)
+      .bind(&front_matter.tags)
+      .bind(&front_matter.visibility)
+      .bind(&body)
+      .bind(front_matter.created_at)
+      .bind(front_matter.published_at)
+      .bind(&user.id)
+      .fetch_one(&state.db)
+      .await
+      .inspect(|_| created += 1),
+    };
+
+    let post = match post {
+      Ok(post) => post,
+      Err(_) => {
+        errors.push(json!({"file": file, "code": "failed_to_save_post"}));
+        continue;
+      }
+    };
+
+    if let Some(previous_slug) = &previous_slug {
+      record_slug_change(&state, &post.id, previous_slug, &post.slug)
+        .await;
+    }
+
+    if !was_published && post.visibility == "public" {
+      tokio::spawn(deliver_post(web::Data::clone(&state), post.clone()));
+    }
+
+    for name in &front_matter.assets {
+      // Assets are named after their hash, so ones already stored (most
+      // likely by an earlier import of this bundle) can be skipped before
+      // paying for variant generation again.
+      let hash = name.split('.').next().unwrap_or_default();
+      let exists =
+        sqlx::query("SELECT 1 FROM blog_assets WHERE hash = $1")
+          .bind(hash)
+          .fetch_optional(&state.db)
+          .await
+          .is_ok_and(|row| row.is_some());
+      if exists {
+        continue;
+      }
+
+      let data = match assets.remove(name) {
+        Some(data) => data,
+        None => {
+          errors
+            .push(json!({"file": name, "code": "asset_not_in_bundle"}));
+          continue;
+        }
+      };
+
+      match store_asset(&state, &post.id, data.into()).await {
+        Ok(_) => uploaded += 1,
+        Err(code) => errors.push(json!({"file": name, "code": code})),
+      }
+    }
+  }
+
+  Ok(HttpResponse::Ok().json(json!({
+    "created": created,
+    "updated": updated,
+    "assets": uploaded,
+    "errors": errors,
+  })))
+}
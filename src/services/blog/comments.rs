@@ -0,0 +1,498 @@
+use actix_web::{
+  delete, get, http::header, http::Error, post, web, HttpMessage,
+  HttpRequest, HttpResponse,
+};
+use redis::aio::ConnectionManager;
+use serde_json::json;
+
+use crate::{
+  connectivity::valkey::ValkeyManager,
+  helpers::client_ip::client_ip,
+  structs::blog::{
+    BlogAdminUser, BlogComment, BlogCommentCreate, BlogCommentsQuery,
+    BlogPost,
+  },
+  ServerState,
+};
+
+/// Comments a single IP can leave inside `COMMENT_RATE_WINDOW`.
+const COMMENT_RATE_LIMIT: u64 = 5;
+const COMMENT_RATE_WINDOW: u64 = 10 * 60;
+
+const MAX_AUTHOR_NAME_LENGTH: usize = 100;
+const MAX_AUTHOR_EMAIL_LENGTH: usize = 254;
+const MAX_BODY_LENGTH: usize = 5000;
+
+/// Nest approved comments under their parents. Replies to comments that
+/// aren't approved are left out along with them.
+fn thread_comments(
+  comments: &[BlogComment],
+  parent_id: Option<&str>,
+) -> Vec<serde_json::Value> {
+  comments
+    .iter()
+    .filter(|comment| comment.parent_id.as_deref() == parent_id)
+    .map(|comment| {
+      json!({
+        "id": comment.id,
+        "author_name": comment.author_name,
+        "body": comment.body,
+        "created_at": comment.created_at,
+        "replies": thread_comments(comments, Some(&comment.id)),
+      })
+    })
+    .collect()
+}
+
+/// Approved comments for a post, threaded for display.
+pub(crate) async fn approved_comments(
+  state: &ServerState,
+  post_id: &str,
+) -> Vec<serde_json::Value> {
+  let comments = sqlx::query_as::<_, BlogComment>(
+    "SELECT * FROM blog_comments WHERE post_id = $1 AND status = 'approved' \
+     ORDER BY created_at ASC",
+  )
+  .bind(post_id)
+  .fetch_all(&state.db)
+  .await
+  .unwrap_or_default();
+
+  thread_comments(&comments, None)
+}
+
+/// A submitted comment's author name, email and body, trimmed, or `None`
+/// if any of them is missing, too long or malformed. A blank email counts
+/// as none given.
+fn clean_comment(
+  comment: &BlogCommentCreate,
+) -> Option<(&str, Option<&str>, &str)> {
+  let author_name = comment.author_name.trim();
+  let author_email = comment
+    .author_email
+    .as_deref()
+    .map(str::trim)
+    .filter(|email| !email.is_empty());
+  let body = comment.body.trim();
+
+  if author_name.is_empty()
+    || body.is_empty()
+    || author_name.chars().count() > MAX_AUTHOR_NAME_LENGTH
+    || body.chars().count() > MAX_BODY_LENGTH
+    || author_email.is_some_and(|email| {
+      email.len() > MAX_AUTHOR_EMAIL_LENGTH || !email.contains('@')
+    })
+  {
+    return None;
+  }
+
+  Some((author_name, author_email, body))
+}
+
+fn comment_json(comment: &BlogComment) -> serde_json::Value {
+  json!({
+    "id": comment.id,
+    "post_id": comment.post_id,
+    "parent_id": comment.parent_id,
+    "author_name": comment.author_name,
+    "author_email": comment.author_email,
+    "body": comment.body,
+    "status": comment.status,
+    "ip": comment.ip,
+    "user_agent": comment.user_agent,
+    "created_at": comment.created_at,
+    "moderated_at": comment.moderated_at,
+    "moderated_by": comment.moderated_by,
+  })
+}
+
+async fn find_public_post(
+  state: &ServerState,
+  id: &str,
+) -> Option<BlogPost> {
+  sqlx::query_as::<_, BlogPost>(
+    "SELECT * FROM blog_posts WHERE id = $1 AND visibility = 'public' LIMIT 1",
+  )
+  .bind(id)
+  .fetch_optional(&state.db)
+  .await
+  .ok()
+  .flatten()
+}
+
+/// Count a comment against the caller's IP, returning false once they are
+/// over the limit for the current window.
+async fn within_comment_rate(
+  valkey: &mut ValkeyManager,
+  ip: &str,
+) -> bool {
+  let key = format!("blog_comment_rate/{}", ip);
+
+  let count = redis::cmd("INCR")
+    .arg(&key)
+    .query_async::<ConnectionManager, u64>(&mut valkey.cm)
+    .await
+    .unwrap_or(0);
+
+  if count == 1 {
+    let _ = redis::cmd("EXPIRE")
+      .arg(&key)
+      .arg(COMMENT_RATE_WINDOW)
+      .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+      .await;
+  }
+
+  count <= COMMENT_RATE_LIMIT
+}
+
+#[get("/posts/{id}/comments")]
+async fn get_comments(
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  if find_public_post(&state, &id).await.is_none() {
+    return Ok(
+      HttpResponse::NotFound().json(json!({"code": "post_not_found"})),
+    );
+  }
+
+  let comments = approved_comments(&state, &id).await;
+
+  Ok(HttpResponse::Ok().json(json!({"comments": comments})))
+}
+
+#[post("/posts/{id}/comments")]
+async fn create_comment(
+  req: HttpRequest,
+  id: web::Path<String>,
+  body: web::Json<BlogCommentCreate>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let pending = json!({"comment": {"status": "pending"}});
+
+  // Bots get the same answer as a real submission so they have nothing to
+  // adapt to, but nothing is stored.
+  if body
+    .website
+    .as_deref()
+    .is_some_and(|value| !value.is_empty())
+  {
+    return Ok(HttpResponse::Accepted().json(pending));
+  }
+
+  let (author_name, author_email, comment_body) =
+    match clean_comment(&body) {
+      Some(comment) => comment,
+      None => {
+        return Ok(
+          HttpResponse::BadRequest()
+            .json(json!({"code": "invalid_comment"})),
+        );
+      }
+    };
+
+  let post = match find_public_post(&state, &id).await {
+    Some(post) => post,
+    None => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "post_not_found"})),
+      );
+    }
+  };
+
+  let ip = client_ip(&req);
+  let user_agent = req
+    .headers()
+    .get(header::USER_AGENT)
+    .and_then(|value| value.to_str().ok())
+    .map(|value| value.to_string());
+
+  if let Some(ip) = &ip {
+    let valkey = &mut state.valkey.clone();
+    if !within_comment_rate(valkey, ip).await {
+      return Ok(
+        HttpResponse::TooManyRequests()
+          .insert_header((
+            header::RETRY_AFTER,
+            COMMENT_RATE_WINDOW.to_string(),
+          ))
+          .json(json!({"code": "too_many_comments"})),
+      );
+    }
+  }
+
+  // Replies can only go under approved comments on the same post.
+  if let Some(parent_id) = &body.parent_id {
+    let parent = sqlx::query_as::<_, BlogComment>(
+      "SELECT * FROM blog_comments WHERE id = $1 AND post_id = $2 \
+       AND status = 'approved' LIMIT 1",
+    )
+    .bind(parent_id)
+    .bind(&post.id)
+    .fetch_optional(&state.db)
+    .await;
+
+    if !matches!(parent, Ok(Some(_))) {
+      return Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "invalid_parent_comment"})),
+      );
+    }
+  }
+
+  let created = sqlx::query(
+    "INSERT INTO blog_comments \
+       (post_id, parent_id, author_name, author_email, body, ip, user_agent) \
+     VALUES ($1, $2, $3, $4, $5, $6, $7)",
+  )
+  .bind(&post.id)
+  .bind(&body.parent_id)
+  .bind(author_name)
+  .bind(author_email)
+  .bind(comment_body)
+  .bind(ip)
+  .bind(user_agent)
+  .execute(&state.db)
+  .await;
+
+  match created {
+    Ok(_) => Ok(HttpResponse::Accepted().json(pending)),
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_create_comment"})),
+    ),
+  }
+}
+
+#[get("")]
+async fn get_moderation_queue(
+  query: Option<web::Query<BlogCommentsQuery>>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let query = query.map(|query| query.into_inner());
+  let status = query
+    .as_ref()
+    .and_then(|query| query.status.clone())
+    .unwrap_or("pending".to_string());
+  let limit = query.as_ref().and_then(|query| query.limit).unwrap_or(25);
+  let offset = query.as_ref().and_then(|query| query.offset).unwrap_or(0);
+
+  let comments = sqlx::query_as::<_, BlogComment>(
+    "SELECT * FROM blog_comments WHERE status = $1 \
+     ORDER BY created_at ASC LIMIT $2 OFFSET $3",
+  )
+  .bind(status)
+  .bind(limit)
+  .bind(offset)
+  .fetch_all(&state.db)
+  .await;
+
+  match comments {
+    Ok(comments) => {
+      let comments: Vec<serde_json::Value> =
+        comments.iter().map(comment_json).collect();
+      Ok(HttpResponse::Ok().json(json!({"comments": comments})))
+    }
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_lookup_comments"})),
+    ),
+  }
+}
+
+async fn moderate_comment(
+  req: &HttpRequest,
+  state: &ServerState,
+  id: &str,
+  status: &str,
+) -> HttpResponse {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+
+  let comment = sqlx::query_as::<_, BlogComment>(
+    "UPDATE blog_comments SET status = $1, moderated_at = now(), \
+     moderated_by = $2 WHERE id = $3 RETURNING *",
+  )
+  .bind(status)
+  .bind(&user.id)
+  .bind(id)
+  .fetch_optional(&state.db)
+  .await;
+
+  match comment {
+    Ok(Some(comment)) => {
+      HttpResponse::Ok().json(json!({"comment": comment_json(&comment)}))
+    }
+    Ok(None) => {
+      HttpResponse::NotFound().json(json!({"code": "comment_not_found"}))
+    }
+    Err(_) => HttpResponse::InternalServerError()
+      .json(json!({"code": "failed_to_moderate_comment"})),
+  }
+}
+
+#[post("/{id}/approve")]
+async fn approve_comment(
+  req: HttpRequest,
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  Ok(moderate_comment(&req, &state, &id, "approved").await)
+}
+
+#[post("/{id}/reject")]
+async fn reject_comment(
+  req: HttpRequest,
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  Ok(moderate_comment(&req, &state, &id, "rejected").await)
+}
+
+#[delete("/{id}")]
+async fn delete_comment(
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let deleted = sqlx::query("DELETE FROM blog_comments WHERE id = $1")
+    .bind(id.to_string())
+    .execute(&state.db)
+    .await;
+
+  match deleted {
+    Ok(result) if result.rows_affected() > 0 => {
+      Ok(HttpResponse::NoContent().finish())
+    }
+    Ok(_) => Ok(
+      HttpResponse::NotFound().json(json!({"code": "comment_not_found"})),
+    ),
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_delete_comment"})),
+    ),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::NaiveDateTime;
+
+  fn comment(id: &str, parent_id: Option<&str>) -> BlogComment {
+    BlogComment {
+      id: id.to_string(),
+      post_id: "1".to_string(),
+      parent_id: parent_id.map(str::to_string),
+      author_name: "Reader".to_string(),
+      author_email: Some("reader@example.com".to_string()),
+      body: format!("Comment {}", id),
+      status: "approved".to_string(),
+      ip: Some("203.0.113.9".to_string()),
+      user_agent: None,
+      created_at: NaiveDateTime::default(),
+      moderated_at: None,
+      moderated_by: None,
+    }
+  }
+
+  fn submission(
+    name: &str,
+    email: Option<&str>,
+    body: &str,
+  ) -> BlogCommentCreate {
+    BlogCommentCreate {
+      author_name: name.to_string(),
+      author_email: email.map(str::to_string),
+      body: body.to_string(),
+      parent_id: None,
+      website: None,
+    }
+  }
+
+  #[test]
+  fn threads_replies_under_their_parents() {
+    let comments = vec![
+      comment("a", None),
+      comment("b", Some("a")),
+      comment("c", Some("b")),
+      comment("d", None),
+    ];
+    let threaded = thread_comments(&comments, None);
+
+    assert_eq!(threaded.len(), 2);
+    assert_eq!(threaded[0]["id"], "a");
+    assert_eq!(threaded[0]["replies"][0]["id"], "b");
+    assert_eq!(threaded[0]["replies"][0]["replies"][0]["id"], "c");
+    assert_eq!(threaded[1]["id"], "d");
+    assert_eq!(threaded[1]["replies"], json!([]));
+  }
+
+  #[test]
+  fn drops_replies_to_comments_that_are_not_shown() {
+    // "b" is pending, so it isn't among the approved comments threaded.
+    let comments = vec![comment("a", None), comment("c", Some("b"))];
+    let threaded = thread_comments(&comments, None);
+
+    assert_eq!(threaded.len(), 1);
+    assert_eq!(threaded[0]["replies"], json!([]));
+  }
+
+  #[test]
+  fn keeps_private_details_out_of_threads() {
+    let threaded = thread_comments(&[comment("a", None)], None);
+    let public = serde_json::to_string(&threaded).unwrap();
+
+    assert!(!public.contains("reader@example.com"));
+    assert!(!public.contains("203.0.113.9"));
+
+    let moderated = comment_json(&comment("a", None));
+    assert_eq!(moderated["author_email"], "reader@example.com");
+    assert_eq!(moderated["ip"], "203.0.113.9");
+  }
+
+  #[test]
+  fn trims_submitted_comments() {
+    let submitted =
+      submission("  Reader ", Some("   "), "\n Nice post \n");
+    assert_eq!(
+      clean_comment(&submitted),
+      Some(("Reader", None, "Nice post"))
+    );
+
+    let submitted =
+      submission("Reader", Some(" reader@example.com "), "Hi");
+    assert_eq!(
+      clean_comment(&submitted),
+      Some(("Reader", Some("reader@example.com"), "Hi"))
+    );
+  }
+
+  #[test]
+  fn rejects_invalid_comments() {
+    assert!(clean_comment(&submission("  ", None, "Hi")).is_none());
+    assert!(clean_comment(&submission("Reader", None, " \n ")).is_none());
+    assert!(clean_comment(&submission(
+      "Reader",
+      Some("not-an-email"),
+      "Hi"
+    ))
+    .is_none());
+
+    let long_name = "x".repeat(MAX_AUTHOR_NAME_LENGTH + 1);
+    assert!(clean_comment(&submission(&long_name, None, "Hi")).is_none());
+
+    let long_email = format!("{}@example.com", "x".repeat(250));
+    assert!(
+      clean_comment(&submission("Reader", Some(&long_email), "Hi"))
+        .is_none()
+    );
+  }
+
+  #[test]
+  fn measures_comment_length_in_characters() {
+    let body = "é".repeat(MAX_BODY_LENGTH);
+    assert!(clean_comment(&submission("Reader", None, &body)).is_some());
+
+    let body = "é".repeat(MAX_BODY_LENGTH + 1);
+    assert!(clean_comment(&submission("Reader", None, &body)).is_none());
+  }
+}
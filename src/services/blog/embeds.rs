@@ -0,0 +1,251 @@
+use redis::aio::ConnectionManager;
+use serde_json::json;
+
+use crate::{
+  helpers::riderr::fetch_user_stats,
+  structs::{
+    photography::{Album, AlbumItem, Photo},
+    spotify::SpotifyHistory,
+  },
+  ServerState,
+};
+
+const KINDS: [&str; 4] = ["album", "photo", "spotify", "ride"];
+const RIDE_TARGETS: [&str; 3] = ["latest", "current", "stats"];
+
+/// Riderr stats are cached briefly so a popular post doesn't call out to
+/// the Riderr API on every read.
+const RIDE_CACHE_KEY: &str = "blog_embed/riderr_stats";
+const RIDE_CACHE_TTL: u64 = 60;
+
+/// A `{{kind:target}}` shortcode in a post body.
+pub(crate) struct Shortcode {
+  pub kind: String,
+  pub target: String,
+}
+
+impl Shortcode {
+  pub fn code(&self) -> String {
+    format!("{}:{}", self.kind, self.target)
+  }
+}
+
+/// Every distinct shortcode in a body, in order of first appearance.
+/// Braces around anything that isn't a known kind are left alone.
+pub(crate) fn parse_shortcodes(body: &str) -> Vec<Shortcode> {
+  let mut shortcodes: Vec<Shortcode> = Vec::new();
+  let mut rest = body;
+
+  while let Some(start) = rest.find("{{") {
+    rest = &rest[start + 2..];
+    let end = match rest.find("}}") {
+      Some(end) => end,
+      None => break,
+    };
+
+    let mut inner = &rest[..end];
+    if let Some(nested) = inner.rfind("{{") {
+      inner = &inner[nested + 2..];
+    }
+    rest = &rest[end + 2..];
+
+    let (kind, target) = match inner.trim().split_once(':') {
+      Some(parts) => parts,
+      None => continue,
+    };
+    if !KINDS.contains(&kind)
+      || target.is_empty()
+      || target.contains(char::is_whitespace)
+    {
+      continue;
+    }
+
+    if !shortcodes.iter().any(|shortcode| {
+      shortcode.kind == kind && shortcode.target == target
+    }) {
+      shortcodes.push(Shortcode {
+        kind: kind.to_string(),
+        target: target.to_string(),
+      });
+    }
+  }
+
+  shortcodes
+}
+
+/// Albums a post can embed: anything reachable by slug without unlocking.
+async fn find_album(state: &ServerState, slug: &str) -> Option<Album> {
+  sqlx::query_as::<_, Album>(
+    "SELECT * FROM photography_albums \
+     WHERE slug = $1 AND visibility IN ('public', 'unlisted') LIMIT 1",
+  )
+  .bind(slug)
+  .fetch_optional(&state.db)
+  .await
+  .ok()
+  .flatten()
+}
+
+async fn riderr_stats(state: &ServerState) -> Option<serde_json::Value> {
+  let valkey = &mut state.valkey.clone();
+
+  let cached = redis::cmd("GET")
+    .arg(RIDE_CACHE_KEY)
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await
+    .ok()
+    .and_then(|cached| serde_json::from_str(&cached).ok());
+  if cached.is_some() {
+    return cached;
+  }
+
+  let stats = serde_json::to_value(fetch_user_stats().await?).ok()?;
+  let _ = redis::cmd("SET")
+    .arg(RIDE_CACHE_KEY)
+    .arg(stats.to_string())
+    .arg("EX")
+    .arg(RIDE_CACHE_TTL)
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await;
+
+  Some(stats)
+}
+
+/// Structured data for one shortcode, or `None` if what it points at
+/// doesn't exist (or, for rides, can't currently be fetched).
+async fn resolve_embed(
+  state: &ServerState,
+  shortcode: &Shortcode,
+) -> Option<serde_json::Value> {
+  match shortcode.kind.as_str() {
+    "album" => {
+      let album = find_album(state, &shortcode.target).await?;
+      let (photos,) = sqlx::query_as::<_, (i64,)>(
+        "SELECT COUNT(*) FROM photography_photos WHERE album_slug = $1",
+      )
+      .bind(&album.slug)
+      .fetch_one(&state.db)
+      .await
+      .ok()?;
+
+      Some(json!({
+        "type": "album",
+        "slug": album.slug,
+        "name": album.name,
+        "description": album.description,
+        "location": album.location,
+        "cover": album.cover,
+        "date": album.date,
+        "photos": photos,
+      }))
+    }
+    "photo" => {
+      let (slug, name) = shortcode.target.split_once('/')?;
+      let album = find_album(state, slug).await?;
+      let photo = sqlx::query_as::<_, Photo>(
+        "SELECT * FROM photography_photos \
+         WHERE album_slug = $1 AND name = $2 LIMIT 1",
+      )
+      .bind(&album.slug)
+      .bind(name)
+      .fetch_optional(&state.db)
+      .await
+      .ok()
+      .flatten()?;
+      let item = AlbumItem::from(photo);
+
+      Some(json!({
+        "type": "photo",
+        "album": album.slug,
+        "album_name": album.name,
+        "name": item.name,
+        "caption": item.caption,
+        "instagram": item.instagram,
+        "frame": item.frame,
+      }))
+    }
+    "spotify" => {
+      let track = sqlx::query_as::<_, SpotifyHistory>(
+        "SELECT * FROM spotify_history WHERE id = $1 \
+         ORDER BY listened_at DESC LIMIT 1",
+      )
+      .bind(&shortcode.target)
+      .fetch_optional(&state.db)
+      .await
+      .ok()
+      .flatten()?;
+      let url =
+        format!("https://open.spotify.com/{}/{}", track.r#type, track.id);
+
+      Some(json!({
+        "type": "spotify",
+        "id": track.id,
+        "media_type": track.r#type,
+        "name": track.name,
+        "artists": track.artists,
+        "length": track.length,
+        "image": track.image,
+        "url": url,
+        "listened_at": track.listened_at,
+      }))
+    }
+    "ride" => {
+      let stats = riderr_stats(state).await?;
+      let data = match shortcode.target.as_str() {
+        "latest" => stats["latest_ride"].clone(),
+        "current" => stats["current_ride"].clone(),
+        "stats" => stats["stats"].clone(),
+        _ => return None,
+      };
+      if data.is_null() {
+        return None;
+      }
+
+      Some(json!({
+        "type": "ride",
+        "target": shortcode.target,
+        "ride": data,
+      }))
+    }
+    _ => None,
+  }
+}
+
+/// Expand every shortcode in a post body, keyed by its `kind:target` code.
+/// Shortcodes that can't be resolved map to `null`.
+pub(crate) async fn expand_embeds(
+  state: &ServerState,
+  body: Option<&str>,
+) -> serde_json::Value {
+  let mut embeds = serde_json::Map::new();
+
+  for shortcode in parse_shortcodes(body.unwrap_or_default()) {
+    let embed = resolve_embed(state, &shortcode).await;
+    embeds.insert(shortcode.code(), embed.unwrap_or_default());
+  }
+
+  serde_json::Value::Object(embeds)
+}
+
+/// Shortcodes in a body that point at something that doesn't exist. Rides
+/// are only checked for a known target, since whether one is in progress
+/// changes over time.
+pub(crate) async fn missing_embeds(
+  state: &ServerState,
+  body: Option<&str>,
+) -> Vec<String> {
+  let mut missing = Vec::new();
+
+  for shortcode in parse_shortcodes(body.unwrap_or_default()) {
+    let exists = match shortcode.kind.as_str() {
+      "ride" => RIDE_TARGETS.contains(&shortcode.target.as_str()),
+      _ => resolve_embed(state, &shortcode).await.is_some(),
+    };
+
+    if !exists {
+      missing.push(shortcode.code());
+    }
+  }
+
+  missing
+}
@@ -1,26 +1,114 @@
+use actix_multipart::form::MultipartFormConfig;
 use actix_web::{web, Scope};
-use actix_web_lab::middleware::from_fn;
 
-use crate::services;
+use crate::{
+  services::{
+    self,
+    blog::{bundles::MAX_BUNDLE_SIZE, middleware::admin_scope},
+  },
+  structs::blog::BlogAdminRole,
+};
 
 pub fn factory() -> Scope {
   web::scope("/blog")
     .service(services::blog::auth::login)
+    .service(services::blog::two_factor::complete_login)
+    .service(services::blog::passkeys::start_passkey_login)
+    .service(services::blog::passkeys::finish_passkey_login)
+    .service(services::blog::views::get_popular_posts)
     .service(services::blog::posts::get_post)
     .service(services::blog::posts::get_posts)
+    .service(services::blog::og_images::get_og_image)
+    .service(services::blog::og_images::get_versioned_og_image)
+    .service(services::blog::comments::get_comments)
+    .service(services::blog::comments::create_comment)
+    .service(services::blog::activitypub::webfinger)
+    .service(services::blog::activitypub::get_actor)
+    .service(services::blog::activitypub::get_outbox)
+    .service(services::blog::activitypub::get_followers)
+    .service(services::blog::activitypub::get_post_object)
+    .service(services::blog::activitypub::receive_activity)
     .service(
-      web::scope("/admin")
-        .wrap(from_fn(services::blog::middleware::blog_admin_auth_mw))
+      admin_scope("/admin/auth", BlogAdminRole::Author)
         .service(services::blog::auth::logout)
+        .service(services::blog::auth::change_password),
+    )
+    .service(
+      admin_scope("/admin/me", BlogAdminRole::Author)
         .service(services::blog::auth::get_user)
-        .service(services::blog::auth::update_user)
-        .service(services::blog::auth::change_password)
+        .service(services::blog::auth::update_user),
+    )
+    .service(
+      admin_scope("/admin/posts", BlogAdminRole::Author)
         .service(services::blog::posts::get_all_posts)
         .service(services::blog::posts::create_post)
         .service(services::blog::posts::update_post)
         .service(services::blog::posts::delete_post)
         .service(services::blog::assets::get_assets_for_post)
         .service(services::blog::assets::upload_asset_for_post)
-        .service(services::blog::assets::delete_asset_for_post),
+        .service(services::blog::assets::delete_asset_for_post)
+        .service(services::blog::previews::create_preview)
+        .service(services::blog::previews::get_previews)
+        .service(services::blog::previews::revoke_preview),
+    )
+    .service(
+      admin_scope("/admin/uploads", BlogAdminRole::Author)
+        .service(services::uploads::resumable::factory()),
+    )
+    .service(
+      admin_scope("/admin/sessions", BlogAdminRole::Author)
+        .service(services::blog::sessions::get_sessions)
+        .service(services::blog::sessions::delete_other_sessions)
+        .service(services::blog::sessions::delete_session),
+    )
+    .service(
+      admin_scope("/admin/2fa", BlogAdminRole::Author)
+        .service(services::blog::two_factor::begin_totp_enrolment)
+        .service(services::blog::two_factor::confirm_totp_enrolment)
+        .service(services::blog::two_factor::disable_totp),
+    )
+    .service(
+      admin_scope("/admin/passkeys", BlogAdminRole::Author)
+        .service(services::blog::passkeys::get_passkeys)
+        .service(services::blog::passkeys::start_passkey_registration)
+        .service(services::blog::passkeys::finish_passkey_registration)
+        .service(services::blog::passkeys::delete_passkey),
+    )
+    .service(
+      admin_scope("/admin/comments", BlogAdminRole::Editor)
+        .service(services::blog::comments::get_moderation_queue)
+        .service(services::blog::comments::approve_comment)
+        .service(services::blog::comments::reject_comment)
+        .service(services::blog::comments::delete_comment),
+    )
+    .service(
+      admin_scope("/admin/trash", BlogAdminRole::Editor)
+        .service(services::blog::trash::get_trash)
+        .service(services::blog::trash::restore_post)
+        .service(services::blog::trash::purge_trashed_post),
+    )
+    .service(
+      admin_scope("/admin/assets", BlogAdminRole::Editor)
+        .service(services::blog::trash::get_orphaned_assets),
+    )
+    .service(
+      admin_scope("/admin/export", BlogAdminRole::Editor)
+        .service(services::blog::bundles::export_posts),
+    )
+    .service(
+      admin_scope("/admin/import", BlogAdminRole::Editor)
+        .app_data(
+          MultipartFormConfig::default()
+            .total_limit(MAX_BUNDLE_SIZE as usize)
+            .memory_limit(MAX_BUNDLE_SIZE as usize),
+        )
+        .service(services::blog::bundles::import_posts),
+    )
+    .service(
+      admin_scope("/admin/users", BlogAdminRole::Owner)
+        .service(services::blog::users::get_users)
+        .service(services::blog::users::create_user)
+        .service(services::blog::users::update_admin_user)
+        .service(services::blog::users::delete_admin_user),
     )
 }
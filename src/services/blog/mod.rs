@@ -1,5 +1,17 @@
+pub mod activitypub;
 pub mod assets;
 pub mod auth;
+pub mod bundles;
+pub mod comments;
+pub mod embeds;
 pub mod factory;
 pub mod middleware;
+pub mod og_images;
+pub mod passkeys;
 pub mod posts;
+pub mod previews;
+pub mod sessions;
+pub mod trash;
+pub mod two_factor;
+pub mod users;
+pub mod views;
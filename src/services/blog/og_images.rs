@@ -0,0 +1,140 @@
+use actix_web::{get, http::header, http::Error, web, HttpResponse};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+
+use crate::{
+  helpers::og_image::render_og_image, structs::blog::BlogPost, ServerState,
+};
+
+/// Fingerprint of what a post's generated card draws, so editing the title
+/// or tags produces a new image rather than serving a stale one.
+fn og_image_version(post: &BlogPost) -> String {
+  let mut hasher = Sha1::new();
+  hasher.update(post.title.as_bytes());
+  hasher.update(b"\n");
+  hasher.update(post.tags.join(",").as_bytes());
+  hex::encode(hasher.finalize())[..16].to_string()
+}
+
+/// S3 key for a post's generated card.
+fn og_image_path(post: &BlogPost, version: &str) -> String {
+  format!("blog/og/{}/{}.png", post.id, version)
+}
+
+fn redirect(location: &str) -> HttpResponse {
+  HttpResponse::Found()
+    .insert_header((header::LOCATION, location))
+    .insert_header((header::CACHE_CONTROL, "public, max-age=300"))
+    .finish()
+}
+
+fn png_response(data: Vec<u8>) -> HttpResponse {
+  HttpResponse::Ok()
+    .content_type("image/png")
+    .insert_header((
+      header::CACHE_CONTROL,
+      "public, max-age=31536000, immutable",
+    ))
+    .body(data)
+}
+
+async fn find_og_post(state: &ServerState, id: &str) -> Option<BlogPost> {
+  sqlx::query_as::<_, BlogPost>(
+    "SELECT * FROM blog_posts WHERE id = $1 \
+     AND visibility IN ('public', 'unlisted') LIMIT 1",
+  )
+  .bind(id)
+  .fetch_optional(&state.db)
+  .await
+  .ok()
+  .flatten()
+}
+
+fn cover_image(post: &BlogPost) -> Option<&str> {
+  post.image.as_deref().filter(|image| !image.is_empty())
+}
+
+/// Stable Open Graph image URL for a post. Posts with a cover redirect to
+/// it; the rest redirect to their current generated card, whose URL
+/// changes whenever the card does so it can be cached for good.
+#[get("/posts/{id}/og.png")]
+async fn get_og_image(
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let post = match find_og_post(&state, &id).await {
+    Some(post) => post,
+    None => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "post_not_found"})),
+      );
+    }
+  };
+
+  if let Some(image) = cover_image(&post) {
+    return Ok(redirect(image));
+  }
+
+  Ok(redirect(&format!("og/{}.png", og_image_version(&post))))
+}
+
+/// A post's generated card, rendered once and cached in S3. Outdated
+/// versions redirect back to the current one.
+#[get("/posts/{id}/og/{version}.png")]
+async fn get_versioned_og_image(
+  path: web::Path<(String, String)>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let (id, version) = path.into_inner();
+
+  let post = match find_og_post(&state, &id).await {
+    Some(post) => post,
+    None => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "post_not_found"})),
+      );
+    }
+  };
+
+  if let Some(image) = cover_image(&post) {
+    return Ok(redirect(image));
+  }
+
+  let current = og_image_version(&post);
+  if version != current {
+    return Ok(redirect(&format!("{current}.png")));
+  }
+
+  let s3 = &state.s3;
+  let path = og_image_path(&post, &current);
+
+  if let Ok(cached) = s3.cdn_bucket.get_object(&path).await {
+    if cached.status_code() == 200 {
+      return Ok(png_response(cached.bytes().to_vec()));
+    }
+  }
+
+  let title = post.title.clone();
+  let tags = post.tags.clone();
+  let rendered = web::block(move || render_og_image(&title, &tags)).await;
+
+  let data = match rendered {
+    Ok(Some(data)) => data,
+    _ => {
+      return Ok(
+        HttpResponse::InternalServerError()
+          .json(json!({"code": "failed_to_render_image"})),
+      );
+    }
+  };
+
+  if let Err(error) = s3
+    .cdn_bucket
+    .put_object_with_content_type(&path, &data, "image/png")
+    .await
+  {
+    tracing::warn!("Failed to cache Open Graph image {}: {}", path, error);
+  }
+
+  Ok(png_response(data))
+}
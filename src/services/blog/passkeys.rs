@@ -0,0 +1,472 @@
+use actix_web::{
+  delete, get, http::Error, post, web, HttpMessage, HttpRequest,
+  HttpResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use envconfig::Envconfig;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use rand::{distributions::Alphanumeric, Rng, RngCore};
+use redis::aio::ConnectionManager;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use webauthn_rs::{
+  prelude::{
+    Passkey, PasskeyRegistration, RequestChallengeResponse, Url, Uuid,
+    WebauthnError,
+  },
+  Webauthn, WebauthnBuilder,
+};
+
+use crate::{
+  config::Config,
+  helpers::{
+    client_ip::client_ip,
+    limiter::{locked_out_response, AuthLimiter},
+  },
+  services::blog::{auth::session_response, sessions::create_session},
+  structs::blog::{
+    BlogAdminPasskey, BlogAdminUser, BlogPasskeyLoginFinish,
+    BlogPasskeyLoginStart, BlogPasskeyLoginState,
+    BlogPasskeyRegisterFinish,
+  },
+  ServerState,
+};
+
+const CEREMONY_TTL: u64 = 5 * 60;
+
+/// Keys the decoy credential ids handed out for unknown usernames. It only
+/// has to stay stable between a caller's requests, so a per-process key is
+/// enough.
+static DECOY_KEY: Lazy<[u8; 32]> = Lazy::new(|| {
+  let mut key = [0u8; 32];
+  rand::thread_rng().fill_bytes(&mut key);
+  key
+});
+
+fn webauthn() -> Webauthn {
+  let config = Config::init_from_env().unwrap();
+  let origin = Url::parse(&config.blog_webauthn_origin)
+    .expect("BLOG_WEBAUTHN_ORIGIN must be a valid url");
+
+  WebauthnBuilder::new(&config.blog_webauthn_rp_id, &origin)
+    .expect("invalid webauthn relying party configuration")
+    .rp_name(&config.blog_webauthn_rp_id)
+    .build()
+    .expect("invalid webauthn relying party configuration")
+}
+
+/// Stable WebAuthn user handle derived from the admin user id, so it needs
+/// no column of its own.
+fn user_handle(user_id: &str) -> Uuid {
+  let digest = Sha256::digest(user_id.as_bytes());
+  Uuid::from_slice(&digest[..16]).unwrap()
+}
+
+/// Credential id offered to usernames that can't log in with a passkey.
+/// It's derived from the username so repeated requests see the same id,
+/// the way they would for a real account.
+fn decoy_credential_id(username: &str) -> String {
+  let mut mac = Hmac::<Sha256>::new_from_slice(DECOY_KEY.as_slice())
+    .expect("HMAC accepts keys of any length");
+  mac.update(username.trim().to_lowercase().as_bytes());
+  URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+/// Challenge shaped like a real one for a username with no usable
+/// passkeys. Its ceremony is thrown away, so it can never be completed.
+fn decoy_challenge(
+  username: &str,
+) -> Result<RequestChallengeResponse, WebauthnError> {
+  let (mut options, _) = webauthn().start_passkey_authentication(&[])?;
+
+  options.public_key.allow_credentials = serde_json::from_value(json!([{
+    "type": "public-key",
+    "id": decoy_credential_id(username),
+  }]))
+  .unwrap();
+
+  Ok(options)
+}
+
+fn passkey_json(passkey: &BlogAdminPasskey) -> serde_json::Value {
+  json!({
+    "id": passkey.id,
+    "name": passkey.name,
+    "created_at": passkey.created_at,
+    "last_used_at": passkey.last_used_at,
+  })
+}
+
+async fn user_passkeys(
+  db: &sqlx::PgPool,
+  user_id: &str,
+) -> Result<Vec<BlogAdminPasskey>, sqlx::Error> {
+  sqlx::query_as::<_, BlogAdminPasskey>(
+    "SELECT * FROM blog_admin_passkeys WHERE user_id = $1 \
+     ORDER BY created_at ASC",
+  )
+  .bind(user_id)
+  .fetch_all(db)
+  .await
+}
+
+#[get("")]
+async fn get_passkeys(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+
+  match user_passkeys(&state.db, &user.id).await {
+    Ok(passkeys) => {
+      let passkeys: Vec<serde_json::Value> =
+        passkeys.iter().map(passkey_json).collect();
+      Ok(HttpResponse::Ok().json(json!({"passkeys": passkeys})))
+    }
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_lookup_passkeys"})),
+    ),
+  }
+}
+
+#[post("/register")]
+async fn start_passkey_registration(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+  let valkey = &mut state.valkey.clone();
+
+  // Stop the same authenticator being registered twice.
+  let existing: Vec<_> = user_passkeys(&state.db, &user.id)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .filter_map(|passkey| {
+      serde_json::from_value::<Passkey>(passkey.passkey).ok()
+    })
+    .map(|passkey| passkey.cred_id().clone())
+    .collect();
+
+  let display_name =
+    user.display_name.clone().unwrap_or(user.username.clone());
+  let started = webauthn().start_passkey_registration(
+    user_handle(&user.id),
+    &user.username,
+    &display_name,
+    Some(existing),
+  );
+
+  let (options, registration) = match started {
+    Ok(started) => started,
+    Err(_) => {
+      return Ok(
+        HttpResponse::InternalServerError()
+          .json(json!({"code": "failed_to_start_registration"})),
+      );
+    }
+  };
+
+  let _ = redis::cmd("SET")
+    .arg(format!("blog_admin_passkey_registration/{}", user.id))
+    .arg(serde_json::to_string(&registration).unwrap())
+    .arg("EX")
+    .arg(CEREMONY_TTL)
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await;
+
+  Ok(HttpResponse::Ok().json(json!({"options": options})))
+}
+
+#[post("/register/finish")]
+async fn finish_passkey_registration(
+  req: HttpRequest,
+  body: web::Json<BlogPasskeyRegisterFinish>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+  let valkey = &mut state.valkey.clone();
+  let state_key = format!("blog_admin_passkey_registration/{}", user.id);
+
+  let registration = redis::cmd("GETDEL")
+    .arg(&state_key)
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await
+    .ok()
+    .and_then(|stored| {
+      serde_json::from_str::<PasskeyRegistration>(&stored).ok()
+    });
+
+  let registration = match registration {
+    Some(registration) => registration,
+    None => {
+      return Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "no_pending_registration"})),
+      );
+    }
+  };
+
+  let passkey = match webauthn()
+    .finish_passkey_registration(&body.credential, &registration)
+  {
+    Ok(passkey) => passkey,
+    Err(_) => {
+      return Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "invalid_passkey_registration"})),
+      );
+    }
+  };
+
+  let stored = sqlx::query_as::<_, BlogAdminPasskey>(
+    "INSERT INTO blog_admin_passkeys (id, user_id, name, passkey) \
+     VALUES ($1, $2, $3, $4) RETURNING *",
+  )
+  .bind(URL_SAFE_NO_PAD.encode(passkey.cred_id()))
+  .bind(&user.id)
+  .bind(body.name.clone())
+  .bind(serde_json::to_value(&passkey).unwrap())
+  .fetch_one(&state.db)
+  .await;
+
+  match stored {
+    Ok(passkey) => Ok(
+      HttpResponse::Created()
+        .json(json!({"passkey": passkey_json(&passkey)})),
+    ),
+    Err(sqlx::Error::Database(error)) if error.is_unique_violation() => {
+      Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "passkey_already_registered"})),
+      )
+    }
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_store_passkey"})),
+    ),
+  }
+}
+
+#[delete("/{id}")]
+async fn delete_passkey(
+  req: HttpRequest,
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+
+  let result = sqlx::query(
+    "DELETE FROM blog_admin_passkeys WHERE id = $1 AND user_id = $2",
+  )
+  .bind(id.to_string())
+  .bind(&user.id)
+  .execute(&state.db)
+  .await;
+
+  match result {
+    Ok(result) if result.rows_affected() > 0 => {
+      Ok(HttpResponse::NoContent().finish())
+    }
+    _ => Ok(
+      HttpResponse::NotFound().json(json!({"code": "passkey_not_found"})),
+    ),
+  }
+}
+
+#[post("/auth/passkey")]
+async fn start_passkey_login(
+  req: HttpRequest,
+  body: web::Json<BlogPasskeyLoginStart>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let valkey = &mut state.valkey.clone();
+
+  let limiter = AuthLimiter::new("blog")
+    .ip(client_ip(&req).as_deref())
+    .username(&body.username);
+
+  if let Some(retry_after) = limiter.locked_for(valkey).await {
+    return Ok(locked_out_response(retry_after));
+  }
+
+  let user = sqlx::query_as::<_, BlogAdminUser>(
+    "SELECT * FROM blog_admin_users WHERE username = $1 LIMIT 1",
+  )
+  .bind(&body.username)
+  .fetch_optional(&state.db)
+  .await;
+
+  let user = match user {
+    Ok(Some(user)) if !user.disabled => Some(user),
+    _ => None,
+  };
+  let passkeys: Vec<Passkey> = match &user {
+    Some(user) => user_passkeys(&state.db, &user.id)
+      .await
+      .unwrap_or_default()
+      .into_iter()
+      .filter_map(|passkey| serde_json::from_value(passkey.passkey).ok())
+      .collect(),
+    None => Vec::new(),
+  };
+
+  // Usernames that can't log in with a passkey get a challenge of the same
+  // shape rather than an error, so the route doesn't reveal which accounts
+  // exist or have passkeys.
+  let started = if passkeys.is_empty() {
+    decoy_challenge(&body.username).map(|options| (options, None, None))
+  } else {
+    webauthn().start_passkey_authentication(&passkeys).map(
+      |(options, authentication)| {
+        (options, user.map(|user| user.id), Some(authentication))
+      },
+    )
+  };
+
+  let (options, user_id, authentication) = match started {
+    Ok(started) => started,
+    Err(_) => {
+      return Ok(
+        HttpResponse::InternalServerError()
+          .json(json!({"code": "failed_to_start_authentication"})),
+      );
+    }
+  };
+
+  let challenge: String = rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(64)
+    .map(char::from)
+    .collect();
+  let login_state = BlogPasskeyLoginState {
+    username: body.username.clone(),
+    user_id,
+    state: authentication,
+  };
+
+  let _ = redis::cmd("SET")
+    .arg(format!("blog_admin_passkey_login/{}", challenge))
+    .arg(serde_json::to_string(&login_state).unwrap())
+    .arg("EX")
+    .arg(CEREMONY_TTL)
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await;
+
+  Ok(
+    HttpResponse::Ok()
+      .json(json!({"challenge": challenge, "options": options})),
+  )
+}
+
+#[post("/auth/passkey/finish")]
+async fn finish_passkey_login(
+  req: HttpRequest,
+  body: web::Json<BlogPasskeyLoginFinish>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let valkey = &mut state.valkey.clone();
+
+  // GETDEL so each ceremony can only be completed once.
+  let login_state = redis::cmd("GETDEL")
+    .arg(format!("blog_admin_passkey_login/{}", body.challenge))
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await
+    .ok()
+    .and_then(|stored| {
+      serde_json::from_str::<BlogPasskeyLoginState>(&stored).ok()
+    });
+
+  let login_state = match login_state {
+    Some(login_state) => login_state,
+    None => {
+      return Ok(
+        HttpResponse::Unauthorized()
+          .json(json!({"code": "invalid_login_challenge"})),
+      );
+    }
+  };
+
+  let limiter = AuthLimiter::new("blog")
+    .ip(client_ip(&req).as_deref())
+    .username(&login_state.username);
+
+  if let Some(retry_after) = limiter.locked_for(valkey).await {
+    return Ok(locked_out_response(retry_after));
+  }
+
+  // Decoy challenges have no ceremony and always fail here.
+  let verified = match (&login_state.user_id, &login_state.state) {
+    (Some(user_id), Some(authentication)) => webauthn()
+      .finish_passkey_authentication(&body.credential, authentication)
+      .ok()
+      .map(|result| (user_id, result)),
+    _ => None,
+  };
+
+  let user = match &verified {
+    Some((user_id, _)) => sqlx::query_as::<_, BlogAdminUser>(
+      "SELECT * FROM blog_admin_users WHERE id = $1 LIMIT 1",
+    )
+    .bind(user_id)
+    .fetch_optional(&state.db)
+    .await
+    .ok()
+    .flatten()
+    .filter(|user| !user.disabled),
+    None => None,
+  };
+
+  let (user, result) = match (user, verified) {
+    (Some(user), Some((_, result))) => (user, result),
+    _ => {
+      limiter.record_failure(valkey).await;
+      return Ok(
+        HttpResponse::Unauthorized()
+          .json(json!({"code": "invalid_authentication"})),
+      );
+    }
+  };
+
+  limiter.record_success(valkey).await;
+
+  // Persist the authenticator's new signature counter so cloned
+  // credentials can be detected on their next use.
+  let credential_id = URL_SAFE_NO_PAD.encode(result.cred_id());
+  let stored = sqlx::query_as::<_, BlogAdminPasskey>(
+    "SELECT * FROM blog_admin_passkeys WHERE id = $1 AND user_id = $2",
+  )
+  .bind(&credential_id)
+  .bind(&user.id)
+  .fetch_optional(&state.db)
+  .await;
+
+  if let Ok(Some(stored)) = stored {
+    if let Ok(mut passkey) =
+      serde_json::from_value::<Passkey>(stored.passkey)
+    {
+      passkey.update_credential(&result);
+
+      let _ = sqlx::query(
+        "UPDATE blog_admin_passkeys SET passkey = $1, last_used_at = now() \
+         WHERE id = $2",
+      )
+      .bind(serde_json::to_value(&passkey).unwrap())
+      .bind(&credential_id)
+      .execute(&state.db)
+      .await;
+    }
+  }
+
+  match create_session(valkey, &user.id, &req).await {
+    Some(token) => {
+      Ok(HttpResponse::Ok().json(session_response(&user, &token)))
+    }
+    None => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_create_session"})),
+    ),
+  }
+}
@@ -1,18 +1,93 @@
 use actix_web::{
   delete, get,
-  http::Error,
+  http::{header, Error},
   patch, post,
   web::{self},
-  HttpResponse,
+  HttpMessage, HttpRequest, HttpResponse,
 };
 use chrono::{NaiveDateTime, Utc};
+use optional_field::Field;
 use serde_json::json;
 
 use crate::{
-  structs::blog::{BlogPost, BlogPostMutate, BlogPostsQuery},
+  services::blog::{
+    activitypub::deliver_post,
+    assets::asset_url,
+    comments::approved_comments,
+    embeds::{expand_embeds, missing_embeds},
+    previews::verify_preview_token,
+    views::record_view,
+  },
+  structs::blog::{
+    BlogAdminRole, BlogAdminUser, BlogAsset, BlogPost, BlogPostMutate,
+    BlogPostQuery, BlogPostWithViews, BlogPostsQuery, RESERVED_POST_SLUGS,
+  },
   ServerState,
 };
 
+/// Owners and editors can change any post; authors only their own drafts.
+pub(crate) fn can_edit_post(
+  user: &BlogAdminUser,
+  post: &BlogPost,
+) -> bool {
+  user.role() >= BlogAdminRole::Editor
+    || (post.visibility == "draft"
+      && post.author_id.as_deref() == Some(user.id.as_str()))
+}
+
+/// Remember a post's previous slug so links to it keep working. Taking a
+/// slug back out of the history drops it from there.
+pub(crate) async fn record_slug_change(
+  state: &ServerState,
+  post_id: &str,
+  old_slug: &str,
+  new_slug: &str,
+) {
+  if old_slug == new_slug {
+    return;
+  }
+
+  let _ = sqlx::query(
+    "INSERT INTO blog_post_slugs (slug, post_id) VALUES ($1, $2) \
+     ON CONFLICT (slug) DO UPDATE \
+     SET post_id = EXCLUDED.post_id, created_at = now()",
+  )
+  .bind(old_slug)
+  .bind(post_id)
+  .execute(&state.db)
+  .await;
+
+  let _ = sqlx::query("DELETE FROM blog_post_slugs WHERE slug = $1")
+    .bind(new_slug)
+    .execute(&state.db)
+    .await;
+}
+
+/// Look up a post the current admin is allowed to change, or the error
+/// response to send back instead.
+pub(crate) async fn find_editable_post(
+  state: &ServerState,
+  user: &BlogAdminUser,
+  id: &str,
+) -> Result<BlogPost, HttpResponse> {
+  let existing = sqlx::query_as::<_, BlogPost>(
+    "SELECT * FROM blog_posts WHERE id = $1 LIMIT 1",
+  )
+  .bind(id)
+  .fetch_optional(&state.db)
+  .await;
+
+  match existing {
+    Ok(Some(post)) if can_edit_post(user, &post) => Ok(post),
+    Ok(Some(_)) => Err(
+      HttpResponse::Forbidden().json(json!({"code": "post_not_editable"})),
+    ),
+    _ => {
+      Err(HttpResponse::NotFound().json(json!({"code": "post_not_found"})))
+    }
+  }
+}
+
 #[get("/posts")]
 async fn get_posts(
   query: Option<web::Query<BlogPostsQuery>>,
@@ -55,7 +130,7 @@ async fn get_posts(
   Ok(HttpResponse::Ok().json(json!({"posts": posts})))
 }
 
-#[get("/posts")]
+#[get("")]
 async fn get_all_posts(
   query: Option<web::Query<BlogPostsQuery>>,
   state: web::Data<ServerState>,
@@ -66,8 +141,12 @@ async fn get_all_posts(
       offset: Some(0),
     }));
 
-  let posts = sqlx::query_as::<_, BlogPost>(
-    "SELECT * FROM blog_posts ORDER BY created_at DESC LIMIT $1 OFFSET $2",
+  let posts = sqlx::query_as::<_, BlogPostWithViews>(
+    "SELECT p.*, COALESCE(( \
+       SELECT SUM(v.views) FROM blog_post_views v WHERE v.post_id = p.id \
+     ), 0)::bigint AS views \
+     FROM blog_posts p WHERE p.visibility <> 'deleted' \
+     ORDER BY p.created_at DESC LIMIT $1 OFFSET $2",
   )
   .bind(query.limit.unwrap_or(25))
   .bind(query.offset.unwrap_or(0))
@@ -77,7 +156,7 @@ async fn get_all_posts(
   let posts: Vec<serde_json::Value> = posts
     .unwrap()
     .iter()
-    .map(|post| {
+    .map(|BlogPostWithViews { post, views }| {
       json!({
         "id": post.id,
         "slug": post.slug,
@@ -87,8 +166,10 @@ async fn get_all_posts(
         "visibility": post.visibility,
         "body": post.body,
         "tags": post.tags,
+        "author_id": post.author_id,
         "created_at": post.created_at,
         "published_at": post.published_at,
+        "views": views,
       })
     })
     .collect();
@@ -96,31 +177,70 @@ async fn get_all_posts(
   Ok(HttpResponse::Ok().json(json!({"posts": posts})))
 }
 
-#[post("/posts")]
+#[post("")]
 async fn create_post(
+  req: HttpRequest,
   body: Option<web::Json<BlogPostMutate>>,
   state: web::Data<ServerState>,
 ) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+
   let body = body.unwrap_or(actix_web::web::Json(BlogPostMutate {
     slug: None,
     title: None,
     description: None,
-    image: None,
+    image: Field::Missing,
+    cover_asset: None,
     visibility: None,
     tags: None,
     body: None,
   }));
 
+  if user.role() < BlogAdminRole::Editor
+    && body.visibility.as_deref().is_some_and(|v| v != "draft")
+  {
+    return Ok(
+      HttpResponse::Forbidden().json(json!({"code": "insufficient_role"})),
+    );
+  }
+
+  if body
+    .slug
+    .as_deref()
+    .is_some_and(|slug| RESERVED_POST_SLUGS.contains(&slug))
+  {
+    return Ok(
+      HttpResponse::BadRequest().json(json!({"code": "reserved_slug"})),
+    );
+  }
+
+  // A new post has no assets yet, so its cover can only be a raw URL.
+  if body.cover_asset.is_some() {
+    return Ok(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "cover_asset_not_found"})),
+    );
+  }
+
+  let image = match &body.image {
+    Field::Present(image) => image.to_owned(),
+    Field::Missing => None,
+  };
+
   // Omitted columns fall back to the database-side defaults (id_generator(),
-  // date_title(), date_slug(), 'draft', '{}').
+  // date_title(), date_slug(), 'draft', '{}'). Posts created public are
+  // published straight away.
   let post = sqlx::query_as::<_, BlogPost>(
-    "INSERT INTO blog_posts (title, slug, visibility, tags, description, body) \
+    "INSERT INTO blog_posts \
+       (title, slug, visibility, tags, description, body, author_id, \
+        image, published_at) \
      VALUES (\
        COALESCE($1, date_title()), \
        COALESCE($2, date_slug()), \
        COALESCE($3, 'draft'), \
        COALESCE($4, '{}'::text[]), \
-       $5, $6\
+       $5, $6, $7, $8, \
+       CASE WHEN $3 = 'public' THEN now() END\
      ) RETURNING *",
   )
   .bind(body.title.clone())
@@ -129,9 +249,17 @@ async fn create_post(
   .bind(body.tags.clone())
   .bind(body.description.clone())
   .bind(body.body.clone())
+  .bind(user.id)
+  .bind(image)
   .fetch_one(&state.db)
   .await;
 
+  if let Ok(post) = &post {
+    if post.visibility == "public" {
+      tokio::spawn(deliver_post(web::Data::clone(&state), post.clone()));
+    }
+  }
+
   match post {
     Ok(post) => Ok(HttpResponse::Created().json(json!({
         "post": {
@@ -143,9 +271,11 @@ async fn create_post(
             "visibility": post.visibility,
             "tags": post.tags,
             "body": post.body,
+            "author_id": post.author_id,
             "created_at": post.created_at,
             "published_at": post.published_at,
-        }
+        },
+        "missing_embeds": missing_embeds(&state, post.body.as_deref()).await,
     }))),
     Err(_) => Ok(
       HttpResponse::InternalServerError()
@@ -156,24 +286,95 @@ async fn create_post(
 
 #[get("/posts/{id_or_slug}")]
 async fn get_post(
+  req: HttpRequest,
   id_or_slug: web::Path<String>,
+  query: Option<web::Query<BlogPostQuery>>,
   state: web::Data<ServerState>,
 ) -> Result<HttpResponse, Error> {
-  let query = if id_or_slug.parse::<f64>().is_ok() {
-    "SELECT * FROM blog_posts WHERE id = $1 \
-     AND visibility IN ('public', 'unlisted') LIMIT 1"
+  let sql = if id_or_slug.parse::<f64>().is_ok() {
+    "SELECT * FROM blog_posts WHERE id = $1 LIMIT 1"
   } else {
-    "SELECT * FROM blog_posts WHERE slug = $1 \
-     AND visibility IN ('public', 'unlisted') LIMIT 1"
+    "SELECT * FROM blog_posts WHERE slug = $1 LIMIT 1"
   };
 
-  let post = sqlx::query_as::<_, BlogPost>(query)
+  let post = sqlx::query_as::<_, BlogPost>(sql)
+    .bind(id_or_slug.to_string())
+    .fetch_optional(&state.db)
+    .await;
+
+  // A slug the post has since moved away from redirects to the current one.
+  // Only listed posts are followed so a renamed draft's slug isn't leaked.
+  if let Ok(None) = post {
+    let renamed = sqlx::query_as::<_, BlogPost>(
+      "SELECT p.* FROM blog_post_slugs s \
+       JOIN blog_posts p ON p.id = s.post_id \
+       WHERE s.slug = $1 AND p.visibility IN ('public', 'unlisted') LIMIT 1",
+    )
     .bind(id_or_slug.to_string())
     .fetch_optional(&state.db)
     .await;
 
+    if let Ok(Some(renamed)) = renamed {
+      let path = req.path();
+      let base = path.rsplit_once('/').map_or("", |(base, _)| base);
+      let location = match req.query_string() {
+        "" => format!("{base}/{}", renamed.slug),
+        query => format!("{base}/{}?{query}", renamed.slug),
+      };
+
+      return Ok(
+        HttpResponse::MovedPermanently()
+          .insert_header((header::LOCATION, location))
+          .json(
+            json!({"code": "post_moved", "redirect_to": renamed.slug}),
+          ),
+      );
+    }
+  }
+
+  // Anything that isn't public or unlisted needs a valid preview token
+  // minted for this exact post.
+  let post = match post {
+    Ok(Some(post))
+      if post.visibility == "public" || post.visibility == "unlisted" =>
+    {
+      Ok(Some(post))
+    }
+    Ok(Some(post)) => {
+      let preview = match query.and_then(|query| query.preview.clone()) {
+        Some(token) => {
+          let valkey = &mut state.valkey.clone();
+          verify_preview_token(valkey, &token).await
+        }
+        None => None,
+      };
+
+      match preview {
+        Some(preview) if preview.post_id == post.id => Ok(Some(post)),
+        _ => Ok(None),
+      }
+    }
+    other => other,
+  };
+
+  // Preview views aren't counted.
+  if let Ok(Some(post)) = &post {
+    if post.visibility == "public" || post.visibility == "unlisted" {
+      let valkey = &mut state.valkey.clone();
+      record_view(valkey, &post.id, &req).await;
+    }
+  }
+
   match post {
-    Ok(Some(post)) => Ok(HttpResponse::Ok().json(json!({
+    Ok(Some(post)) => {
+      // Comments are only open on public posts, so other posts show none.
+      let comments = if post.visibility == "public" {
+        approved_comments(&state, &post.id).await
+      } else {
+        Vec::new()
+      };
+
+      Ok(HttpResponse::Ok().json(json!({
         "post": {
             "id": post.id,
             "title": post.title,
@@ -184,8 +385,11 @@ async fn get_post(
             "tags": post.tags,
             "body": post.body,
             "published_at": post.published_at,
-        }
-    }))),
+        },
+        "embeds": expand_embeds(&state, post.body.as_deref()).await,
+        "comments": comments,
+      })))
+    }
     Ok(None) => Ok(
       HttpResponse::NotFound().json(json!({"code": "post_not_found"})),
     ),
@@ -195,56 +399,89 @@ async fn get_post(
   }
 }
 
-#[patch("/posts/{id}")]
+#[patch("/{id}")]
 async fn update_post(
+  req: HttpRequest,
   id: web::Path<String>,
   state: web::Data<ServerState>,
   body: web::Json<BlogPostMutate>,
 ) -> Result<HttpResponse, Error> {
-  let existing = sqlx::query_as::<_, BlogPost>(
-    "SELECT * FROM blog_posts WHERE id = $1 LIMIT 1",
-  )
-  .bind(id.to_string())
-  .fetch_optional(&state.db)
-  .await;
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
 
-  let post = match existing {
-    Ok(Some(post)) => post,
-    Ok(None) => {
-      return Ok(
-        HttpResponse::NotFound().json(json!({"code": "post_not_found"})),
-      );
-    }
-    Err(_) => {
-      return Ok(
-        HttpResponse::Unauthorized()
-          .json(json!({"code": "post_not_found"})),
-      );
-    }
+  let post = match find_editable_post(&state, &user, &id).await {
+    Ok(post) => post,
+    Err(response) => return Ok(response),
   };
 
+  if body
+    .slug
+    .as_deref()
+    .is_some_and(|slug| RESERVED_POST_SLUGS.contains(&slug))
+  {
+    return Ok(
+      HttpResponse::BadRequest().json(json!({"code": "reserved_slug"})),
+    );
+  }
+
   let intended_visibility =
     body.visibility.clone().unwrap_or(post.visibility.clone());
 
+  // Authors can edit their drafts but not publish them.
+  if user.role() < BlogAdminRole::Editor && intended_visibility != "draft"
+  {
+    return Ok(
+      HttpResponse::Forbidden().json(json!({"code": "insufficient_role"})),
+    );
+  }
+
   // If the post is being made public for the first time, stamp published_at.
   let mut published_at: Option<NaiveDateTime> = post.published_at;
-  if post.published_at.is_none() && intended_visibility == "public" {
+  let first_publish =
+    post.published_at.is_none() && intended_visibility == "public";
+  if first_publish {
     published_at = Some(Utc::now().naive_utc());
   }
 
   // Resolve each column to its new-or-existing value (only `description` and
   // `body` are set when provided; the rest fall back to the current row).
+  let previous_slug = post.slug.clone();
   let title = body.title.clone().unwrap_or(post.title);
   let slug = body.slug.clone().unwrap_or(post.slug);
   let tags = body.tags.clone().unwrap_or(post.tags);
   let description = body.description.clone().or(post.description);
   let body_text = body.body.clone().or(post.body);
 
+  // A cover picked from the post's own assets wins over a raw URL.
+  let image = match (&body.cover_asset, &body.image) {
+    (Some(hash), _) => {
+      let asset = sqlx::query_as::<_, BlogAsset>(
+        "SELECT * FROM blog_assets WHERE hash = $1 AND post_id = $2 LIMIT 1",
+      )
+      .bind(hash)
+      .bind(&post.id)
+      .fetch_optional(&state.db)
+      .await;
+
+      match asset {
+        Ok(Some(asset)) => Some(asset_url(&asset)),
+        _ => {
+          return Ok(
+            HttpResponse::BadRequest()
+              .json(json!({"code": "cover_asset_not_found"})),
+          );
+        }
+      }
+    }
+    (None, Field::Missing) => post.image,
+    (None, Field::Present(None)) => None,
+    (None, Field::Present(image)) => image.to_owned(),
+  };
+
   let updated = sqlx::query_as::<_, BlogPost>(
     "UPDATE blog_posts SET \
        title = $1, slug = $2, visibility = $3, tags = $4, \
-       description = $5, body = $6, published_at = $7 \
-     WHERE id = $8 RETURNING *",
+       description = $5, body = $6, published_at = $7, image = $8 \
+     WHERE id = $9 RETURNING *",
   )
   .bind(title)
   .bind(slug)
@@ -253,10 +490,19 @@ async fn update_post(
   .bind(description)
   .bind(body_text)
   .bind(published_at)
+  .bind(image)
   .bind(post.id)
   .fetch_one(&state.db)
   .await;
 
+  if let Ok(post) = &updated {
+    record_slug_change(&state, &post.id, &previous_slug, &post.slug).await;
+
+    if first_publish {
+      tokio::spawn(deliver_post(web::Data::clone(&state), post.clone()));
+    }
+  }
+
   match updated {
     Err(_) => Ok(
       HttpResponse::NotFound().json(json!({"code": "post_not_found"})),
@@ -271,23 +517,35 @@ async fn update_post(
             "visibility": post.visibility,
             "tags": post.tags,
             "body": post.body,
+            "author_id": post.author_id,
             "created_at": post.created_at,
             "published_at": post.published_at,
-        }
+        },
+        "missing_embeds": missing_embeds(&state, post.body.as_deref()).await,
     }))),
   }
 }
 
-#[delete("/posts/{id}")]
+#[delete("/{id}")]
 async fn delete_post(
+  req: HttpRequest,
   id: web::Path<String>,
   state: web::Data<ServerState>,
 ) -> Result<HttpResponse, Error> {
-  let result =
-    sqlx::query("UPDATE blog_posts SET visibility = 'deleted' WHERE id = $1")
-      .bind(id.to_string())
-      .execute(&state.db)
-      .await;
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+
+  if let Err(response) = find_editable_post(&state, &user, &id).await {
+    return Ok(response);
+  }
+
+  // Moves the post to the trash; see `trash::purge_post` for the rest.
+  let result = sqlx::query(
+    "UPDATE blog_posts SET visibility = 'deleted', deleted_at = now() \
+     WHERE id = $1",
+  )
+  .bind(id.to_string())
+  .execute(&state.db)
+  .await;
 
   match result {
     Ok(result) if result.rows_affected() > 0 => {
@@ -0,0 +1,206 @@
+use actix_web::{
+  delete, get, http::Error, post, web, HttpMessage, HttpRequest,
+  HttpResponse,
+};
+use chrono::{Duration, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use redis::aio::ConnectionManager;
+use serde_json::json;
+
+use crate::{
+  connectivity::valkey::ValkeyManager,
+  services::blog::posts::find_editable_post,
+  structs::blog::{BlogAdminUser, BlogPreviewCreate, BlogPreviewToken},
+  ServerState,
+};
+
+const DEFAULT_PREVIEW_TTL: i64 = 7 * 24 * 60 * 60;
+const MAX_PREVIEW_TTL: i64 = 30 * 24 * 60 * 60;
+
+/// Resolve a preview token to its record. The token is the record's
+/// random id; links from before that carry a trailing `.{signature}`,
+/// which is ignored. Returns `None` for anything unknown, revoked or
+/// expired.
+pub async fn verify_preview_token(
+  valkey: &mut ValkeyManager,
+  token: &str,
+) -> Option<BlogPreviewToken> {
+  let id = token.split_once('.').map_or(token, |(id, _)| id);
+
+  let stored = redis::cmd("GET")
+    .arg(format!("blog_preview/{}", id))
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await
+    .ok()?;
+  let record = serde_json::from_str::<BlogPreviewToken>(&stored).ok()?;
+
+  if record.expires_at <= Utc::now().naive_utc() {
+    return None;
+  }
+
+  Some(record)
+}
+
+fn preview_json(record: &BlogPreviewToken) -> serde_json::Value {
+  json!({
+    "id": record.id,
+    "token": record.id,
+    "post_id": record.post_id,
+    "created_by": record.created_by,
+    "created_at": record.created_at,
+    "expires_at": record.expires_at,
+  })
+}
+
+#[post("/{id}/previews")]
+async fn create_preview(
+  req: HttpRequest,
+  post_id: web::Path<String>,
+  state: web::Data<ServerState>,
+  body: Option<web::Json<BlogPreviewCreate>>,
+) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+  let valkey = &mut state.valkey.clone();
+
+  let expires_in = body
+    .and_then(|body| body.expires_in)
+    .unwrap_or(DEFAULT_PREVIEW_TTL);
+  if expires_in <= 0 || expires_in > MAX_PREVIEW_TTL {
+    return Ok(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "invalid_preview_expiry"})),
+    );
+  }
+
+  let post = match find_editable_post(&state, &user, &post_id).await {
+    Ok(post) => post,
+    Err(response) => return Ok(response),
+  };
+
+  let now = Utc::now().naive_utc();
+  let record = BlogPreviewToken {
+    id: rand::thread_rng()
+      .sample_iter(&Alphanumeric)
+      .take(32)
+      .map(char::from)
+      .collect(),
+    post_id: post.id.clone(),
+    created_by: user.id,
+    created_at: now,
+    expires_at: now + Duration::seconds(expires_in),
+  };
+
+  let stored = redis::cmd("SET")
+    .arg(format!("blog_preview/{}", record.id))
+    .arg(serde_json::to_string(&record).unwrap())
+    .arg("EX")
+    .arg(expires_in)
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await;
+
+  if stored.is_err() {
+    return Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_store_preview"})),
+    );
+  }
+
+  let _ = redis::cmd("SADD")
+    .arg(format!("blog_post_previews/{}", post.id))
+    .arg(&record.id)
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await;
+
+  Ok(
+    HttpResponse::Created()
+      .json(json!({"preview": preview_json(&record)})),
+  )
+}
+
+#[get("/{id}/previews")]
+async fn get_previews(
+  req: HttpRequest,
+  post_id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+  if let Err(response) = find_editable_post(&state, &user, &post_id).await
+  {
+    return Ok(response);
+  }
+
+  let valkey = &mut state.valkey.clone();
+  let index_key = format!("blog_post_previews/{}", post_id);
+
+  let ids = redis::cmd("SMEMBERS")
+    .arg(&index_key)
+    .query_async::<ConnectionManager, Vec<String>>(&mut valkey.cm)
+    .await
+    .unwrap_or_default();
+
+  let mut previews: Vec<BlogPreviewToken> = Vec::new();
+  for id in ids {
+    let stored = redis::cmd("GET")
+      .arg(format!("blog_preview/{}", id))
+      .query_async::<ConnectionManager, String>(&mut valkey.cm)
+      .await
+      .ok()
+      .and_then(|stored| {
+        serde_json::from_str::<BlogPreviewToken>(&stored).ok()
+      });
+
+    match stored {
+      Some(record) => previews.push(record),
+      // The record expired out of valkey, drop it from the index too.
+      None => {
+        let _ = redis::cmd("SREM")
+          .arg(&index_key)
+          .arg(&id)
+          .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+          .await;
+      }
+    }
+  }
+
+  previews.sort_by_key(|preview| preview.created_at);
+
+  let previews: Vec<serde_json::Value> =
+    previews.iter().map(preview_json).collect();
+
+  Ok(HttpResponse::Ok().json(json!({"previews": previews})))
+}
+
+#[delete("/{id}/previews/{preview_id}")]
+async fn revoke_preview(
+  req: HttpRequest,
+  params: web::Path<(String, String)>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let (post_id, preview_id) = params.into_inner();
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+  if let Err(response) = find_editable_post(&state, &user, &post_id).await
+  {
+    return Ok(response);
+  }
+  let valkey = &mut state.valkey.clone();
+
+  let removed = redis::cmd("SREM")
+    .arg(format!("blog_post_previews/{}", post_id))
+    .arg(&preview_id)
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await
+    .unwrap_or(0);
+
+  if removed == 0 {
+    return Ok(
+      HttpResponse::NotFound().json(json!({"code": "preview_not_found"})),
+    );
+  }
+
+  let _ = redis::cmd("DEL")
+    .arg(format!("blog_preview/{}", preview_id))
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await;
+
+  Ok(HttpResponse::NoContent().finish())
+}
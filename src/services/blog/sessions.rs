@@ -0,0 +1,466 @@
+use actix_web::{
+  delete, get, http::header, http::Error, web, HttpMessage, HttpRequest,
+  HttpResponse,
+};
+use chrono::{NaiveDateTime, Utc};
+use envconfig::Envconfig;
+use rand::{distributions::Alphanumeric, Rng};
+use redis::aio::ConnectionManager;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{
+  config::Config,
+  connectivity::valkey::ValkeyManager,
+  helpers::client_ip::client_ip,
+  structs::blog::{BlogAdminIntSession, BlogAdminSession},
+  ServerState,
+};
+
+/// Public identifier for a session, so tokens never leave the session
+/// they belong to.
+pub fn session_id(token: &str) -> String {
+  hex::encode(Sha256::digest(token.as_bytes()))[..16].to_string()
+}
+
+const SESSION_PREFIX: &str = "blog_admin_session/";
+
+/// Key a session's record is stored under.
+fn session_key(token: &str) -> String {
+  format!("{}{}", SESSION_PREFIX, token)
+}
+
+/// Key of the set of a user's session tokens.
+fn index_key(user_id: &str) -> String {
+  format!("blog_admin_sessions/{}", user_id)
+}
+
+fn client_details(req: &HttpRequest) -> (Option<String>, Option<String>) {
+  let user_agent = req
+    .headers()
+    .get(header::USER_AGENT)
+    .and_then(|value| value.to_str().ok())
+    .map(|value| value.to_string());
+  let ip = client_ip(req);
+
+  (user_agent, ip)
+}
+
+/// Write a session record. With `existing`, only an existing record is
+/// overwritten, so a request still in flight when its session is revoked
+/// can't bring it back.
+async fn store_session(
+  valkey: &mut ValkeyManager,
+  token: &str,
+  session: &BlogAdminSession,
+  existing: bool,
+) -> bool {
+  let config = Config::init_from_env().unwrap();
+
+  let stored = redis::cmd("SET")
+    .arg(session_key(token))
+    .arg(serde_json::to_string(session).unwrap())
+    .arg(if existing { "XX" } else { "NX" })
+    .arg("EX")
+    .arg(config.blog_session_ttl)
+    .query_async::<ConnectionManager, Option<String>>(&mut valkey.cm)
+    .await;
+
+  if !matches!(stored, Ok(Some(_))) {
+    return false;
+  }
+
+  let _ = redis::cmd("SADD")
+    .arg(index_key(&session.user_id))
+    .arg(token)
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await;
+
+  true
+}
+
+/// Issue a new session for a user, returning its token.
+pub async fn create_session(
+  valkey: &mut ValkeyManager,
+  user_id: &str,
+  req: &HttpRequest,
+) -> Option<String> {
+  let token: String = rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(96)
+    .map(char::from)
+    .collect();
+
+  let (user_agent, ip) = client_details(req);
+  let now = Utc::now().naive_utc();
+  let session = BlogAdminSession {
+    user_id: user_id.to_string(),
+    user_agent,
+    ip,
+    created_at: Some(now),
+    last_seen: Some(now),
+  };
+
+  if store_session(valkey, &token, &session, false).await {
+    Some(token)
+  } else {
+    None
+  }
+}
+
+/// Record activity on a session and push its expiry out again.
+pub async fn touch_session(
+  valkey: &mut ValkeyManager,
+  token: &str,
+  mut session: BlogAdminSession,
+  req: &HttpRequest,
+) {
+  let (user_agent, ip) = client_details(req);
+  refresh_session(&mut session, user_agent, ip, Utc::now().naive_utc());
+
+  store_session(valkey, token, &session, true).await;
+}
+
+/// Bring a session up to date with the request using it. Sessions issued
+/// before expiry tracking get their first sighting as their creation time.
+fn refresh_session(
+  session: &mut BlogAdminSession,
+  user_agent: Option<String>,
+  ip: Option<String>,
+  now: NaiveDateTime,
+) {
+  session.user_agent = user_agent;
+  session.ip = ip;
+  session.created_at = session.created_at.or(Some(now));
+  session.last_seen = Some(now);
+}
+
+/// Add sessions issued before the per-user index existed to it, so they
+/// can be listed and revoked like any other. Only runs once per valkey.
+pub async fn index_legacy_sessions(valkey: &mut ValkeyManager) {
+  let first_run = redis::cmd("SET")
+    .arg("blog_admin_sessions_indexed")
+    .arg(Utc::now().timestamp())
+    .arg("NX")
+    .query_async::<ConnectionManager, Option<String>>(&mut valkey.cm)
+    .await;
+  if !matches!(first_run, Ok(Some(_))) {
+    return;
+  }
+
+  let mut cursor: u64 = 0;
+  let mut indexed = 0;
+  loop {
+    let (next, keys) = match redis::cmd("SCAN")
+      .arg(cursor)
+      .arg("MATCH")
+      .arg(format!("{}*", SESSION_PREFIX))
+      .arg("COUNT")
+      .arg(100)
+      .query_async::<ConnectionManager, (u64, Vec<String>)>(&mut valkey.cm)
+      .await
+    {
+      Ok(page) => page,
+      Err(error) => {
+        tracing::warn!("Failed to scan blog admin sessions: {}", error);
+        return;
+      }
+    };
+
+    for key in keys {
+      let session = redis::cmd("GET")
+        .arg(&key)
+        .query_async::<ConnectionManager, String>(&mut valkey.cm)
+        .await
+        .ok()
+        .and_then(|stored| {
+          serde_json::from_str::<BlogAdminSession>(&stored).ok()
+        });
+      let (Some(session), Some(token)) =
+        (session, key.strip_prefix(SESSION_PREFIX))
+      else {
+        continue;
+      };
+
+      let _ = redis::cmd("SADD")
+        .arg(index_key(&session.user_id))
+        .arg(token)
+        .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+        .await;
+      indexed += 1;
+    }
+
+    if next == 0 {
+      break;
+    }
+    cursor = next;
+  }
+
+  tracing::info!("Indexed {} existing blog admin sessions", indexed);
+}
+
+async fn load_sessions(
+  valkey: &mut ValkeyManager,
+  user_id: &str,
+) -> Vec<(String, BlogAdminSession)> {
+  let index_key = index_key(user_id);
+
+  let tokens = redis::cmd("SMEMBERS")
+    .arg(&index_key)
+    .query_async::<ConnectionManager, Vec<String>>(&mut valkey.cm)
+    .await
+    .unwrap_or_default();
+
+  let mut sessions = Vec::new();
+  for token in tokens {
+    let session = redis::cmd("GET")
+      .arg(session_key(&token))
+      .query_async::<ConnectionManager, String>(&mut valkey.cm)
+      .await
+      .ok()
+      .and_then(|stored| {
+        serde_json::from_str::<BlogAdminSession>(&stored).ok()
+      });
+
+    match session {
+      Some(session) => sessions.push((token, session)),
+      // Expired out of valkey, drop it from the index as well.
+      None => {
+        let _ = redis::cmd("SREM")
+          .arg(&index_key)
+          .arg(&token)
+          .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+          .await;
+      }
+    }
+  }
+
+  sessions
+}
+
+/// Remove a single session token.
+pub async fn revoke_session(
+  valkey: &mut ValkeyManager,
+  user_id: &str,
+  token: &str,
+) {
+  let _ = redis::cmd("DEL")
+    .arg(session_key(token))
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await;
+
+  let _ = redis::cmd("SREM")
+    .arg(index_key(user_id))
+    .arg(token)
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await;
+}
+
+/// Remove every session for a user, optionally keeping one token alive.
+pub async fn revoke_sessions(
+  valkey: &mut ValkeyManager,
+  user_id: &str,
+  except: Option<&str>,
+) -> usize {
+  let sessions = load_sessions(valkey, user_id).await;
+
+  let mut revoked = 0;
+  for (token, _) in sessions {
+    if Some(token.as_str()) == except {
+      continue;
+    }
+
+    revoke_session(valkey, user_id, &token).await;
+    revoked += 1;
+  }
+
+  revoked
+}
+
+#[get("")]
+async fn get_sessions(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let current = req
+    .extensions()
+    .get::<BlogAdminIntSession>()
+    .unwrap()
+    .clone();
+  let valkey = &mut state.valkey.clone();
+
+  let sessions = load_sessions(valkey, &current.user_id).await;
+  let sessions = list_sessions(sessions, &current.token);
+
+  Ok(HttpResponse::Ok().json(json!({"sessions": sessions})))
+}
+
+/// Sessions as listed to their user, most recently used first, with the
+/// one making the request marked. Tokens are swapped for their ids.
+fn list_sessions(
+  mut sessions: Vec<(String, BlogAdminSession)>,
+  current: &str,
+) -> Vec<serde_json::Value> {
+  sessions
+    .sort_by_key(|(_, session)| std::cmp::Reverse(session.last_seen));
+
+  sessions
+    .iter()
+    .map(|(token, session)| {
+      json!({
+        "id": session_id(token),
+        "current": token == current,
+        "user_agent": session.user_agent,
+        "ip": session.ip,
+        "created_at": session.created_at,
+        "last_seen": session.last_seen,
+      })
+    })
+    .collect()
+}
+
+#[delete("/{id}")]
+async fn delete_session(
+  req: HttpRequest,
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let current = req
+    .extensions()
+    .get::<BlogAdminIntSession>()
+    .unwrap()
+    .clone();
+  let valkey = &mut state.valkey.clone();
+
+  let sessions = load_sessions(valkey, &current.user_id).await;
+  let session = sessions
+    .into_iter()
+    .find(|(token, _)| session_id(token) == id.as_str());
+
+  match session {
+    Some((token, _)) => {
+      revoke_session(valkey, &current.user_id, &token).await;
+      Ok(HttpResponse::NoContent().finish())
+    }
+    None => Ok(
+      HttpResponse::NotFound().json(json!({"code": "session_not_found"})),
+    ),
+  }
+}
+
+#[delete("")]
+async fn delete_other_sessions(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let current = req
+    .extensions()
+    .get::<BlogAdminIntSession>()
+    .unwrap()
+    .clone();
+  let valkey = &mut state.valkey.clone();
+
+  let revoked =
+    revoke_sessions(valkey, &current.user_id, Some(&current.token)).await;
+
+  Ok(HttpResponse::Ok().json(json!({"revoked": revoked})))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn at(time: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(time, "%F %T").unwrap()
+  }
+
+  fn session(last_seen: &str) -> BlogAdminSession {
+    BlogAdminSession {
+      user_id: "1".to_string(),
+      user_agent: None,
+      ip: None,
+      created_at: Some(at("2024-01-01 00:00:00")),
+      last_seen: Some(at(last_seen)),
+    }
+  }
+
+  #[test]
+  fn session_ids_are_stable_and_hide_the_token() {
+    let token = "a".repeat(96);
+    let id = session_id(&token);
+
+    assert_eq!(id, session_id(&token));
+    assert_eq!(id.len(), 16);
+    assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    assert!(!token.contains(&id));
+    assert_ne!(id, session_id(&"b".repeat(96)));
+  }
+
+  #[test]
+  fn legacy_indexing_only_finds_session_records() {
+    let pattern = format!("{}*", SESSION_PREFIX);
+    let prefix = pattern.trim_end_matches('*');
+
+    assert!(session_key("token").starts_with(prefix));
+    assert!(!index_key("1").starts_with(prefix));
+    assert_eq!(
+      session_key("token").strip_prefix(SESSION_PREFIX),
+      Some("token")
+    );
+  }
+
+  #[test]
+  fn refreshing_keeps_the_creation_time() {
+    let mut refreshed = session("2024-01-02 00:00:00");
+    let now = at("2024-01-03 12:00:00");
+    refresh_session(
+      &mut refreshed,
+      Some("Firefox".to_string()),
+      Some("203.0.113.9".to_string()),
+      now,
+    );
+
+    assert_eq!(refreshed.created_at, Some(at("2024-01-01 00:00:00")));
+    assert_eq!(refreshed.last_seen, Some(now));
+    assert_eq!(refreshed.user_agent.as_deref(), Some("Firefox"));
+    assert_eq!(refreshed.ip.as_deref(), Some("203.0.113.9"));
+  }
+
+  #[test]
+  fn refreshing_dates_legacy_sessions() {
+    let mut legacy =
+      serde_json::from_str::<BlogAdminSession>(r#"{"user_id": "1"}"#)
+        .unwrap();
+    let now = at("2024-01-03 12:00:00");
+    refresh_session(&mut legacy, None, None, now);
+
+    assert_eq!(legacy.created_at, Some(now));
+    assert_eq!(legacy.last_seen, Some(now));
+  }
+
+  #[test]
+  fn lists_recent_sessions_first_without_tokens() {
+    let sessions = vec![
+      ("old".to_string(), session("2024-01-02 00:00:00")),
+      ("current".to_string(), session("2024-01-04 00:00:00")),
+      ("recent".to_string(), session("2024-01-03 00:00:00")),
+    ];
+    let listed = list_sessions(sessions, "current");
+
+    let ids: Vec<&str> = listed
+      .iter()
+      .map(|session| session["id"].as_str().unwrap())
+      .collect();
+    assert_eq!(
+      ids,
+      [
+        session_id("current"),
+        session_id("recent"),
+        session_id("old")
+      ]
+    );
+    assert_eq!(listed[0]["current"], true);
+    assert_eq!(listed[1]["current"], false);
+    assert!(!serde_json::to_string(&listed).unwrap().contains("recent"));
+  }
+}
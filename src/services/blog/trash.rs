@@ -0,0 +1,297 @@
+use actix_web::{delete, get, http::Error, post, web, HttpResponse};
+use envconfig::Envconfig;
+use serde_json::json;
+
+use crate::{
+  config::Config,
+  services::blog::assets::delete_asset_objects,
+  structs::blog::{BlogAsset, BlogPost, BlogPurgedAsset},
+  ServerState,
+};
+
+/// What purging a post does with one of its assets.
+#[derive(Debug, PartialEq)]
+enum AssetPurge {
+  /// Nothing else needs the file, so it goes from S3 with its row.
+  DeleteFile,
+  /// Another post uses the file without a row of its own, so the row moves
+  /// to that post and stays listed.
+  HandOver,
+  /// Another post's row already covers the file, so only this row goes.
+  DropRow,
+}
+
+fn asset_purge(asset: &BlogPurgedAsset) -> AssetPurge {
+  match (asset.covered, asset.used_elsewhere) {
+    (true, _) => AssetPurge::DropRow,
+    (false, true) => AssetPurge::HandOver,
+    (false, false) => AssetPurge::DeleteFile,
+  }
+}
+
+/// Permanently remove a trashed post and its assets. Files still used by
+/// another post, through its body, cover or an asset of its own with the
+/// same hash, stay in S3, and their rows go to a post that uses them when
+/// no other row covers them. Stops without touching the database if any
+/// original can't be removed from S3, so a later purge can try again.
+pub(crate) async fn purge_post(
+  state: &ServerState,
+  post: &BlogPost,
+) -> Result<(), &'static str> {
+  let assets = sqlx::query_as::<_, BlogPurgedAsset>(
+    "SELECT a.*, EXISTS ( \
+       SELECT 1 FROM blog_posts p WHERE p.id <> a.post_id \
+       AND (strpos(COALESCE(p.body, ''), a.hash) > 0 \
+         OR strpos(COALESCE(p.image, ''), a.hash) > 0) \
+     ) AS used_elsewhere, EXISTS ( \
+       SELECT 1 FROM blog_assets o \
+       WHERE o.hash = a.hash AND o.post_id <> a.post_id \
+     ) AS covered \
+     FROM blog_assets a WHERE a.post_id = $1",
+  )
+  .bind(&post.id)
+  .fetch_all(&state.db)
+  .await
+  .map_err(|_| "failed_to_lookup_assets")?;
+
+  for purged in &assets {
+    if asset_purge(purged) == AssetPurge::DeleteFile
+      && !delete_asset_objects(&state.s3, &purged.asset).await
+    {
+      return Err("failed_to_delete_from_s3");
+    }
+  }
+
+  // The post using the file is looked up again, in case it changed since.
+  let handed_over: Vec<String> = assets
+    .iter()
+    .filter(|purged| asset_purge(purged) == AssetPurge::HandOver)
+    .map(|purged| purged.asset.hash.clone())
+    .collect();
+  sqlx::query(
+    "UPDATE blog_assets a SET post_id = ( \
+       SELECT p.id FROM blog_posts p WHERE p.id <> a.post_id \
+       AND (strpos(COALESCE(p.body, ''), a.hash) > 0 \
+         OR strpos(COALESCE(p.image, ''), a.hash) > 0) \
+       ORDER BY p.created_at ASC LIMIT 1 \
+     ) WHERE a.post_id = $1 AND a.hash = ANY($2) AND EXISTS ( \
+       SELECT 1 FROM blog_posts p WHERE p.id <> a.post_id \
+       AND (strpos(COALESCE(p.body, ''), a.hash) > 0 \
+         OR strpos(COALESCE(p.image, ''), a.hash) > 0) \
+     )",
+  )
+  .bind(&post.id)
+  .bind(&handed_over)
+  .execute(&state.db)
+  .await
+  .map_err(|_| "failed_to_delete_assets")?;
+
+  sqlx::query("DELETE FROM blog_assets WHERE post_id = $1")
+    .bind(&post.id)
+    .execute(&state.db)
+    .await
+    .map_err(|_| "failed_to_delete_assets")?;
+
+  sqlx::query("DELETE FROM blog_posts WHERE id = $1")
+    .bind(&post.id)
+    .execute(&state.db)
+    .await
+    .map_err(|_| "failed_to_delete_post")?;
+
+  Ok(())
+}
+
+async fn find_trashed_post(
+  state: &ServerState,
+  id: &str,
+) -> Option<BlogPost> {
+  sqlx::query_as::<_, BlogPost>(
+    "SELECT * FROM blog_posts WHERE id = $1 AND visibility = 'deleted' \
+     LIMIT 1",
+  )
+  .bind(id)
+  .fetch_optional(&state.db)
+  .await
+  .ok()
+  .flatten()
+}
+
+#[get("")]
+async fn get_trash(
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let config = Config::init_from_env().unwrap();
+  let retention =
+    chrono::Duration::days(config.blog_trash_retention_days as i64);
+
+  let posts = sqlx::query_as::<_, BlogPost>(
+    "SELECT * FROM blog_posts WHERE visibility = 'deleted' \
+     ORDER BY deleted_at DESC",
+  )
+  .fetch_all(&state.db)
+  .await;
+
+  match posts {
+    Ok(posts) => {
+      let posts: Vec<serde_json::Value> = posts
+        .iter()
+        .map(|post| {
+          json!({
+            "id": post.id,
+            "slug": post.slug,
+            "title": post.title,
+            "author_id": post.author_id,
+            "created_at": post.created_at,
+            "deleted_at": post.deleted_at,
+            "purge_at": post.deleted_at.map(|deleted_at| deleted_at + retention),
+          })
+        })
+        .collect();
+
+      Ok(HttpResponse::Ok().json(json!({"posts": posts})))
+    }
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_lookup_posts"})),
+    ),
+  }
+}
+
+#[post("/{id}/restore")]
+async fn restore_post(
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  // Restored posts come back as drafts, so nothing is republished by
+  // accident.
+  let post = sqlx::query_as::<_, BlogPost>(
+    "UPDATE blog_posts SET visibility = 'draft', deleted_at = NULL \
+     WHERE id = $1 AND visibility = 'deleted' RETURNING *",
+  )
+  .bind(id.to_string())
+  .fetch_optional(&state.db)
+  .await;
+
+  match post {
+    Ok(Some(post)) => Ok(HttpResponse::Ok().json(json!({
+      "post": {
+        "id": post.id,
+        "slug": post.slug,
+        "title": post.title,
+        "visibility": post.visibility,
+      }
+    }))),
+    Ok(None) => {
+      Ok(HttpResponse::NotFound().json(json!({"code": "post_not_found"})))
+    }
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_restore_post"})),
+    ),
+  }
+}
+
+#[delete("/{id}")]
+async fn purge_trashed_post(
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let post = match find_trashed_post(&state, &id).await {
+    Some(post) => post,
+    None => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "post_not_found"})),
+      );
+    }
+  };
+
+  match purge_post(&state, &post).await {
+    Ok(()) => Ok(HttpResponse::NoContent().finish()),
+    Err(code) => {
+      Ok(HttpResponse::InternalServerError().json(json!({"code": code})))
+    }
+  }
+}
+
+#[get("/orphaned")]
+async fn get_orphaned_assets(
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  // An asset counts as referenced if its hash appears in the body or cover
+  // image of any post, trashed ones included since they can be restored.
+  let assets = sqlx::query_as::<_, BlogAsset>(
+    "SELECT a.* FROM blog_assets a WHERE NOT EXISTS ( \
+       SELECT 1 FROM blog_posts p \
+       WHERE strpos(COALESCE(p.body, ''), a.hash) > 0 \
+          OR strpos(COALESCE(p.image, ''), a.hash) > 0 \
+     ) ORDER BY a.upload_date ASC",
+  )
+  .fetch_all(&state.db)
+  .await;
+
+  match assets {
+    Ok(assets) => {
+      let total_size: i64 =
+        assets.iter().map(|asset| asset.file_size as i64).sum();
+      let assets: Vec<serde_json::Value> = assets
+        .iter()
+        .map(|asset| {
+          json!({
+            "hash": asset.hash,
+            "post_id": asset.post_id,
+            "file_type": asset.file_type,
+            "file_size": asset.file_size,
+            "upload_date": asset.upload_date,
+          })
+        })
+        .collect();
+
+      Ok(HttpResponse::Ok().json(json!({
+        "assets": assets,
+        "total_size": total_size,
+      })))
+    }
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_lookup_assets"})),
+    ),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn purged(used_elsewhere: bool, covered: bool) -> BlogPurgedAsset {
+    BlogPurgedAsset {
+      asset: BlogAsset {
+        hash: "abc123".to_string(),
+        post_id: "1".to_string(),
+        file_type: "png".to_string(),
+        file_size: 1024,
+        upload_date: chrono::NaiveDateTime::default(),
+        width: None,
+        height: None,
+        variants: Vec::new(),
+      },
+      used_elsewhere,
+      covered,
+    }
+  }
+
+  #[test]
+  fn deletes_files_nothing_else_needs() {
+    assert_eq!(asset_purge(&purged(false, false)), AssetPurge::DeleteFile);
+  }
+
+  #[test]
+  fn hands_files_used_elsewhere_to_that_post() {
+    assert_eq!(asset_purge(&purged(true, false)), AssetPurge::HandOver);
+  }
+
+  #[test]
+  fn only_drops_rows_another_post_covers() {
+    assert_eq!(asset_purge(&purged(false, true)), AssetPurge::DropRow);
+    assert_eq!(asset_purge(&purged(true, true)), AssetPurge::DropRow);
+  }
+}
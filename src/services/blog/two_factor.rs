@@ -0,0 +1,406 @@
+use actix_web::{
+  delete, http::Error, post, web, HttpMessage, HttpRequest, HttpResponse,
+};
+use chrono::Utc;
+use envconfig::Envconfig;
+use rand::{distributions::Alphanumeric, Rng};
+use redis::aio::ConnectionManager;
+use serde_json::json;
+
+use crate::{
+  config::Config,
+  connectivity::valkey::ValkeyManager,
+  helpers::{
+    client_ip::client_ip,
+    limiter::{locked_out_response, AuthLimiter},
+    totp::{base32_decode, base32_encode, provisioning_uri, verify_totp},
+  },
+  services::blog::{
+    auth::{hash_password, session_response},
+    sessions::create_session,
+  },
+  structs::blog::{
+    BlogAdminUser, BlogLoginChallenge, BlogLoginChallengeRequest,
+    BlogTotpConfirm, BlogTotpDisable,
+  },
+  ServerState,
+};
+
+pub const LOGIN_CHALLENGE_TTL: u64 = 5 * 60;
+const LOGIN_CHALLENGE_ATTEMPTS: u32 = 5;
+const TOTP_ENROLMENT_TTL: u64 = 10 * 60;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+fn random_string(length: usize) -> String {
+  rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(length)
+    .map(char::from)
+    .collect()
+}
+
+/// Recovery codes in `xxxxx-xxxxx` form, lowercased so they are easy to
+/// read back and type.
+fn generate_recovery_codes() -> Vec<String> {
+  (0..RECOVERY_CODE_COUNT)
+    .map(|_| {
+      let code = random_string(10).to_lowercase();
+      format!("{}-{}", &code[..5], &code[5..])
+    })
+    .collect()
+}
+
+/// Start the second login step for a user whose password checked out.
+pub async fn create_login_challenge(
+  valkey: &mut ValkeyManager,
+  user_id: &str,
+) -> Option<String> {
+  let token = random_string(64);
+  let challenge = BlogLoginChallenge {
+    user_id: user_id.to_string(),
+  };
+
+  redis::cmd("SET")
+    .arg(format!("blog_admin_login_challenge/{}", token))
+    .arg(serde_json::to_string(&challenge).unwrap())
+    .arg("EX")
+    .arg(LOGIN_CHALLENGE_TTL)
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await
+    .ok()
+    .map(|_| token)
+}
+
+/// Check a TOTP code for an enrolled user. A step that was already used is
+/// rejected, so an observed code can't be replayed inside its window.
+async fn verify_user_totp(
+  valkey: &mut ValkeyManager,
+  user: &BlogAdminUser,
+  code: &str,
+  unix_time: u64,
+) -> bool {
+  let secret = match user.totp_secret.as_deref().and_then(base32_decode) {
+    Some(secret) => secret,
+    None => return false,
+  };
+
+  let step = match verify_totp(&secret, code, unix_time) {
+    Some(step) => step,
+    None => return false,
+  };
+
+  let step_key = format!("blog_admin_totp_step/{}", user.id);
+  let last_step = redis::cmd("GET")
+    .arg(&step_key)
+    .query_async::<ConnectionManager, u64>(&mut valkey.cm)
+    .await
+    .ok();
+
+  if last_step.is_some_and(|last_step| step <= last_step) {
+    return false;
+  }
+
+  let _ = redis::cmd("SET")
+    .arg(&step_key)
+    .arg(step)
+    .arg("EX")
+    .arg(120)
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await;
+
+  true
+}
+
+/// Consume a recovery code, removing its hash so it only works once.
+async fn use_recovery_code(
+  state: &ServerState,
+  user: &BlogAdminUser,
+  code: &str,
+) -> bool {
+  let code = code.trim().to_lowercase();
+
+  let hash = user.totp_recovery_codes.iter().find(|hash| {
+    argon2::verify_encoded(hash, code.as_bytes()).unwrap_or(false)
+  });
+
+  match hash {
+    Some(hash) => sqlx::query(
+      "UPDATE blog_admin_users SET totp_recovery_codes = \
+       array_remove(totp_recovery_codes, $1) WHERE id = $2",
+    )
+    .bind(hash)
+    .bind(&user.id)
+    .execute(&state.db)
+    .await
+    .is_ok_and(|result| result.rows_affected() > 0),
+    None => false,
+  }
+}
+
+#[post("/auth/challenge")]
+async fn complete_login(
+  req: HttpRequest,
+  body: web::Json<BlogLoginChallengeRequest>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let valkey = &mut state.valkey.clone();
+  let challenge_key =
+    format!("blog_admin_login_challenge/{}", body.challenge);
+
+  let challenge = redis::cmd("GET")
+    .arg(&challenge_key)
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await
+    .ok()
+    .and_then(|stored| {
+      serde_json::from_str::<BlogLoginChallenge>(&stored).ok()
+    });
+
+  let challenge = match challenge {
+    Some(challenge) => challenge,
+    None => {
+      return Ok(
+        HttpResponse::Unauthorized()
+          .json(json!({"code": "invalid_login_challenge"})),
+      );
+    }
+  };
+
+  let user = sqlx::query_as::<_, BlogAdminUser>(
+    "SELECT * FROM blog_admin_users WHERE id = $1 LIMIT 1",
+  )
+  .bind(&challenge.user_id)
+  .fetch_optional(&state.db)
+  .await;
+
+  let user = match user {
+    Ok(Some(user)) if !user.disabled => user,
+    _ => {
+      return Ok(
+        HttpResponse::Unauthorized()
+          .json(json!({"code": "invalid_login_challenge"})),
+      );
+    }
+  };
+
+  // Shares counters with the password step, so guessing codes across fresh
+  // challenges still runs into the lockout.
+  let limiter = AuthLimiter::new("blog")
+    .ip(client_ip(&req).as_deref())
+    .username(&user.username);
+
+  if let Some(retry_after) = limiter.locked_for(valkey).await {
+    return Ok(locked_out_response(retry_after));
+  }
+
+  // Each challenge only gets a handful of guesses before the password has
+  // to be entered again. Attempts are counted with INCR before the code is
+  // checked, so parallel guesses can't all slip in under the limit.
+  let attempts_key = format!("{}/attempts", challenge_key);
+  let attempts = redis::cmd("INCR")
+    .arg(&attempts_key)
+    .query_async::<ConnectionManager, u32>(&mut valkey.cm)
+    .await
+    .unwrap_or(u32::MAX);
+
+  let _ = redis::cmd("EXPIRE")
+    .arg(&attempts_key)
+    .arg(LOGIN_CHALLENGE_TTL)
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await;
+
+  if attempts > LOGIN_CHALLENGE_ATTEMPTS {
+    let _ = redis::cmd("DEL")
+      .arg(&challenge_key)
+      .arg(&attempts_key)
+      .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+      .await;
+
+    return Ok(
+      HttpResponse::Unauthorized()
+        .json(json!({"code": "invalid_login_challenge"})),
+    );
+  }
+
+  let unix_time = Utc::now().timestamp() as u64;
+  let valid = match (&body.code, &body.recovery_code) {
+    (Some(code), _) => {
+      verify_user_totp(valkey, &user, code, unix_time).await
+    }
+    (None, Some(code)) => use_recovery_code(&state, &user, code).await,
+    (None, None) => false,
+  };
+
+  if !valid {
+    limiter.record_failure(valkey).await;
+
+    if attempts >= LOGIN_CHALLENGE_ATTEMPTS {
+      let _ = redis::cmd("DEL")
+        .arg(&challenge_key)
+        .arg(&attempts_key)
+        .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+        .await;
+    }
+
+    return Ok(
+      HttpResponse::Unauthorized()
+        .json(json!({"code": "invalid_second_factor"})),
+    );
+  }
+
+  let _ = redis::cmd("DEL")
+    .arg(&challenge_key)
+    .arg(&attempts_key)
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await;
+
+  limiter.record_success(valkey).await;
+
+  match create_session(valkey, &user.id, &req).await {
+    Some(token) => {
+      Ok(HttpResponse::Ok().json(session_response(&user, &token)))
+    }
+    None => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_create_session"})),
+    ),
+  }
+}
+
+#[post("/totp")]
+async fn begin_totp_enrolment(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+  let config = Config::init_from_env().unwrap();
+  let valkey = &mut state.valkey.clone();
+
+  if user.totp_enabled {
+    return Ok(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "totp_already_enabled"})),
+    );
+  }
+
+  let secret: [u8; 20] = rand::thread_rng().gen();
+  let secret = base32_encode(&secret);
+
+  let stored = redis::cmd("SET")
+    .arg(format!("blog_admin_totp_pending/{}", user.id))
+    .arg(&secret)
+    .arg("EX")
+    .arg(TOTP_ENROLMENT_TTL)
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await;
+
+  if stored.is_err() {
+    return Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_start_enrolment"})),
+    );
+  }
+
+  Ok(HttpResponse::Ok().json(json!({
+    "totp": {
+      "secret": secret,
+      "uri": provisioning_uri(&config.blog_totp_issuer, &user.username, &secret),
+      "expires_in": TOTP_ENROLMENT_TTL,
+    }
+  })))
+}
+
+#[post("/totp/confirm")]
+async fn confirm_totp_enrolment(
+  req: HttpRequest,
+  body: web::Json<BlogTotpConfirm>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+  let valkey = &mut state.valkey.clone();
+  let pending_key = format!("blog_admin_totp_pending/{}", user.id);
+
+  let secret = redis::cmd("GET")
+    .arg(&pending_key)
+    .query_async::<ConnectionManager, String>(&mut valkey.cm)
+    .await;
+
+  let secret = match secret {
+    Ok(secret) => secret,
+    Err(_) => {
+      return Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "no_pending_enrolment"})),
+      );
+    }
+  };
+
+  let unix_time = Utc::now().timestamp() as u64;
+  let decoded = base32_decode(&secret).unwrap_or_default();
+  if verify_totp(&decoded, &body.code, unix_time).is_none() {
+    return Ok(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "invalid_totp_code"})),
+    );
+  }
+
+  let recovery_codes = generate_recovery_codes();
+  let recovery_hashes: Vec<String> = recovery_codes
+    .iter()
+    .map(|code| hash_password(code))
+    .collect();
+
+  let updated = sqlx::query(
+    "UPDATE blog_admin_users SET totp_secret = $1, totp_enabled = true, \
+     totp_recovery_codes = $2 WHERE id = $3",
+  )
+  .bind(&secret)
+  .bind(recovery_hashes)
+  .bind(&user.id)
+  .execute(&state.db)
+  .await;
+
+  if updated.is_err() {
+    return Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_enable_totp"})),
+    );
+  }
+
+  let _ = redis::cmd("DEL")
+    .arg(&pending_key)
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await;
+
+  // The plaintext codes are only ever shown here.
+  Ok(HttpResponse::Ok().json(json!({"recovery_codes": recovery_codes})))
+}
+
+#[delete("/totp")]
+async fn disable_totp(
+  req: HttpRequest,
+  body: web::Json<BlogTotpDisable>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let user = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+
+  let valid =
+    argon2::verify_encoded(&user.password, body.password.as_bytes())
+      .unwrap_or(false);
+  if !valid {
+    return Ok(
+      HttpResponse::Unauthorized()
+        .json(json!({"code": "current_password_invalid"})),
+    );
+  }
+
+  let _ = sqlx::query(
+    "UPDATE blog_admin_users SET totp_secret = NULL, totp_enabled = false, \
+     totp_recovery_codes = '{}' WHERE id = $1",
+  )
+  .bind(&user.id)
+  .execute(&state.db)
+  .await;
+
+  Ok(HttpResponse::NoContent().finish())
+}
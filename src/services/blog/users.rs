@@ -0,0 +1,188 @@
+use actix_web::{
+  delete, get, http::Error, patch, post, web, HttpMessage, HttpRequest,
+  HttpResponse,
+};
+use serde_json::json;
+
+use crate::{
+  services::blog::{auth::hash_password, sessions::revoke_sessions},
+  structs::blog::{
+    BlogAdminRole, BlogAdminUser, BlogUserAdminMutate, BlogUserCreate,
+  },
+  ServerState,
+};
+
+fn user_json(user: &BlogAdminUser) -> serde_json::Value {
+  json!({
+    "id": user.id,
+    "username": user.username,
+    "display_name": user.display_name,
+    "role": user.role,
+    "disabled": user.disabled,
+    "created_at": user.created_at,
+  })
+}
+
+#[get("")]
+async fn get_users(
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let users = sqlx::query_as::<_, BlogAdminUser>(
+    "SELECT * FROM blog_admin_users ORDER BY created_at ASC",
+  )
+  .fetch_all(&state.db)
+  .await;
+
+  match users {
+    Ok(users) => {
+      let users: Vec<serde_json::Value> =
+        users.iter().map(user_json).collect();
+      Ok(HttpResponse::Ok().json(json!({"users": users})))
+    }
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_lookup_users"})),
+    ),
+  }
+}
+
+#[post("")]
+async fn create_user(
+  state: web::Data<ServerState>,
+  body: web::Json<BlogUserCreate>,
+) -> Result<HttpResponse, Error> {
+  if body.username.trim().is_empty() || body.password.is_empty() {
+    return Ok(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "missing_username_or_password"})),
+    );
+  }
+
+  let role = body.role.unwrap_or(BlogAdminRole::Author);
+  let password = hash_password(&body.password);
+
+  let user = sqlx::query_as::<_, BlogAdminUser>(
+    "INSERT INTO blog_admin_users (username, display_name, password, role) \
+     VALUES ($1, $2, $3, $4) RETURNING *",
+  )
+  .bind(body.username.trim())
+  .bind(body.display_name.clone())
+  .bind(password)
+  .bind(role.as_str())
+  .fetch_one(&state.db)
+  .await;
+
+  match user {
+    Ok(user) => {
+      Ok(HttpResponse::Created().json(json!({"user": user_json(&user)})))
+    }
+    Err(sqlx::Error::Database(error)) if error.is_unique_violation() => {
+      Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "username_already_exists"})),
+      )
+    }
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_create_user"})),
+    ),
+  }
+}
+
+#[patch("/{id}")]
+async fn update_admin_user(
+  req: HttpRequest,
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+  body: web::Json<BlogUserAdminMutate>,
+) -> Result<HttpResponse, Error> {
+  let current = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+
+  let existing = sqlx::query_as::<_, BlogAdminUser>(
+    "SELECT * FROM blog_admin_users WHERE id = $1 LIMIT 1",
+  )
+  .bind(id.to_string())
+  .fetch_optional(&state.db)
+  .await;
+
+  let user = match existing {
+    Ok(Some(user)) => user,
+    _ => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "user_not_found"})),
+      );
+    }
+  };
+
+  // Owners can't demote or disable themselves, so there is always at least
+  // one account able to manage the rest.
+  let role = body.role.unwrap_or(user.role());
+  let disabled = body.disabled.unwrap_or(user.disabled);
+  if user.id == current.id && (role != user.role() || disabled) {
+    return Ok(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "cannot_modify_own_access"})),
+    );
+  }
+
+  let display_name = body.display_name.clone().or(user.display_name);
+
+  let updated = sqlx::query_as::<_, BlogAdminUser>(
+    "UPDATE blog_admin_users SET display_name = $1, role = $2, \
+     disabled = $3 WHERE id = $4 RETURNING *",
+  )
+  .bind(display_name)
+  .bind(role.as_str())
+  .bind(disabled)
+  .bind(user.id)
+  .fetch_one(&state.db)
+  .await;
+
+  match updated {
+    Ok(user) => {
+      if user.disabled {
+        let valkey = &mut state.valkey.clone();
+        revoke_sessions(valkey, &user.id, None).await;
+      }
+
+      Ok(HttpResponse::Ok().json(json!({"user": user_json(&user)})))
+    }
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_update_user"})),
+    ),
+  }
+}
+
+#[delete("/{id}")]
+async fn delete_admin_user(
+  req: HttpRequest,
+  id: web::Path<String>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let current = req.extensions().get::<BlogAdminUser>().unwrap().clone();
+
+  if id.as_str() == current.id {
+    return Ok(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "cannot_modify_own_access"})),
+    );
+  }
+
+  let result = sqlx::query("DELETE FROM blog_admin_users WHERE id = $1")
+    .bind(id.to_string())
+    .execute(&state.db)
+    .await;
+
+  match result {
+    Ok(result) if result.rows_affected() > 0 => {
+      let valkey = &mut state.valkey.clone();
+      revoke_sessions(valkey, &id, None).await;
+
+      Ok(HttpResponse::NoContent().finish())
+    }
+    _ => {
+      Ok(HttpResponse::NotFound().json(json!({"code": "user_not_found"})))
+    }
+  }
+}
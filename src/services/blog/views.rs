@@ -0,0 +1,119 @@
+use actix_web::{
+  get, http::header, http::Error, web, HttpRequest, HttpResponse,
+};
+use chrono::Utc;
+use redis::aio::ConnectionManager;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{
+  connectivity::valkey::ValkeyManager,
+  helpers::client_ip::client_ip,
+  structs::blog::{BlogPopularQuery, BlogPostWithViews},
+  ServerState,
+};
+
+/// Hash of `{post_id}/{day}` fields to views not yet written to Postgres.
+pub const VIEW_BUFFER_KEY: &str = "blog_post_view_buffer";
+
+/// Identifies a visitor for a single day without storing their IP. The date
+/// is part of the hash, so the same visitor can't be followed across days.
+fn visitor_hash(req: &HttpRequest, day: &str) -> String {
+  let ip = client_ip(req).unwrap_or_default();
+  let user_agent = req
+    .headers()
+    .get(header::USER_AGENT)
+    .and_then(|value| value.to_str().ok())
+    .unwrap_or_default();
+
+  hex::encode(Sha256::digest(format!("{}:{}:{}", day, ip, user_agent)))
+}
+
+/// Count a view of a post, at most once per visitor per day. Views are
+/// buffered in Valkey and written out by `modules::blog_views`.
+pub(crate) async fn record_view(
+  valkey: &mut ValkeyManager,
+  post_id: &str,
+  req: &HttpRequest,
+) {
+  let day = Utc::now().format("%Y-%m-%d").to_string();
+  let viewers_key = format!("blog_post_viewers/{}/{}", post_id, day);
+
+  let added = redis::cmd("SADD")
+    .arg(&viewers_key)
+    .arg(visitor_hash(req, &day))
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await
+    .unwrap_or(0);
+
+  if added == 0 {
+    return;
+  }
+
+  let _ = redis::cmd("EXPIRE")
+    .arg(&viewers_key)
+    .arg(2 * 24 * 60 * 60)
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await;
+
+  let _ = redis::cmd("HINCRBY")
+    .arg(VIEW_BUFFER_KEY)
+    .arg(format!("{}/{}", post_id, day))
+    .arg(1)
+    .query_async::<ConnectionManager, i64>(&mut valkey.cm)
+    .await;
+}
+
+#[get("/posts/popular")]
+async fn get_popular_posts(
+  query: Option<web::Query<BlogPopularQuery>>,
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let days = query
+    .as_ref()
+    .and_then(|query| query.days)
+    .unwrap_or(30)
+    .clamp(1, 365);
+  let limit = query
+    .as_ref()
+    .and_then(|query| query.limit)
+    .unwrap_or(10)
+    .clamp(1, 50);
+
+  let posts = sqlx::query_as::<_, BlogPostWithViews>(
+    "SELECT p.*, SUM(v.views)::bigint AS views FROM blog_posts p \
+     JOIN blog_post_views v ON v.post_id = p.id \
+     WHERE p.visibility = 'public' AND v.day > CURRENT_DATE - $1 \
+     GROUP BY p.id ORDER BY views DESC, p.published_at DESC LIMIT $2",
+  )
+  .bind(days)
+  .bind(limit)
+  .fetch_all(&state.db)
+  .await;
+
+  match posts {
+    Ok(posts) => {
+      let posts: Vec<serde_json::Value> = posts
+        .iter()
+        .map(|BlogPostWithViews { post, views }| {
+          json!({
+            "id": post.id,
+            "slug": post.slug,
+            "title": post.title,
+            "description": post.description,
+            "image": post.image,
+            "tags": post.tags,
+            "published_at": post.published_at,
+            "views": views,
+          })
+        })
+        .collect();
+
+      Ok(HttpResponse::Ok().json(json!({"days": days, "posts": posts})))
+    }
+    Err(_) => Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_lookup_posts"})),
+    ),
+  }
+}
@@ -8,7 +8,14 @@ use actix_web::{
 use actix_web_lab::middleware::Next;
 use serde_json::json;
 
-use crate::{helpers::authentication::is_management_authed, ServerState};
+use crate::{
+  helpers::{
+    authentication::is_management_authed,
+    client_ip::client_ip,
+    limiter::{locked_out_response, AuthLimiter},
+  },
+  ServerState,
+};
 
 pub async fn auth_middleware(
   req: ServiceRequest,
@@ -37,13 +44,23 @@ pub async fn auth_middleware(
 
       if let Ok(authed) = is_management_authed {
         if !authed {
-          return Ok(
-            ServiceResponse::new(
-              req.request().to_owned(),
+          // Lockouts are only looked at once a token has failed, so a
+          // valid token always gets through whatever else is being tried.
+          let limiter = AuthLimiter::new("management")
+            .ip(client_ip(req.request()).as_deref());
+
+          let response = match limiter.locked_for(valkey).await {
+            Some(retry_after) => locked_out_response(retry_after),
+            None => {
+              limiter.record_failure(valkey).await;
               HttpResponse::Unauthorized()
-                .json(json!({"code": "invalid_authentication"})),
-            )
-            .map_into_boxed_body(),
+                .json(json!({"code": "invalid_authentication"}))
+            }
+          };
+
+          return Ok(
+            ServiceResponse::new(req.request().to_owned(), response)
+              .map_into_boxed_body(),
           );
         }
       } else {
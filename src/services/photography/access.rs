@@ -0,0 +1,214 @@
+use actix_web::{
+  cookie::{time::Duration, Cookie, SameSite},
+  error::ErrorInternalServerError,
+  http::header::AUTHORIZATION,
+  post, web, Error, HttpRequest, HttpResponse,
+};
+use chrono::Utc;
+use envconfig::Envconfig;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+
+use crate::{
+  config::Config,
+  helpers::{
+    authentication::is_management_authed,
+    client_ip::client_ip,
+    limiter::{locked_out_response, AuthLimiter},
+  },
+  structs::photography::{Album, UnlockAlbumPayload},
+  ServerState,
+};
+
+/// Seconds an album stays unlocked for.
+const UNLOCK_TTL: i64 = 7 * 24 * 60 * 60;
+
+/// Header an unlock token can be sent in, for clients that don't keep
+/// cookies.
+const UNLOCK_HEADER: &str = "x-album-token";
+
+/// The album's password hash is part of the signature, so changing the
+/// password locks everyone out again. `None` when no
+/// `PHOTOGRAPHY_UNLOCK_SECRET` is configured, since tokens signed with an
+/// empty key could be forged by anyone.
+fn unlock_mac(album: &Album, expires_at: i64) -> Option<Hmac<Sha256>> {
+  let config = Config::init_from_env().unwrap();
+  if config.photography_unlock_secret.is_empty() {
+    return None;
+  }
+
+  let mut mac = Hmac::<Sha256>::new_from_slice(
+    config.photography_unlock_secret.as_bytes(),
+  )
+  .expect("HMAC can take key of any size");
+  mac.update(
+    format!(
+      "{}:{}:{}",
+      album.slug,
+      expires_at,
+      album.password.as_deref().unwrap_or_default()
+    )
+    .as_bytes(),
+  );
+
+  Some(mac)
+}
+
+/// Build an `{expires_at}.{signature}` unlock token for an album.
+fn sign_unlock_token(album: &Album, expires_at: i64) -> Option<String> {
+  let signature = unlock_mac(album, expires_at)?.finalize().into_bytes();
+  Some(format!("{}.{}", expires_at, hex::encode(signature)))
+}
+
+fn verify_unlock_token(album: &Album, token: &str) -> bool {
+  let verified = || -> Option<()> {
+    let (expires_at, signature) = token.split_once('.')?;
+    let expires_at = expires_at.parse::<i64>().ok()?;
+    if expires_at <= Utc::now().timestamp() {
+      return None;
+    }
+
+    let signature = hex::decode(signature).ok()?;
+    unlock_mac(album, expires_at)?.verify_slice(&signature).ok()
+  };
+
+  verified().is_some()
+}
+
+fn unlock_cookie_name(slug: &str) -> String {
+  format!("album_unlock_{}", slug)
+}
+
+/// Whether a request carries a valid unlock token for a password-protected
+/// album, either in the `X-Album-Token` header or the album's cookie.
+fn is_unlocked(req: &HttpRequest, album: &Album) -> bool {
+  let header = req
+    .headers()
+    .get(UNLOCK_HEADER)
+    .and_then(|value| value.to_str().ok())
+    .map(|value| value.to_string());
+  let cookie = req
+    .cookie(&unlock_cookie_name(&album.slug))
+    .map(|cookie| cookie.value().to_string());
+
+  header
+    .into_iter()
+    .chain(cookie)
+    .any(|token| verify_unlock_token(album, &token))
+}
+
+/// The response to send instead of an album the request may not see, if
+/// any. Public and unlisted albums are open to anyone with the slug.
+/// Drafts are only open to management, and password-protected albums to
+/// management or once unlocked.
+pub(crate) async fn deny_album_access(
+  state: &ServerState,
+  req: &HttpRequest,
+  album: &Album,
+) -> Option<HttpResponse> {
+  if album.visibility != "draft" && album.visibility != "password" {
+    return None;
+  }
+
+  let valkey = &mut state.valkey.clone();
+  let is_management_authed =
+    is_management_authed(valkey, req.headers().get(AUTHORIZATION))
+      .await
+      .unwrap_or(false);
+  if is_management_authed {
+    return None;
+  }
+
+  if album.visibility == "draft" {
+    return Some(HttpResponse::NotFound().finish());
+  }
+  if is_unlocked(req, album) {
+    return None;
+  }
+
+  Some(HttpResponse::Unauthorized().json(json!({
+    "code": "album_locked",
+    "album": {"slug": album.slug, "name": album.name},
+  })))
+}
+
+/// Trade an album's password for an unlock token. The token is returned
+/// in the body and set as a cookie.
+#[post("/albums/{slug}/unlock")]
+async fn unlock_album(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+  slug: web::Path<String>,
+  payload: web::Json<UnlockAlbumPayload>,
+) -> Result<HttpResponse, Error> {
+  let album = sqlx::query_as::<_, Album>(
+    "SELECT * FROM photography_albums WHERE slug = $1 LIMIT 1",
+  )
+  .bind(slug.into_inner())
+  .fetch_optional(&state.db)
+  .await
+  .map_err(|error| {
+    eprintln!("failed to get album from database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  let (album, password_hash) = match album {
+    Some(album) if album.visibility == "password" => {
+      let password_hash = album.password.clone().unwrap_or_default();
+      (album, password_hash)
+    }
+    _ => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "album_not_found"})),
+      );
+    }
+  };
+
+  // Signed up front so a missing secret fails before the password is
+  // checked, but only handed out once it has been.
+  let token =
+    match sign_unlock_token(&album, Utc::now().timestamp() + UNLOCK_TTL) {
+      Some(token) => token,
+      None => {
+        eprintln!(
+          "PHOTOGRAPHY_UNLOCK_SECRET is not set, refusing to unlock"
+        );
+        return Err(ErrorInternalServerError(
+          "album unlocking is unavailable",
+        ));
+      }
+    };
+
+  let valkey = &mut state.valkey.clone();
+  let limiter =
+    AuthLimiter::new("photography").ip(client_ip(&req).as_deref());
+
+  if let Some(retry_after) = limiter.locked_for(valkey).await {
+    return Ok(locked_out_response(retry_after));
+  }
+
+  let password = payload.password.as_bytes();
+  if !argon2::verify_encoded(&password_hash, password).unwrap_or(false) {
+    limiter.record_failure(valkey).await;
+    return Ok(
+      HttpResponse::Unauthorized()
+        .json(json!({"code": "invalid_album_password"})),
+    );
+  }
+
+  let cookie =
+    Cookie::build(unlock_cookie_name(&album.slug), token.clone())
+      .path("/")
+      .http_only(true)
+      .secure(true)
+      .same_site(SameSite::Lax)
+      .max_age(Duration::seconds(UNLOCK_TTL))
+      .finish();
+
+  Ok(
+    HttpResponse::Ok()
+      .cookie(cookie)
+      .json(json!({"token": token, "expires_in": UNLOCK_TTL})),
+  )
+}
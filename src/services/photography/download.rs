@@ -0,0 +1,211 @@
+use actix_web::{
+  error::ErrorInternalServerError,
+  get,
+  http::header::{ContentDisposition, CACHE_CONTROL},
+  web, Error, HttpRequest, HttpResponse,
+};
+use bytes::Bytes;
+use chrono::Utc;
+use envconfig::Envconfig;
+use futures::{stream, StreamExt};
+use influxdb2::models::DataPoint;
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use crate::{
+  config::Config,
+  helpers::zip_stream::ZipStream,
+  services::photography::{
+    access::deny_album_access, storage::original_path,
+  },
+  structs::photography::{Album, Photo},
+  ServerState,
+};
+
+/// Chunks buffered between reading from S3 and writing to the client.
+const CHUNK_BUFFER: usize = 8;
+
+async fn record_download(state: &ServerState, slug: &str, photos: usize) {
+  let config = Config::init_from_env().unwrap();
+
+  let download = DataPoint::builder("photography_downloads")
+    .tag("album", slug)
+    .field("photos", photos as i64)
+    .timestamp(Utc::now().timestamp_nanos_opt().unwrap())
+    .build()
+    .unwrap();
+
+  let _ = state
+    .influxdb
+    .client
+    .write(&config.influxdb_bucket, stream::iter(vec![download]))
+    .await;
+}
+
+/// Stream the zip into `tx`, one S3 chunk at a time. Photos that can't be
+/// fetched are left out and listed in the manifest. Stops as soon as the
+/// client goes away.
+async fn write_album_zip(
+  state: web::Data<ServerState>,
+  album: Album,
+  photos: Vec<Photo>,
+  tx: mpsc::Sender<Result<Bytes, Error>>,
+) {
+  let mut zip = ZipStream::new();
+  let mut included = Vec::new();
+  let mut skipped = Vec::new();
+
+  for photo in photos {
+    let path = original_path(&album.storage_dir, &photo.name);
+    let mut object =
+      match state.s3.cdn_bucket.get_object_stream(&path).await {
+        Ok(object) if object.status_code == 200 => object,
+        _ => {
+          tracing::warn!("Failed to fetch {} for an album download", path);
+          skipped.push(photo.name);
+          continue;
+        }
+      };
+
+    let name = format!("{}/{}", album.slug, photo.name);
+    if tx
+      .send(Ok(zip.start_file(&name, photo.uploaded_at)))
+      .await
+      .is_err()
+    {
+      return;
+    }
+    while let Some(chunk) = object.bytes.next().await {
+      // A file too large for the archive ends the download with an error
+      // rather than a zip that can't be opened.
+      let chunk = zip.write(chunk).map_err(|code| {
+        tracing::warn!(
+          "Can't add {} to an album download: {}",
+          path,
+          code
+        );
+        ErrorInternalServerError(code)
+      });
+      let failed = chunk.is_err();
+      if tx.send(chunk).await.is_err() || failed {
+        return;
+      }
+    }
+    if tx.send(Ok(zip.finish_file())).await.is_err() {
+      return;
+    }
+
+    included.push(json!({
+      "file": photo.name,
+      "caption": photo.caption,
+      "instagram": photo.instagram,
+      "keywords": photo.keywords,
+      "tags": photo.tags,
+      "exif": photo.exif.map(|exif| exif.0.public()),
+    }));
+  }
+
+  let manifest = json!({
+    "album": {
+      "slug": album.slug,
+      "name": album.name,
+      "description": album.description,
+      "location": album.location,
+      "date": album.date,
+    },
+    "photos": included,
+    "skipped": skipped,
+  });
+  let manifest =
+    Bytes::from(serde_json::to_vec_pretty(&manifest).unwrap());
+
+  let name = format!("{}/manifest.json", album.slug);
+  let _ = tx
+    .send(Ok(zip.start_file(&name, Utc::now().naive_utc())))
+    .await;
+  let _ = tx
+    .send(zip.write(manifest).map_err(ErrorInternalServerError))
+    .await;
+  let _ = tx.send(Ok(zip.finish_file())).await;
+  let _ = tx.send(Ok(zip.finish())).await;
+}
+
+/// Download an album's originals as a zip, with a `manifest.json` of
+/// captions and metadata. The archive is streamed straight from S3 as it
+/// is built rather than assembled first.
+///
+/// The manifest's GPS positions are rounded like everywhere else, and the
+/// originals are stored with their location stripped out of their EXIF.
+#[get("/albums/{slug}/download")]
+async fn download_album(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+  slug: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+  let album = sqlx::query_as::<_, Album>(
+    "SELECT * FROM photography_albums WHERE slug = $1 LIMIT 1",
+  )
+  .bind(slug.into_inner())
+  .fetch_optional(&state.db)
+  .await
+  .map_err(|error| {
+    eprintln!("failed to get album from database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  let album = match album {
+    Some(album) => album,
+    None => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "album_not_found"})),
+      );
+    }
+  };
+
+  if let Some(response) = deny_album_access(&state, &req, &album).await {
+    return Ok(response);
+  }
+
+  let photos = sqlx::query_as::<_, Photo>(
+    "SELECT * FROM photography_photos WHERE album_slug = $1 \
+     ORDER BY position ASC",
+  )
+  .bind(&album.slug)
+  .fetch_all(&state.db)
+  .await
+  .map_err(|error| {
+    eprintln!("failed to get album photos {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  if photos.is_empty() {
+    return Ok(
+      HttpResponse::NotFound().json(json!({"code": "album_empty"})),
+    );
+  }
+
+  record_download(&state, &album.slug, photos.len()).await;
+
+  // S3 object streams aren't `Send`, so the zip is written from a task on
+  // this worker and handed to the response through a bounded channel.
+  let (tx, rx) = mpsc::channel(CHUNK_BUFFER);
+  let file_name = format!("{}.zip", album.slug);
+  actix_web::rt::spawn(write_album_zip(
+    web::Data::clone(&state),
+    album,
+    photos,
+    tx,
+  ));
+
+  let body = stream::unfold(rx, |mut rx| async move {
+    rx.recv().await.map(|chunk| (chunk, rx))
+  });
+
+  Ok(
+    HttpResponse::Ok()
+      .content_type("application/zip")
+      .insert_header(ContentDisposition::attachment(file_name))
+      .insert_header((CACHE_CONTROL, "no-store"))
+      .streaming(body),
+  )
+}
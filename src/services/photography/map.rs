@@ -0,0 +1,69 @@
+use actix_web::{
+  error::ErrorInternalServerError, get, http::header::CACHE_CONTROL, web,
+  Error, HttpResponse,
+};
+use serde_json::json;
+
+use crate::{
+  structs::photography::{Coordinates, Photo},
+  ServerState,
+};
+
+/// Every located photo in a public album as a GeoJSON `FeatureCollection`
+/// of points. Coordinates are rounded to the configured precision.
+#[get("/map.geojson")]
+async fn get_map(
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  let photos = sqlx::query_as::<_, Photo>(
+    "SELECT p.* FROM photography_photos p \
+     JOIN photography_albums a ON a.slug = p.album_slug \
+     WHERE a.visibility = 'public' \
+       AND p.latitude IS NOT NULL AND p.longitude IS NOT NULL \
+     ORDER BY p.album_slug, p.position",
+  )
+  .fetch_all(&state.db)
+  .await
+  .map_err(|error| {
+    eprintln!("failed to get located photos from database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  let features = photos
+    .into_iter()
+    .filter_map(|photo| {
+      let coordinates =
+        Coordinates::from_columns(photo.latitude, photo.longitude)?
+          .rounded();
+      let thumbnail = photo
+        .variants
+        .0
+        .into_iter()
+        .find(|variant| variant.size == "thumbnail")
+        .map(|variant| variant.url);
+
+      Some(json!({
+        "type": "Feature",
+        // GeoJSON positions are longitude first.
+        "geometry": {
+          "type": "Point",
+          "coordinates": [coordinates.longitude, coordinates.latitude],
+        },
+        "properties": {
+          "album": photo.album_slug,
+          "name": photo.name,
+          "caption": photo.caption,
+          "thumbnail": thumbnail,
+          "taken_at": photo.exif.as_ref().and_then(|exif| exif.taken_at),
+        },
+      }))
+    })
+    .collect::<Vec<_>>();
+
+  Ok(
+    HttpResponse::Ok()
+      .content_type("application/geo+json")
+      .insert_header((CACHE_CONTROL, "public, max-age=300"))
+      .json(json!({"type": "FeatureCollection", "features": features})),
+  )
+}
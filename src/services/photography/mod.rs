@@ -1,4 +1,10 @@
+pub mod access;
+pub mod download;
+pub mod map;
 pub mod routes;
+pub mod search;
+pub mod storage;
+pub mod variants;
 
 use actix_web::{web, Scope};
 use actix_web_lab::middleware::from_fn;
@@ -9,6 +15,10 @@ pub fn factory() -> Scope {
   web::scope("/photography")
     .service(routes::get_albums)
     .service(routes::get_album)
+    .service(access::unlock_album)
+    .service(download::download_album)
+    .service(search::search_photos)
+    .service(map::get_map)
     .service(
       web::scope("")
         .wrap(from_fn(services::middleware::auth_middleware))
@@ -19,6 +29,12 @@ pub fn factory() -> Scope {
         .service(routes::reorder_photos)
         .service(routes::bulk_update_photos)
         .service(routes::delete_photo)
-        .service(routes::update_photo),
+        .service(routes::update_photo)
+        .service(routes::rename_photo)
+        .service(routes::move_photo)
+        .service(routes::copy_photo)
+        .service(routes::refresh_exif)
+        .service(routes::backfill_photos)
+        .service(routes::backfill_variants),
     )
 }
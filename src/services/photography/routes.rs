@@ -6,26 +6,44 @@ use actix_web::{
 };
 use optional_field::Field;
 use serde_json::json;
+use sqlx::types::Json;
 
 use crate::{
-  helpers::authentication::is_management_authed,
-  services::uploads::helpers,
+  helpers::{
+    authentication::is_management_authed,
+    exif::{extract_exif, strip_gps},
+  },
+  modules::photography_backfill::start_photo_backfill,
+  services::{
+    blog::auth::hash_password,
+    photography::{
+      access::deny_album_access,
+      storage::{album_dir, new_album_dir, original_path, relocate_album},
+      variants::{
+        copy_photo_variants, delete_photo_variants, process_photo,
+        save_processed_photo,
+      },
+    },
+    uploads::helpers,
+  },
   structs::{
     photography::{
-      Album, AlbumItem, BulkUpdatePhotosPayload, CreateAlbumPayload,
-      EditAlbumPayload, EditPhotoPayload, GetAlbumResponse,
-      GetAlbumsResponse, PublicAlbum, ReorderPhotosPayload, SkippedUpload,
-      UploadPhotosResponse,
+      Album, BulkUpdatePhotosPayload, Coordinates, CreateAlbumPayload,
+      EditAlbumPayload, EditPhotoPayload, Frame, GetAlbumResponse,
+      GetAlbumsResponse, Photo, PublicAlbum, RefreshExifResponse,
+      RenamePhotoPayload, ReorderPhotosPayload, SkippedUpload,
+      TransferPhotoPayload, TransferPhotoResponse, UploadPhotosResponse,
+      ALBUM_VISIBILITIES,
     },
     uploads::CdnUpload,
   },
   ServerState,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// Fetch a single album by its slug.
-async fn find_album(
-  db: &sqlx::PgPool,
+pub(crate) async fn find_album<'e>(
+  db: impl sqlx::PgExecutor<'e>,
   slug: &str,
 ) -> Result<Option<Album>, sqlx::Error> {
   sqlx::query_as::<_, Album>(
@@ -36,6 +54,146 @@ async fn find_album(
   .await
 }
 
+/// Fetch an album's photos in album order.
+async fn find_photos<'e>(
+  db: impl sqlx::PgExecutor<'e>,
+  slug: &str,
+) -> Result<Vec<Photo>, sqlx::Error> {
+  sqlx::query_as::<_, Photo>(
+    "SELECT * FROM photography_photos WHERE album_slug = $1 \
+     ORDER BY position ASC",
+  )
+  .bind(slug)
+  .fetch_all(db)
+  .await
+}
+
+/// Fetch an album's photos in album order, locking them for the rest of
+/// the transaction.
+async fn lock_photos(
+  tx: &mut sqlx::PgConnection,
+  slug: &str,
+) -> Result<Vec<Photo>, sqlx::Error> {
+  sqlx::query_as::<_, Photo>(
+    "SELECT * FROM photography_photos WHERE album_slug = $1 \
+     ORDER BY position ASC FOR UPDATE",
+  )
+  .bind(slug)
+  .fetch_all(tx)
+  .await
+}
+
+/// Trimmed, lowercased and deduplicated, so searches can match them
+/// exactly. `null` clears them.
+fn normalize_keywords(keywords: Option<&[String]>) -> Vec<String> {
+  let mut normalized: Vec<String> = Vec::new();
+  for keyword in keywords.unwrap_or_default() {
+    let keyword = keyword.trim().to_lowercase();
+    if !keyword.is_empty() && !normalized.contains(&keyword) {
+      normalized.push(keyword);
+    }
+  }
+
+  normalized
+}
+
+/// Apply tri-state edits to a photo: missing fields keep their current
+/// value and `null` clears them.
+fn apply_photo_fields(
+  photo: &mut Photo,
+  caption: &Field<String>,
+  instagram: &Field<String>,
+  frame: &Field<Frame>,
+  keywords: &Field<Vec<String>>,
+  tags: &Field<Vec<String>>,
+) {
+  if let Field::Present(caption) = caption {
+    photo.caption = caption.clone();
+  }
+  if let Field::Present(instagram) = instagram {
+    photo.instagram = instagram.clone();
+  }
+  if let Field::Present(frame) = frame {
+    photo.frame = frame.clone().map(Json);
+  }
+  if let Field::Present(keywords) = keywords {
+    photo.keywords = normalize_keywords(keywords.as_deref());
+  }
+  if let Field::Present(tags) = tags {
+    photo.tags = normalize_keywords(tags.as_deref());
+  }
+}
+
+async fn save_photo_fields(
+  tx: &mut sqlx::PgConnection,
+  photo: &Photo,
+) -> Result<(), sqlx::Error> {
+  sqlx::query(
+    "UPDATE photography_photos SET caption = $1, instagram = $2, frame = $3, \
+       keywords = $4, tags = $5, latitude = $6, longitude = $7 \
+     WHERE album_slug = $8 AND name = $9",
+  )
+  .bind(&photo.caption)
+  .bind(&photo.instagram)
+  .bind(photo.frame.clone())
+  .bind(&photo.keywords)
+  .bind(&photo.tags)
+  .bind(photo.latitude)
+  .bind(photo.longitude)
+  .bind(&photo.album_slug)
+  .bind(&photo.name)
+  .execute(tx)
+  .await
+  .map(|_| ())
+}
+
+/// Check a requested visibility and work out the password hash to store
+/// with it. `password` albums need either a new password or an existing
+/// one; every other visibility drops it. On failure, returns the response
+/// to send.
+fn album_password(
+  visibility: &str,
+  password: Option<&str>,
+  current: Option<String>,
+) -> Result<Option<String>, HttpResponse> {
+  if !ALBUM_VISIBILITIES.contains(&visibility) {
+    return Err(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "invalid_visibility"})),
+    );
+  }
+  if visibility != "password" {
+    return Ok(None);
+  }
+
+  match password.filter(|password| !password.is_empty()) {
+    Some(password) => Ok(Some(hash_password(password))),
+    None => current.map(Some).ok_or_else(|| {
+      HttpResponse::BadRequest().json(json!({"code": "password_required"}))
+    }),
+  }
+}
+
+/// Columns for a tri-state coordinates edit: missing keeps `current` and
+/// `null` clears them. On out-of-range coordinates, returns the response to
+/// send.
+fn coordinate_columns(
+  coordinates: &Field<Coordinates>,
+  current: (Option<f64>, Option<f64>),
+) -> Result<(Option<f64>, Option<f64>), HttpResponse> {
+  match coordinates {
+    Field::Missing => Ok(current),
+    Field::Present(None) => Ok((None, None)),
+    Field::Present(Some(coordinates)) if coordinates.is_valid() => {
+      Ok((Some(coordinates.latitude), Some(coordinates.longitude)))
+    }
+    Field::Present(Some(_)) => Err(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "invalid_coordinates"})),
+    ),
+  }
+}
+
 #[get("/albums")]
 async fn get_albums(
   state: web::Data<ServerState>,
@@ -51,6 +209,24 @@ async fn get_albums(
     ErrorInternalServerError(error.to_string())
   })?;
 
+  let photos = sqlx::query_as::<_, Photo>(
+    "SELECT * FROM photography_photos ORDER BY album_slug, position ASC",
+  )
+  .fetch_all(&state.db)
+  .await
+  .map_err(|error| {
+    eprintln!("failed to fetch photos from database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  let mut photos_by_album: HashMap<String, Vec<Photo>> = HashMap::new();
+  for photo in photos {
+    photos_by_album
+      .entry(photo.album_slug.clone())
+      .or_default()
+      .push(photo);
+  }
+
   let valkey = &mut state.valkey.clone();
   let is_management_authed =
     is_management_authed(valkey, req.headers().get(AUTHORIZATION))
@@ -59,38 +235,51 @@ async fn get_albums(
 
   let albums = albums
     .into_iter()
+    .map(|album| {
+      let photos = photos_by_album.remove(&album.slug).unwrap_or_default();
+      PublicAlbum::new(album, photos)
+    })
     .filter(|album| {
       if let Some(true) = is_management_authed {
         true
       } else {
-        serde_json::from_value::<Vec<AlbumItem>>(album.items.clone())
-          .unwrap()
-          .len()
-          > 0
+        album.visibility == "public"
       }
     })
     .collect::<Vec<_>>();
 
-  Ok(HttpResponse::Ok().json(GetAlbumsResponse::from(albums)))
+  Ok(HttpResponse::Ok().json(GetAlbumsResponse { albums }))
 }
 
 #[get("/albums/{slug}")]
 async fn get_album(
   state: web::Data<ServerState>,
   slug: web::Path<String>,
+  req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
-  let album = find_album(&state.db, &slug.into_inner())
-    .await
-    .map_err(|error| {
-      eprintln!("failed to fetch albums from database {:?}", error);
-      ErrorInternalServerError(error.to_string())
-    })?;
+  let album = find_album(&state.db, &slug).await.map_err(|error| {
+    eprintln!("failed to fetch albums from database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
 
-  if album.is_none() {
-    return Ok(HttpResponse::NotFound().finish());
+  let album = match album {
+    Some(album) => album,
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+
+  if let Some(response) = deny_album_access(&state, &req, &album).await {
+    return Ok(response);
   }
 
-  Ok(HttpResponse::Ok().json(GetAlbumResponse::from(album.unwrap())))
+  let photos = find_photos(&state.db, &slug).await.map_err(|error| {
+    eprintln!("failed to fetch photos from database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  Ok(
+    HttpResponse::Ok()
+      .json(GetAlbumResponse::from(PublicAlbum::new(album, photos))),
+  )
 }
 
 #[post("/albums")]
@@ -98,14 +287,35 @@ async fn create_album(
   state: web::Data<ServerState>,
   payload: web::Json<CreateAlbumPayload>,
 ) -> Result<HttpResponse, Error> {
+  let visibility = payload.visibility.as_deref().unwrap_or("draft");
+  let password =
+    match album_password(visibility, payload.password.as_deref(), None) {
+      Ok(password) => password,
+      Err(response) => return Ok(response),
+    };
+  let (latitude, longitude) = match coordinate_columns(
+    &Field::Present(payload.coordinates),
+    (None, None),
+  ) {
+    Ok(columns) => columns,
+    Err(response) => return Ok(response),
+  };
+
   let album = sqlx::query_as::<_, Album>(
-    "INSERT INTO photography_albums (slug, name, location, description) \
-     VALUES ($1, $2, $3, $4) RETURNING *",
+    "INSERT INTO photography_albums \
+       (slug, name, location, description, visibility, password, \
+        latitude, longitude, storage_dir) \
+     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *",
   )
   .bind(payload.slug.clone())
   .bind(payload.name.clone())
   .bind(payload.location.clone())
   .bind(payload.description.clone())
+  .bind(visibility)
+  .bind(password)
+  .bind(latitude)
+  .bind(longitude)
+  .bind(new_album_dir(&payload.slug, visibility))
   .fetch_one(&state.db)
   .await
   .map_err(|error| {
@@ -113,7 +323,10 @@ async fn create_album(
     ErrorInternalServerError(error.to_string())
   })?;
 
-  Ok(HttpResponse::Created().json(GetAlbumResponse::from(album)))
+  Ok(
+    HttpResponse::Created()
+      .json(GetAlbumResponse::from(PublicAlbum::new(album, Vec::new()))),
+  )
 }
 
 #[patch("/albums/{slug}")]
@@ -136,11 +349,10 @@ async fn edit_album(
     }
   };
 
-  let items: Vec<AlbumItem> = serde_json::from_value(album.items.clone())
-    .map_err(|error| {
-      eprintln!("failed to get album items {:?}", error);
-      ErrorInternalServerError(error.to_string())
-    })?;
+  let photos = find_photos(&state.db, &slug).await.map_err(|error| {
+    eprintln!("failed to get album photos {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
 
   // Resolve each column to its final value (tri-state fields can clear to
   // NULL; missing fields keep the current value).
@@ -160,7 +372,7 @@ async fn edit_album(
     Field::Present(None) => None,
     Field::Present(cover) => {
       let cover_name = cover.to_owned().unwrap();
-      if !items.iter().any(|item| item.name == cover_name) {
+      if !photos.iter().any(|photo| photo.name == cover_name) {
         return Ok(
           HttpResponse::NotFound()
             .json(json!({"code": "cover_photo_not_found"})),
@@ -169,16 +381,37 @@ async fn edit_album(
       Some(cover_name)
     }
   };
+  let visibility = payload.visibility.clone().unwrap_or(album.visibility);
+  let password = match album_password(
+    &visibility,
+    payload.password.as_deref(),
+    album.password,
+  ) {
+    Ok(password) => password,
+    Err(response) => return Ok(response),
+  };
+  let (latitude, longitude) = match coordinate_columns(
+    &payload.coordinates,
+    (album.latitude, album.longitude),
+  ) {
+    Ok(columns) => columns,
+    Err(response) => return Ok(response),
+  };
 
   let album = sqlx::query_as::<_, Album>(
     "UPDATE photography_albums \
-     SET name = $1, description = $2, location = $3, cover = $4 \
-     WHERE slug = $5 RETURNING *",
+     SET name = $1, description = $2, location = $3, cover = $4, \
+       visibility = $5, password = $6, latitude = $7, longitude = $8 \
+     WHERE slug = $9 RETURNING *",
   )
   .bind(name)
   .bind(description)
   .bind(location)
   .bind(cover)
+  .bind(visibility)
+  .bind(password)
+  .bind(latitude)
+  .bind(longitude)
   .bind(&slug)
   .fetch_one(&state.db)
   .await
@@ -187,7 +420,22 @@ async fn edit_album(
     ErrorInternalServerError(error.to_string())
   })?;
 
-  Ok(HttpResponse::Ok().json(GetAlbumResponse::from(album)))
+  // Saving again retries a move that didn't make it.
+  if !relocate_album(&state, &album).await {
+    return Ok(
+      HttpResponse::InternalServerError()
+        .json(json!({"code": "failed_to_move_album_files"})),
+    );
+  }
+  let photos = find_photos(&state.db, &slug).await.map_err(|error| {
+    eprintln!("failed to get album photos {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  Ok(
+    HttpResponse::Ok()
+      .json(GetAlbumResponse::from(PublicAlbum::new(album, photos))),
+  )
 }
 
 #[delete("/albums/{slug}")]
@@ -209,11 +457,12 @@ async fn delete_album(
     }
   };
 
-  let items: Vec<AlbumItem> = serde_json::from_value(album.items)
+  let photos = find_photos(&state.db, &slug)
+    .await
     .map_err(|error| ErrorInternalServerError(error.to_string()))?;
 
-  if items.len() > 0 {
-    let path = format!("gallery/albums/{}", slug.clone());
+  if !photos.is_empty() {
+    let path = format!("gallery/albums/{}/", album.storage_dir);
     let s3 = &state.s3;
 
     let list_res = s3
@@ -239,6 +488,7 @@ async fn delete_album(
     }
   }
 
+  // The album's photo rows go with it through the foreign key.
   sqlx::query("DELETE FROM photography_albums WHERE slug = $1")
     .bind(&slug)
     .execute(&state.db)
@@ -251,6 +501,67 @@ async fn delete_album(
   Ok(HttpResponse::NoContent().finish())
 }
 
+/// Add a photo whose original is already in S3 to the end of an album and
+/// process it. Returns `false` if the album already has a photo by that
+/// name.
+pub(crate) async fn add_photo(
+  state: &ServerState,
+  slug: &str,
+  file_name: &str,
+  data: bytes::Bytes,
+) -> Result<bool, sqlx::Error> {
+  if !insert_photo(&state.db, slug, file_name, &data).await? {
+    return Ok(false);
+  }
+
+  process_added_photo(state, slug, file_name, data).await;
+  Ok(true)
+}
+
+/// Add a photo's row to the end of its album, without processing it yet.
+/// False when the album already has a photo by that name, including one
+/// from a concurrent upload.
+pub(crate) async fn insert_photo<'e>(
+  db: impl sqlx::PgExecutor<'e>,
+  slug: &str,
+  file_name: &str,
+  data: &[u8],
+) -> Result<bool, sqlx::Error> {
+  let exif = extract_exif(data);
+  let gps = exif.as_ref().and_then(|exif| exif.gps.clone());
+  let inserted = sqlx::query(
+    "INSERT INTO photography_photos \
+       (album_slug, name, position, exif, latitude, longitude) \
+     SELECT $1, $2, COALESCE(MAX(position) + 1, 0), $3, $4, $5 \
+     FROM photography_photos WHERE album_slug = $1 \
+     ON CONFLICT DO NOTHING",
+  )
+  .bind(slug)
+  .bind(file_name)
+  .bind(exif.map(Json))
+  .bind(gps.as_ref().map(|gps| gps.latitude))
+  .bind(gps.as_ref().map(|gps| gps.longitude))
+  .execute(db)
+  .await?;
+
+  Ok(inserted.rows_affected() > 0)
+}
+
+/// Generate the variants and placeholders of a photo `insert_photo` added.
+/// A photo that can't be processed is still kept; the backfill job can try
+/// again later.
+pub(crate) async fn process_added_photo(
+  state: &ServerState,
+  slug: &str,
+  file_name: &str,
+  data: bytes::Bytes,
+) {
+  let photo = process_photo(state, slug, file_name, data, true).await;
+  if let Some(photo) = photo {
+    save_processed_photo(state, slug, file_name, photo).await;
+  }
+}
+
 #[put("/albums/{slug}")]
 async fn upload_photos(
   MultipartForm(form): MultipartForm<CdnUpload>,
@@ -277,11 +588,15 @@ async fn upload_photos(
     );
   }
 
-  let mut items: Vec<AlbumItem> =
-    serde_json::from_value(album.items.clone()).map_err(|error| {
-      eprintln!("failed to parse album items {:?}", error);
+  let mut names: HashSet<String> = find_photos(&state.db, &slug)
+    .await
+    .map_err(|error| {
+      eprintln!("failed to get album photos {:?}", error);
       ErrorInternalServerError(error.to_string())
-    })?;
+    })?
+    .into_iter()
+    .map(|photo| photo.name)
+    .collect();
 
   let s3 = &state.s3;
   let mut uploaded: Vec<String> = Vec::new();
@@ -313,7 +628,7 @@ async fn upload_photos(
 
     // Guard against clashing with a photo that already exists, or with
     // another file of the same name earlier in this same request.
-    if items.iter().any(|item| item.name == file_name) {
+    if names.contains(&file_name) {
       skipped.push(SkippedUpload {
         name: Some(file_name),
         reason: "photo_already_exists".to_string(),
@@ -321,51 +636,56 @@ async fn upload_photos(
       continue;
     }
 
-    let path = format!("gallery/albums/{}/{}", slug.clone(), file_name);
+    // Originals are public, so they're stored without their location.
+    // EXIF is still read from the upload, which keeps the coordinates.
+    let original = strip_gps(&file.data);
+    let path = original_path(&album.storage_dir, &file_name);
     let response = s3
       .cdn_bucket
-      .put_object_with_content_type(&path, &file.data, &file_type.mime)
+      .put_object_with_content_type(
+        &path,
+        original.as_deref().unwrap_or(&file.data),
+        &file_type.mime,
+      )
       .await;
 
     match response {
-      Ok(response) if response.status_code() == 200 => {
-        items.push(AlbumItem {
-          name: file_name.clone(),
-          caption: None,
-          instagram: None,
-          frame: None,
-        });
-        uploaded.push(file_name);
-      }
+      Ok(response) if response.status_code() == 200 => (),
       _ => {
         skipped.push(SkippedUpload {
           name: Some(file_name),
           reason: "failed_upload_to_s3".to_string(),
         });
+        continue;
       }
     }
+
+    let added = add_photo(&state, &slug, &file_name, file.data.clone())
+      .await
+      .map_err(|error| {
+        eprintln!("failed to insert photo into database {:?}", error);
+        ErrorInternalServerError(error.to_string())
+      })?;
+
+    names.insert(file_name.clone());
+    if !added {
+      skipped.push(SkippedUpload {
+        name: Some(file_name),
+        reason: "photo_already_exists".to_string(),
+      });
+      continue;
+    }
+
+    uploaded.push(file_name);
   }
 
-  // Only touch the database if at least one file actually landed.
-  let album = if uploaded.is_empty() {
-    album
-  } else {
-    sqlx::query_as::<_, Album>(
-      "UPDATE photography_albums SET items = $1 WHERE slug = $2 \
-       RETURNING *",
-    )
-    .bind(serde_json::to_value(&items).unwrap())
-    .bind(&slug)
-    .fetch_one(&state.db)
-    .await
-    .map_err(|error| {
-      eprintln!("failed to update album in database {:?}", error);
-      ErrorInternalServerError(error.to_string())
-    })?
-  };
+  let photos = find_photos(&state.db, &slug).await.map_err(|error| {
+    eprintln!("failed to get album photos {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
 
   Ok(HttpResponse::Ok().json(UploadPhotosResponse {
-    album: PublicAlbum::from(album),
+    album: PublicAlbum::new(album, photos),
     uploaded,
     skipped,
   }))
@@ -391,49 +711,68 @@ async fn update_photo(
     }
   };
 
-  let mut items: Vec<AlbumItem> = serde_json::from_value(album.items)
-    .map_err(|error| {
-      eprintln!("failed to get album items {:?}", error);
-      ErrorInternalServerError(error.to_string())
-    })?;
-
-  let photo = items.iter_mut().find(|item| item.name == name);
-  if photo.is_none() {
-    return Ok(
-      HttpResponse::NotFound().json(json!({"code": "photo_not_found"})),
-    );
-  }
-
-  let photo = photo.unwrap();
-  match &payload.caption {
-    Field::Missing => (),
-    Field::Present(None) => photo.caption = None,
-    Field::Present(caption) => photo.caption = caption.clone(),
-  }
-  match &payload.instagram {
-    Field::Missing => (),
-    Field::Present(None) => photo.instagram = None,
-    Field::Present(instagram) => photo.instagram = instagram.clone(),
-  }
-  match &payload.frame {
-    Field::Missing => (),
-    Field::Present(None) => photo.frame = None,
-    Field::Present(frame) => photo.frame = frame.clone(),
-  }
+  let mut tx = state
+    .db
+    .begin()
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?;
 
-  let album = sqlx::query_as::<_, Album>(
-    "UPDATE photography_albums SET items = $1 WHERE slug = $2 RETURNING *",
+  let photo = sqlx::query_as::<_, Photo>(
+    "SELECT * FROM photography_photos \
+     WHERE album_slug = $1 AND name = $2 FOR UPDATE",
   )
-  .bind(serde_json::to_value(items).unwrap())
   .bind(&slug)
-  .fetch_one(&state.db)
+  .bind(&name)
+  .fetch_optional(&mut *tx)
   .await
   .map_err(|error| {
-    eprintln!("failed to update album in database {:?}", error);
+    eprintln!("failed to get photo from database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  let mut photo = match photo {
+    Some(photo) => photo,
+    None => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "photo_not_found"})),
+      );
+    }
+  };
+
+  apply_photo_fields(
+    &mut photo,
+    &payload.caption,
+    &payload.instagram,
+    &payload.frame,
+    &payload.keywords,
+    &payload.tags,
+  );
+  (photo.latitude, photo.longitude) = match coordinate_columns(
+    &payload.coordinates,
+    (photo.latitude, photo.longitude),
+  ) {
+    Ok(columns) => columns,
+    Err(response) => return Ok(response),
+  };
+
+  save_photo_fields(&mut tx, &photo).await.map_err(|error| {
+    eprintln!("failed to update photo in database {:?}", error);
     ErrorInternalServerError(error.to_string())
   })?;
 
-  Ok(HttpResponse::Ok().json(GetAlbumResponse::from(album)))
+  let photos = find_photos(&mut *tx, &slug)
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+
+  tx.commit().await.map_err(|error| {
+    eprintln!("failed to update photo in database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  Ok(
+    HttpResponse::Ok()
+      .json(GetAlbumResponse::from(PublicAlbum::new(album, photos))),
+  )
 }
 
 #[patch("/albums/{slug}/photos")]
@@ -456,87 +795,132 @@ async fn bulk_update_photos(
     }
   };
 
-  let mut items: Vec<AlbumItem> = serde_json::from_value(album.items)
-    .map_err(|error| {
-      eprintln!("failed to get album items {:?}", error);
-      ErrorInternalServerError(error.to_string())
-    })?;
+  let mut tx = state
+    .db
+    .begin()
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+
+  let mut photos = lock_photos(&mut tx, &slug).await.map_err(|error| {
+    eprintln!("failed to get album photos {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
 
   // Validate every targeted photo exists before mutating anything, so the
   // request is all-or-nothing.
-  if let Some(photos) = &payload.photos {
-    let missing: Vec<String> = photos
-      .iter()
-      .filter(|update| !items.iter().any(|item| item.name == update.name))
-      .map(|update| update.name.clone())
-      .collect();
+  let missing = missing_photos(&photos, &payload);
+  if !missing.is_empty() {
+    return Ok(
+      HttpResponse::NotFound()
+        .json(json!({"code": "photos_not_found", "photos": missing})),
+    );
+  }
 
-    if !missing.is_empty() {
-      return Ok(HttpResponse::NotFound().json(
-        json!({"code": "photos_not_found", "photos": missing}),
-      ));
-    }
+  apply_bulk_update(&mut photos, &payload);
+
+  for photo in photos.iter() {
+    save_photo_fields(&mut tx, photo).await.map_err(|error| {
+      eprintln!("failed to update photo in database {:?}", error);
+      ErrorInternalServerError(error.to_string())
+    })?;
   }
 
-  // First, blanket changes across every photo in the album.
+  tx.commit().await.map_err(|error| {
+    eprintln!("failed to update photos in database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  Ok(
+    HttpResponse::Ok()
+      .json(GetAlbumResponse::from(PublicAlbum::new(album, photos))),
+  )
+}
+
+/// Apply a bulk update: blanket changes across every photo first, then
+/// per-photo overrides on top.
+fn apply_bulk_update(
+  photos: &mut [Photo],
+  payload: &BulkUpdatePhotosPayload,
+) {
   if let Some(all) = &payload.apply_to_all {
-    for item in items.iter_mut() {
-      match &all.caption {
-        Field::Missing => (),
-        Field::Present(None) => item.caption = None,
-        Field::Present(caption) => item.caption = caption.clone(),
-      }
-      match &all.instagram {
-        Field::Missing => (),
-        Field::Present(None) => item.instagram = None,
-        Field::Present(instagram) => item.instagram = instagram.clone(),
-      }
-      match &all.frame {
-        Field::Missing => (),
-        Field::Present(None) => item.frame = None,
-        Field::Present(frame) => item.frame = frame.clone(),
-      }
+    for photo in photos.iter_mut() {
+      apply_photo_fields(
+        photo,
+        &all.caption,
+        &all.instagram,
+        &all.frame,
+        &all.keywords,
+        &all.tags,
+      );
     }
   }
 
-  // Then per-photo overrides on top.
-  if let Some(photos) = &payload.photos {
-    for update in photos {
-      if let Some(item) =
-        items.iter_mut().find(|item| item.name == update.name)
+  if let Some(updates) = &payload.photos {
+    for update in updates {
+      if let Some(photo) =
+        photos.iter_mut().find(|photo| photo.name == update.name)
       {
-        match &update.caption {
-          Field::Missing => (),
-          Field::Present(None) => item.caption = None,
-          Field::Present(caption) => item.caption = caption.clone(),
-        }
-        match &update.instagram {
-          Field::Missing => (),
-          Field::Present(None) => item.instagram = None,
-          Field::Present(instagram) => item.instagram = instagram.clone(),
-        }
-        match &update.frame {
-          Field::Missing => (),
-          Field::Present(None) => item.frame = None,
-          Field::Present(frame) => item.frame = frame.clone(),
-        }
+        apply_photo_fields(
+          photo,
+          &update.caption,
+          &update.instagram,
+          &update.frame,
+          &update.keywords,
+          &update.tags,
+        );
       }
     }
   }
+}
 
-  let album = sqlx::query_as::<_, Album>(
-    "UPDATE photography_albums SET items = $1 WHERE slug = $2 RETURNING *",
-  )
-  .bind(serde_json::to_value(items).unwrap())
-  .bind(&slug)
-  .fetch_one(&state.db)
-  .await
-  .map_err(|error| {
-    eprintln!("failed to update album in database {:?}", error);
-    ErrorInternalServerError(error.to_string())
-  })?;
+/// Names of photos a bulk update targets that the album doesn't have.
+fn missing_photos(
+  photos: &[Photo],
+  payload: &BulkUpdatePhotosPayload,
+) -> Vec<String> {
+  payload
+    .photos
+    .iter()
+    .flatten()
+    .filter(|update| !photos.iter().any(|photo| photo.name == update.name))
+    .map(|update| update.name.clone())
+    .collect()
+}
 
-  Ok(HttpResponse::Ok().json(GetAlbumResponse::from(album)))
+/// Why a new order isn't an exact permutation of an album's photos.
+#[derive(Debug, PartialEq)]
+enum OrderError<'a> {
+  /// A photo the album doesn't have, or one listed twice.
+  Invalid(&'a str),
+  /// Photos the order leaves out, sorted by name.
+  Incomplete(Vec<&'a str>),
+}
+
+/// The new order must be an exact permutation of the existing photos: no
+/// duplicates, and the same set of names (which also enforces equal
+/// length). Names are drained from a set so each is consumed once.
+fn check_order<'a>(
+  photos: &'a [Photo],
+  order: &'a [String],
+) -> Result<(), OrderError<'a>> {
+  let mut remaining: HashSet<&str> =
+    photos.iter().map(|photo| photo.name.as_str()).collect();
+
+  for name in order {
+    if !remaining.remove(name.as_str()) {
+      // Either an unknown photo, or a duplicate that was already drained.
+      return Err(OrderError::Invalid(name));
+    }
+  }
+
+  // Anything left in the set means the caller omitted a photo.
+  if !remaining.is_empty() {
+    let mut missing: Vec<&str> = remaining.into_iter().collect();
+    missing.sort_unstable();
+    return Err(OrderError::Incomplete(missing));
+  }
+
+  Ok(())
 }
 
 #[put("/albums/{slug}/order")]
@@ -559,52 +943,60 @@ async fn reorder_photos(
     }
   };
 
-  let items: Vec<AlbumItem> = serde_json::from_value(album.items)
-    .map_err(|error| {
-      eprintln!("failed to get album items {:?}", error);
-      ErrorInternalServerError(error.to_string())
-    })?;
+  let mut tx = state
+    .db
+    .begin()
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?;
 
-  // The new order must be an exact permutation of the existing photos:
-  // no duplicates, and the same set of names (which also enforces equal
-  // length). Build a lookup we can drain from so each name is consumed once.
-  let mut lookup: HashMap<String, AlbumItem> =
-    items.into_iter().map(|item| (item.name.clone(), item)).collect();
+  let photos = lock_photos(&mut tx, &slug).await.map_err(|error| {
+    eprintln!("failed to get album photos {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
 
-  let mut reordered: Vec<AlbumItem> = Vec::with_capacity(lookup.len());
-  for name in &payload.order {
-    match lookup.remove(name) {
-      Some(item) => reordered.push(item),
-      None => {
-        // Either an unknown photo, or a duplicate that was already drained.
-        return Ok(HttpResponse::BadRequest().json(
-          json!({"code": "invalid_order", "photo": name}),
-        ));
-      }
+  match check_order(&photos, &payload.order) {
+    Ok(()) => (),
+    Err(OrderError::Invalid(name)) => {
+      return Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "invalid_order", "photo": name})),
+      );
+    }
+    Err(OrderError::Incomplete(missing)) => {
+      return Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "incomplete_order", "missing": missing})),
+      );
     }
   }
 
-  // Anything left in the lookup means the caller omitted a photo.
-  if !lookup.is_empty() {
-    let missing: Vec<String> = lookup.into_keys().collect();
-    return Ok(HttpResponse::BadRequest().json(
-      json!({"code": "incomplete_order", "missing": missing}),
-    ));
-  }
-
-  let album = sqlx::query_as::<_, Album>(
-    "UPDATE photography_albums SET items = $1 WHERE slug = $2 RETURNING *",
+  sqlx::query(
+    "UPDATE photography_photos p SET position = (o.position - 1)::integer \
+     FROM unnest($2::text[]) WITH ORDINALITY AS o (name, position) \
+     WHERE p.album_slug = $1 AND p.name = o.name",
   )
-  .bind(serde_json::to_value(reordered).unwrap())
   .bind(&slug)
-  .fetch_one(&state.db)
+  .bind(&payload.order)
+  .execute(&mut *tx)
   .await
   .map_err(|error| {
-    eprintln!("failed to update album in database {:?}", error);
+    eprintln!("failed to reorder photos in database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  let photos = find_photos(&mut *tx, &slug)
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+
+  tx.commit().await.map_err(|error| {
+    eprintln!("failed to reorder photos in database {:?}", error);
     ErrorInternalServerError(error.to_string())
   })?;
 
-  Ok(HttpResponse::Ok().json(GetAlbumResponse::from(album)))
+  Ok(
+    HttpResponse::Ok()
+      .json(GetAlbumResponse::from(PublicAlbum::new(album, photos))),
+  )
 }
 
 #[delete("/albums/{slug}/photos/{name}")]
@@ -614,39 +1006,54 @@ async fn delete_photo(
 ) -> Result<HttpResponse, Error> {
   let (slug, name) = path.into_inner();
 
-  let album = match find_album(&state.db, &slug)
+  if find_album(&state.db, &slug)
     .await
     .map_err(|error| ErrorInternalServerError(error.to_string()))?
+    .is_none()
   {
-    Some(album) => album,
-    None => {
-      return Ok(
-        HttpResponse::NotFound().json(json!({"code": "album_not_found"})),
-      );
-    }
-  };
-
-  let mut items: Vec<AlbumItem> =
-    serde_json::from_value(album.items.clone())
-      .map_err(|error| ErrorInternalServerError(error.to_string()))?;
-
-  let photo_index = items.iter().position(|item| item.name == name);
-  if photo_index.is_none() {
     return Ok(
-      HttpResponse::NotFound().json(json!({"code": "photo_not_found"})),
+      HttpResponse::NotFound().json(json!({"code": "album_not_found"})),
     );
   }
 
-  let photo = items.remove(photo_index.unwrap());
+  let mut tx = state
+    .db
+    .begin()
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+
+  let deleted = sqlx::query(
+    "DELETE FROM photography_photos WHERE album_slug = $1 AND name = $2",
+  )
+  .bind(&slug)
+  .bind(&name)
+  .execute(&mut *tx)
+  .await
+  .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+
+  if deleted.rows_affected() == 0 {
+    return Ok(
+      HttpResponse::NotFound().json(json!({"code": "photo_not_found"})),
+    );
+  }
 
   // Clear the cover if we just deleted the photo it referenced.
-  let cover = if album.cover.as_deref() == Some(photo.name.as_str()) {
-    None
-  } else {
-    album.cover
-  };
+  sqlx::query(
+    "UPDATE photography_albums SET cover = NULL \
+     WHERE slug = $1 AND cover = $2",
+  )
+  .bind(&slug)
+  .bind(&name)
+  .execute(&mut *tx)
+  .await
+  .map_err(|error| ErrorInternalServerError(error.to_string()))?;
 
-  let path = format!("gallery/albums/{}/{}", slug.clone(), name);
+  // The row stays (the transaction rolls back) unless the original is
+  // actually gone from S3.
+  let dir = album_dir(&mut *tx, &slug)
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+  let path = original_path(&dir, &name);
   let s3 = &state.s3;
   let response = s3.cdn_bucket.delete_object(&path).await.unwrap();
   if response.status_code() != 204 {
@@ -656,15 +1063,612 @@ async fn delete_photo(
     );
   }
 
-  sqlx::query(
-    "UPDATE photography_albums SET items = $1, cover = $2 WHERE slug = $3",
+  tx.commit()
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+
+  delete_photo_variants(&state, &slug, &name).await;
+
+  Ok(HttpResponse::NoContent().finish())
+}
+
+/// Error code for a transfer that can't happen, before anything is looked
+/// up: a name that isn't a single path segment, or the photo itself.
+fn invalid_transfer(
+  from: (&str, &str),
+  to: (&str, &str),
+) -> Option<&'static str> {
+  if to.1.is_empty() || to.1.contains('/') {
+    Some("invalid_photo_name")
+  } else if from == to {
+    Some("same_photo")
+  } else {
+    None
+  }
+}
+
+/// The cover an album whose cover was the transferred photo ends up with.
+/// A renamed cover follows the photo and a cover moved out of its album is
+/// cleared. Copies leave the source untouched, so `None` means no change.
+fn transferred_cover<'a>(
+  slug: &str,
+  to: (&str, &'a str),
+  keep_source: bool,
+) -> Option<Option<&'a str>> {
+  if keep_source {
+    return None;
+  }
+
+  Some((slug == to.0).then_some(to.1))
+}
+
+/// Shared by renaming, moving and copying a photo. The original and its
+/// variants are copied server-side in S3, then the rows and cover
+/// references are updated in one transaction. A move only deletes the
+/// source objects once that has committed.
+async fn transfer_photo(
+  state: &ServerState,
+  from: (String, String),
+  to: (String, String),
+  keep_source: bool,
+) -> Result<HttpResponse, Error> {
+  let (slug, name) = from;
+  let (target_slug, target_name) = to;
+
+  if let Some(code) =
+    invalid_transfer((&slug, &name), (&target_slug, &target_name))
+  {
+    return Ok(HttpResponse::BadRequest().json(json!({"code": code})));
+  }
+
+  let album = match find_album(&state.db, &slug)
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?
+  {
+    Some(album) => album,
+    None => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "album_not_found"})),
+      );
+    }
+  };
+  let target_album = match find_album(&state.db, &target_slug)
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?
+  {
+    Some(album) => album,
+    None => {
+      return Ok(
+        HttpResponse::NotFound()
+          .json(json!({"code": "target_album_not_found"})),
+      );
+    }
+  };
+
+  let mut tx = state
+    .db
+    .begin()
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+
+  let photo = sqlx::query_as::<_, Photo>(
+    "SELECT * FROM photography_photos \
+     WHERE album_slug = $1 AND name = $2 FOR UPDATE",
   )
-  .bind(serde_json::to_value(&items).unwrap())
-  .bind(cover)
   .bind(&slug)
-  .execute(&state.db)
+  .bind(&name)
+  .fetch_optional(&mut *tx)
+  .await
+  .map_err(|error| {
+    eprintln!("failed to get photo from database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  let photo = match photo {
+    Some(photo) => photo,
+    None => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "photo_not_found"})),
+      );
+    }
+  };
+
+  let (taken,) = sqlx::query_as::<_, (bool,)>(
+    "SELECT EXISTS (SELECT 1 FROM photography_photos \
+     WHERE album_slug = $1 AND name = $2)",
+  )
+  .bind(&target_slug)
+  .bind(&target_name)
+  .fetch_one(&mut *tx)
   .await
   .map_err(|error| ErrorInternalServerError(error.to_string()))?;
 
-  Ok(HttpResponse::NoContent().finish())
+  if taken {
+    return Ok(
+      HttpResponse::Conflict()
+        .json(json!({"code": "photo_already_exists"})),
+    );
+  }
+
+  let path = original_path(&album.storage_dir, &name);
+  let target_path = original_path(&target_album.storage_dir, &target_name);
+  let copied = state
+    .s3
+    .cdn_bucket
+    .copy_object_internal(&path, &target_path)
+    .await
+    .is_ok_and(|status| status == 200);
+
+  if !copied {
+    return Ok(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "failed_copy_in_s3"})),
+    );
+  }
+
+  let variants = copy_photo_variants(
+    state,
+    (&slug, &name),
+    (&target_slug, &target_name),
+    &photo.variants,
+  )
+  .await;
+
+  if !keep_source && slug == target_slug {
+    // A rename keeps the photo's place in the album.
+    sqlx::query(
+      "UPDATE photography_photos SET name = $3, variants = $4 \
+       WHERE album_slug = $1 AND name = $2",
+    )
+    .bind(&slug)
+    .bind(&name)
+    .bind(&target_name)
+    .bind(Json(&variants))
+    .execute(&mut *tx)
+    .await
+    .map_err(|error| {
+      eprintln!("failed to rename photo in database {:?}", error);
+      ErrorInternalServerError(error.to_string())
+    })?;
+  } else {
+    // Anything else lands at the end of the target album with the same
+    // caption, frame, keywords and metadata.
+    sqlx::query(
+      "INSERT INTO photography_photos ( \
+         album_slug, name, position, caption, instagram, frame, exif, \
+         width, height, aspect_ratio, variants, blurhash, lqip, uploaded_at, \
+         keywords, tags, latitude, longitude \
+       ) \
+       SELECT $3, $4, ( \
+           SELECT COALESCE(MAX(position) + 1, 0) FROM photography_photos \
+           WHERE album_slug = $3 \
+         ), caption, instagram, frame, exif, width, height, aspect_ratio, \
+         $5, blurhash, lqip, uploaded_at, keywords, tags, latitude, longitude \
+       FROM photography_photos WHERE album_slug = $1 AND name = $2",
+    )
+    .bind(&slug)
+    .bind(&name)
+    .bind(&target_slug)
+    .bind(&target_name)
+    .bind(Json(&variants))
+    .execute(&mut *tx)
+    .await
+    .map_err(|error| {
+      eprintln!("failed to copy photo in database {:?}", error);
+      ErrorInternalServerError(error.to_string())
+    })?;
+
+    if !keep_source {
+      sqlx::query(
+        "DELETE FROM photography_photos WHERE album_slug = $1 AND name = $2",
+      )
+      .bind(&slug)
+      .bind(&name)
+      .execute(&mut *tx)
+      .await
+      .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+    }
+  }
+
+  if let Some(cover) =
+    transferred_cover(&slug, (&target_slug, &target_name), keep_source)
+  {
+    sqlx::query(
+      "UPDATE photography_albums SET cover = $3 \
+       WHERE slug = $1 AND cover = $2",
+    )
+    .bind(&slug)
+    .bind(&name)
+    .bind(cover)
+    .execute(&mut *tx)
+    .await
+    .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+  }
+
+  let mut albums = Vec::with_capacity(2);
+  for album_slug in [&slug, &target_slug] {
+    let album = find_album(&mut *tx, album_slug)
+      .await
+      .map_err(|error| ErrorInternalServerError(error.to_string()))?
+      .ok_or_else(|| ErrorInternalServerError("album disappeared"))?;
+    let photos = find_photos(&mut *tx, album_slug)
+      .await
+      .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+    albums.push(PublicAlbum::new(album, photos));
+  }
+
+  tx.commit().await.map_err(|error| {
+    eprintln!("failed to transfer photo in database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  // The rows already point at the copies, so a failed delete only leaves
+  // an orphaned object behind.
+  if !keep_source {
+    if let Err(error) = state.s3.cdn_bucket.delete_object(&path).await {
+      tracing::warn!("Failed to delete moved photo {}: {}", path, error);
+    }
+    delete_photo_variants(state, &slug, &name).await;
+  }
+
+  let target = albums.pop().unwrap();
+  let source = albums.pop().unwrap();
+  Ok(HttpResponse::Ok().json(TransferPhotoResponse { source, target }))
+}
+
+#[post("/albums/{slug}/photos/{name}/rename")]
+async fn rename_photo(
+  state: web::Data<ServerState>,
+  path: web::Path<(String, String)>,
+  payload: web::Json<RenamePhotoPayload>,
+) -> Result<HttpResponse, Error> {
+  let (slug, name) = path.into_inner();
+  let payload = payload.into_inner();
+
+  transfer_photo(&state, (slug.clone(), name), (slug, payload.name), false)
+    .await
+}
+
+#[post("/albums/{slug}/photos/{name}/move")]
+async fn move_photo(
+  state: web::Data<ServerState>,
+  path: web::Path<(String, String)>,
+  payload: web::Json<TransferPhotoPayload>,
+) -> Result<HttpResponse, Error> {
+  let (slug, name) = path.into_inner();
+  let payload = payload.into_inner();
+  let target_name = payload.name.unwrap_or_else(|| name.clone());
+
+  transfer_photo(&state, (slug, name), (payload.album, target_name), false)
+    .await
+}
+
+#[post("/albums/{slug}/photos/{name}/copy")]
+async fn copy_photo(
+  state: web::Data<ServerState>,
+  path: web::Path<(String, String)>,
+  payload: web::Json<TransferPhotoPayload>,
+) -> Result<HttpResponse, Error> {
+  let (slug, name) = path.into_inner();
+  let payload = payload.into_inner();
+  let target_name = payload.name.unwrap_or_else(|| name.clone());
+
+  transfer_photo(&state, (slug, name), (payload.album, target_name), true)
+    .await
+}
+
+/// Re-read EXIF data for every photo in an album from the originals in S3,
+/// for photos uploaded before it was extracted. Takes the location out of
+/// any original that still has one.
+#[post("/albums/{slug}/exif")]
+async fn refresh_exif(
+  state: web::Data<ServerState>,
+  slug: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+  let slug = slug.into_inner();
+
+  let album = match find_album(&state.db, &slug).await.map_err(|error| {
+    eprintln!("failed to get album from database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })? {
+    Some(album) => album,
+    None => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "album_not_found"})),
+      );
+    }
+  };
+
+  let photos = find_photos(&state.db, &slug).await.map_err(|error| {
+    eprintln!("failed to get album photos {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  let s3 = &state.s3;
+  let mut updated: Vec<String> = Vec::new();
+  let mut skipped: Vec<SkippedUpload> = Vec::new();
+
+  for photo in photos.iter() {
+    let path = original_path(&album.storage_dir, &photo.name);
+    let object = match s3.cdn_bucket.get_object(&path).await {
+      Ok(object) if object.status_code() == 200 => object,
+      _ => {
+        skipped.push(SkippedUpload {
+          name: Some(photo.name.clone()),
+          reason: "failed_fetch_from_s3".to_string(),
+        });
+        continue;
+      }
+    };
+
+    let mut exif = match extract_exif(object.bytes()) {
+      Some(exif) => exif,
+      None => {
+        skipped.push(SkippedUpload {
+          name: Some(photo.name.clone()),
+          reason: "no_exif_data".to_string(),
+        });
+        continue;
+      }
+    };
+
+    // Originals uploaded before locations were stripped still have one, so
+    // take it out now. Once stripped, the location already stored is kept.
+    if let Some(stripped) = strip_gps(object.bytes()) {
+      let file_type = helpers::detect_type(&stripped);
+      let stored = s3
+        .cdn_bucket
+        .put_object_with_content_type(&path, &stripped, &file_type.mime)
+        .await
+        .is_ok_and(|response| response.status_code() == 200);
+      if !stored {
+        skipped.push(SkippedUpload {
+          name: Some(photo.name.clone()),
+          reason: "failed_upload_to_s3".to_string(),
+        });
+        continue;
+      }
+    }
+    if exif.gps.is_none() {
+      exif.gps = photo.exif.as_ref().and_then(|stored| stored.gps.clone());
+    }
+
+    // Coordinates only come from GPS when none were set by hand.
+    let gps = exif.gps.clone();
+    sqlx::query(
+      "UPDATE photography_photos SET exif = $1, \
+         latitude = COALESCE(latitude, $2), \
+         longitude = COALESCE(longitude, $3) \
+       WHERE album_slug = $4 AND name = $5",
+    )
+    .bind(Json(exif))
+    .bind(gps.as_ref().map(|gps| gps.latitude))
+    .bind(gps.as_ref().map(|gps| gps.longitude))
+    .bind(&slug)
+    .bind(&photo.name)
+    .execute(&state.db)
+    .await
+    .map_err(|error| {
+      eprintln!("failed to update photo in database {:?}", error);
+      ErrorInternalServerError(error.to_string())
+    })?;
+
+    updated.push(photo.name.clone());
+  }
+
+  let photos = find_photos(&state.db, &slug).await.map_err(|error| {
+    eprintln!("failed to get album photos {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  Ok(HttpResponse::Ok().json(RefreshExifResponse {
+    album: PublicAlbum::new(album, photos),
+    updated,
+    skipped,
+  }))
+}
+
+fn backfill_response(state: &web::Data<ServerState>) -> HttpResponse {
+  if start_photo_backfill(web::Data::clone(state)) {
+    HttpResponse::Accepted().json(json!({"code": "backfill_started"}))
+  } else {
+    HttpResponse::Conflict().json(json!({"code": "backfill_running"}))
+  }
+}
+
+/// Generate variants and placeholders for photos uploaded before they
+/// existed. Runs in the background and logs its progress.
+#[post("/backfill")]
+async fn backfill_photos(
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  Ok(backfill_response(&state))
+}
+
+/// Same as `/backfill`, under the name it had when only variants were
+/// generated.
+#[post("/variants/backfill")]
+async fn backfill_variants(
+  state: web::Data<ServerState>,
+) -> Result<HttpResponse, Error> {
+  Ok(backfill_response(&state))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn photo(name: &str) -> Photo {
+    Photo {
+      album_slug: "album".to_string(),
+      name: name.to_string(),
+      position: 0,
+      caption: None,
+      instagram: None,
+      frame: None,
+      exif: None,
+      width: None,
+      height: None,
+      aspect_ratio: None,
+      variants: Json(Vec::new()),
+      blurhash: None,
+      lqip: None,
+      uploaded_at: chrono::NaiveDateTime::default(),
+      keywords: Vec::new(),
+      tags: Vec::new(),
+      latitude: None,
+      longitude: None,
+    }
+  }
+
+  fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+  }
+
+  #[test]
+  fn rejects_transfers_that_go_nowhere() {
+    let from = ("album", "a.jpg");
+
+    assert_eq!(
+      invalid_transfer(from, ("album", "")),
+      Some("invalid_photo_name")
+    );
+    assert_eq!(
+      invalid_transfer(from, ("album", "../a.jpg")),
+      Some("invalid_photo_name")
+    );
+    assert_eq!(
+      invalid_transfer(from, ("album", "a.jpg")),
+      Some("same_photo")
+    );
+    assert_eq!(invalid_transfer(from, ("album", "b.jpg")), None);
+    assert_eq!(invalid_transfer(from, ("other", "a.jpg")), None);
+  }
+
+  #[test]
+  fn renamed_covers_follow_the_photo() {
+    assert_eq!(
+      transferred_cover("album", ("album", "b.jpg"), false),
+      Some(Some("b.jpg"))
+    );
+  }
+
+  #[test]
+  fn moved_covers_are_cleared() {
+    assert_eq!(
+      transferred_cover("album", ("other", "a.jpg"), false),
+      Some(None)
+    );
+    assert_eq!(
+      transferred_cover("album", ("other", "b.jpg"), false),
+      Some(None)
+    );
+  }
+
+  #[test]
+  fn copies_leave_covers_alone() {
+    assert_eq!(transferred_cover("album", ("other", "a.jpg"), true), None);
+    assert_eq!(transferred_cover("album", ("album", "b.jpg"), true), None);
+  }
+
+  #[test]
+  fn normalizes_keywords() {
+    let keywords =
+      names(&[" Landscape", "landscape ", "", "  ", "Iceland"]);
+
+    assert_eq!(
+      normalize_keywords(Some(&keywords)),
+      ["landscape", "iceland"]
+    );
+    assert!(normalize_keywords(None).is_empty());
+  }
+
+  #[test]
+  fn applies_bulk_updates_to_all_then_to_each_photo() {
+    let mut photos = [photo("a.jpg"), photo("b.jpg")];
+    photos[1].caption = Some("Glacier".to_string());
+    photos[1].tags = names(&["travel"]);
+
+    let payload: BulkUpdatePhotosPayload = serde_json::from_value(json!({
+      "apply_to_all": {"keywords": ["Iceland", " iceland"], "caption": null},
+      "photos": [{"name": "b.jpg", "keywords": ["Glacier"]}],
+    }))
+    .unwrap();
+    apply_bulk_update(&mut photos, &payload);
+
+    assert_eq!(photos[0].keywords, ["iceland"]);
+    assert_eq!(photos[1].keywords, ["glacier"]);
+    assert_eq!(photos[1].caption, None);
+    // Fields left out keep their value.
+    assert_eq!(photos[1].tags, ["travel"]);
+  }
+
+  #[test]
+  fn clears_keywords_set_to_null() {
+    let mut photos = [photo("a.jpg")];
+    photos[0].keywords = names(&["iceland"]);
+
+    let payload: BulkUpdatePhotosPayload = serde_json::from_value(json!({
+      "photos": [{"name": "a.jpg", "keywords": null}],
+    }))
+    .unwrap();
+    apply_bulk_update(&mut photos, &payload);
+
+    assert!(photos[0].keywords.is_empty());
+  }
+
+  #[test]
+  fn accepts_orders_that_are_a_permutation() {
+    let photos = [photo("a.jpg"), photo("b.jpg"), photo("c.jpg")];
+    let order = names(&["c.jpg", "a.jpg", "b.jpg"]);
+
+    assert_eq!(check_order(&photos, &order), Ok(()));
+  }
+
+  #[test]
+  fn rejects_unknown_and_repeated_photos_in_orders() {
+    let photos = [photo("a.jpg"), photo("b.jpg")];
+
+    let order = names(&["a.jpg", "x.jpg"]);
+    assert_eq!(
+      check_order(&photos, &order),
+      Err(OrderError::Invalid("x.jpg"))
+    );
+
+    let order = names(&["a.jpg", "a.jpg", "b.jpg"]);
+    assert_eq!(
+      check_order(&photos, &order),
+      Err(OrderError::Invalid("a.jpg"))
+    );
+  }
+
+  #[test]
+  fn lists_photos_left_out_of_orders() {
+    let photos = [photo("c.jpg"), photo("a.jpg"), photo("b.jpg")];
+    let order = names(&["b.jpg"]);
+
+    assert_eq!(
+      check_order(&photos, &order),
+      Err(OrderError::Incomplete(vec!["a.jpg", "c.jpg"]))
+    );
+  }
+
+  #[test]
+  fn finds_photos_bulk_updates_target_that_are_missing() {
+    let photos = [photo("a.jpg"), photo("b.jpg")];
+    let payload: BulkUpdatePhotosPayload = serde_json::from_value(json!({
+      "photos": [
+        {"name": "a.jpg", "caption": "Sunset"},
+        {"name": "x.jpg", "caption": "Sunrise"},
+      ]
+    }))
+    .unwrap();
+
+    assert_eq!(missing_photos(&photos, &payload), ["x.jpg"]);
+
+    let payload: BulkUpdatePhotosPayload =
+      serde_json::from_value(json!({"apply_to_all": {"caption": null}}))
+        .unwrap();
+    assert!(missing_photos(&photos, &payload).is_empty());
+  }
 }
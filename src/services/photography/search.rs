@@ -0,0 +1,199 @@
+use actix_web::{
+  error::ErrorInternalServerError, get, http::header::AUTHORIZATION, web,
+  Error, HttpRequest, HttpResponse,
+};
+use chrono::{Days, NaiveDateTime, NaiveTime};
+
+use crate::{
+  helpers::authentication::is_management_authed,
+  structs::photography::{
+    Photo, PhotoSearchQuery, PhotoSearchResponse, PhotoSearchResult,
+  },
+  ServerState,
+};
+
+/// Escape `LIKE` wildcards so search text only matches literally.
+fn like_pattern(text: &str) -> String {
+  let escaped = text
+    .trim()
+    .replace('\\', "\\\\")
+    .replace('%', "\\%")
+    .replace('_', "\\_");
+  format!("%{}%", escaped)
+}
+
+/// A search's filters, as bound to the query. Blank text is no filter.
+#[derive(Debug, PartialEq)]
+struct SearchFilters {
+  keyword: Option<String>,
+  caption: Option<String>,
+  camera: Option<String>,
+  from: Option<NaiveDateTime>,
+  /// Exclusive: the start of the day after `to`, so `to` is inclusive.
+  until: Option<NaiveDateTime>,
+  limit: i64,
+  offset: i64,
+}
+
+fn search_filters(query: &PhotoSearchQuery) -> SearchFilters {
+  let text = |text: &Option<String>| {
+    text
+      .as_deref()
+      .map(str::trim)
+      .filter(|text| !text.is_empty())
+      .map(str::to_string)
+  };
+
+  SearchFilters {
+    keyword: text(&query.keyword).map(|keyword| keyword.to_lowercase()),
+    caption: text(&query.q).map(|caption| like_pattern(&caption)),
+    camera: text(&query.camera).map(|camera| like_pattern(&camera)),
+    from: query.from.map(|from| from.and_time(NaiveTime::MIN)),
+    until: query
+      .to
+      .and_then(|to| to.checked_add_days(Days::new(1)))
+      .map(|to| to.and_time(NaiveTime::MIN)),
+    limit: query.limit.unwrap_or(50).clamp(1, 200),
+    offset: query.offset.unwrap_or(0).max(0),
+  }
+}
+
+/// Find photos across albums. Without management auth only photos in
+/// public albums are searched.
+#[get("/search")]
+async fn search_photos(
+  state: web::Data<ServerState>,
+  req: HttpRequest,
+  query: web::Query<PhotoSearchQuery>,
+) -> Result<HttpResponse, Error> {
+  let valkey = &mut state.valkey.clone();
+  let is_management_authed =
+    is_management_authed(valkey, req.headers().get(AUTHORIZATION))
+      .await
+      .unwrap_or(false);
+
+  let filters = search_filters(&query);
+
+  let photos = sqlx::query_as::<_, Photo>(
+    "SELECT p.* FROM photography_photos p \
+     JOIN photography_albums a ON a.slug = p.album_slug \
+     CROSS JOIN LATERAL (SELECT COALESCE( \
+       (p.exif ->> 'taken_at')::timestamp, p.uploaded_at \
+     ) AS taken_at) t \
+     WHERE ($1 OR a.visibility = 'public') \
+       AND ($2::text IS NULL OR $2 = ANY (p.keywords) OR $2 = ANY (p.tags)) \
+       AND ($3::text IS NULL OR p.caption ILIKE $3) \
+       AND ($4::text IS NULL OR p.exif ->> 'camera' ILIKE $4) \
+       AND ($5::timestamp IS NULL OR t.taken_at >= $5) \
+       AND ($6::timestamp IS NULL OR t.taken_at < $6) \
+     ORDER BY t.taken_at DESC, p.album_slug, p.position \
+     LIMIT $7 OFFSET $8",
+  )
+  .bind(is_management_authed)
+  .bind(filters.keyword)
+  .bind(filters.caption)
+  .bind(filters.camera)
+  .bind(filters.from)
+  .bind(filters.until)
+  .bind(filters.limit)
+  .bind(filters.offset)
+  .fetch_all(&state.db)
+  .await
+  .map_err(|error| {
+    eprintln!("failed to search photos in database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  let photos = photos
+    .into_iter()
+    .map(|photo| PhotoSearchResult {
+      taken_at: photo.exif.as_ref().and_then(|exif| exif.taken_at),
+      album: photo.album_slug,
+      name: photo.name,
+      caption: photo.caption,
+      keywords: photo.keywords,
+      tags: photo.tags,
+      variants: photo.variants.0,
+    })
+    .collect();
+
+  Ok(HttpResponse::Ok().json(PhotoSearchResponse { photos }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn filters(query: serde_json::Value) -> SearchFilters {
+    search_filters(&serde_json::from_value(query).unwrap())
+  }
+
+  fn at(time: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(time, "%F %T").ok()
+  }
+
+  #[test]
+  fn escapes_like_wildcards() {
+    assert_eq!(like_pattern(" sunset "), "%sunset%");
+    assert_eq!(like_pattern("100%"), "%100\\%%");
+    assert_eq!(like_pattern("a_b"), "%a\\_b%");
+    assert_eq!(like_pattern("c:\\dir"), "%c:\\\\dir%");
+  }
+
+  #[test]
+  fn defaults_to_every_photo() {
+    assert_eq!(
+      filters(json!({})),
+      SearchFilters {
+        keyword: None,
+        caption: None,
+        camera: None,
+        from: None,
+        until: None,
+        limit: 50,
+        offset: 0,
+      }
+    );
+  }
+
+  #[test]
+  fn normalizes_text_filters() {
+    let filters = filters(json!({
+      "keyword": " Landscape ",
+      "q": "golden hour",
+      "camera": "EOS",
+    }));
+
+    assert_eq!(filters.keyword.as_deref(), Some("landscape"));
+    assert_eq!(filters.caption.as_deref(), Some("%golden hour%"));
+    assert_eq!(filters.camera.as_deref(), Some("%EOS%"));
+  }
+
+  #[test]
+  fn ignores_blank_text_filters() {
+    let filters =
+      filters(json!({"keyword": " ", "q": "", "camera": "  "}));
+
+    assert_eq!(filters.keyword, None);
+    assert_eq!(filters.caption, None);
+    assert_eq!(filters.camera, None);
+  }
+
+  #[test]
+  fn includes_the_whole_last_day() {
+    let filters =
+      filters(json!({"from": "2024-05-01", "to": "2024-05-31"}));
+
+    assert_eq!(filters.from, at("2024-05-01 00:00:00"));
+    assert_eq!(filters.until, at("2024-06-01 00:00:00"));
+  }
+
+  #[test]
+  fn clamps_pagination() {
+    let large = filters(json!({"limit": 1000, "offset": -5}));
+    assert_eq!((large.limit, large.offset), (200, 0));
+
+    assert_eq!(filters(json!({"limit": 0})).limit, 1);
+  }
+}
@@ -0,0 +1,187 @@
+use rand::{distributions::Alphanumeric, Rng};
+
+use crate::{
+  services::photography::variants::variants_url,
+  structs::photography::Album, ServerState,
+};
+
+/// S3 key of a photo's original.
+pub(crate) fn original_path(dir: &str, name: &str) -> String {
+  format!("gallery/albums/{}/{}", dir, name)
+}
+
+/// Directory under `gallery/albums/` an album's files are stored in.
+pub(crate) async fn album_dir<'e>(
+  db: impl sqlx::PgExecutor<'e>,
+  slug: &str,
+) -> Result<String, sqlx::Error> {
+  sqlx::query_scalar::<_, String>(
+    "SELECT storage_dir FROM photography_albums WHERE slug = $1",
+  )
+  .bind(slug)
+  .fetch_one(db)
+  .await
+}
+
+/// Whether an album's files are kept where the slug alone can't find them.
+fn is_hidden(visibility: &str) -> bool {
+  matches!(visibility, "draft" | "password")
+}
+
+/// Directory a new album with `visibility` keeps its files in. Public and
+/// unlisted albums use their slug; draft and password ones get a random
+/// suffix, since the CDN serves whatever is stored there to anyone.
+pub(crate) fn new_album_dir(slug: &str, visibility: &str) -> String {
+  if !is_hidden(visibility) {
+    return slug.to_string();
+  }
+
+  let suffix: String = rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(32)
+    .map(char::from)
+    .collect();
+  format!("{}-{}", slug, suffix)
+}
+
+/// Directory an album's files need to move to for its visibility, if they
+/// aren't already in the right kind of place.
+fn relocation_dir(album: &Album) -> Option<String> {
+  let hidden = album.storage_dir != album.slug;
+  (hidden != is_hidden(&album.visibility))
+    .then(|| new_album_dir(&album.slug, &album.visibility))
+}
+
+/// Move an album's files to where its visibility says they belong, after
+/// its visibility changes. Objects are copied, the album and its variant
+/// URLs switched over, and only then are the old objects deleted. Returns
+/// whether the files are where they should be.
+pub(crate) async fn relocate_album(
+  state: &ServerState,
+  album: &Album,
+) -> bool {
+  let dir = match relocation_dir(album) {
+    Some(dir) => dir,
+    None => return true,
+  };
+
+  let from = format!("gallery/albums/{}/", album.storage_dir);
+  let to = format!("gallery/albums/{}/", dir);
+
+  let keys: Vec<String> =
+    match state.s3.cdn_bucket.list(from.clone(), None).await {
+      Ok(listings) => listings
+        .into_iter()
+        .flat_map(|listing| listing.contents)
+        .map(|object| object.key)
+        .collect(),
+      Err(error) => {
+        tracing::warn!("Failed to list album files {}: {}", from, error);
+        return false;
+      }
+    };
+
+  for key in &keys {
+    let target = format!("{}{}", to, key.trim_start_matches(&from));
+    let copied = state
+      .s3
+      .cdn_bucket
+      .copy_object_internal(key, &target)
+      .await
+      .is_ok_and(|status| status == 200);
+
+    if !copied {
+      tracing::warn!("Failed to copy album file {} to {}", key, target);
+      return false;
+    }
+  }
+
+  let switched = async {
+    let mut tx = state.db.begin().await?;
+
+    sqlx::query(
+      "UPDATE photography_albums SET storage_dir = $1 \
+       WHERE slug = $2 AND storage_dir = $3",
+    )
+    .bind(&dir)
+    .bind(&album.slug)
+    .bind(&album.storage_dir)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+      "UPDATE photography_photos \
+       SET variants = replace(variants::text, $1, $2)::jsonb \
+       WHERE album_slug = $3",
+    )
+    .bind(format!("\"{}/", variants_url(&album.storage_dir)))
+    .bind(format!("\"{}/", variants_url(&dir)))
+    .bind(&album.slug)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+  };
+
+  if let Err(error) = switched.await {
+    eprintln!("failed to move album in database {:?}", error);
+    return false;
+  }
+
+  for key in &keys {
+    if let Err(error) = state.s3.cdn_bucket.delete_object(key).await {
+      tracing::warn!("Failed to delete old album file {}: {}", key, error);
+    }
+  }
+
+  tracing::info!("Moved album {} to {}", album.slug, dir);
+  true
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::NaiveDateTime;
+
+  fn album(slug: &str, storage_dir: &str, visibility: &str) -> Album {
+    Album {
+      slug: slug.to_string(),
+      name: slug.to_string(),
+      location: None,
+      description: None,
+      cover: None,
+      date: NaiveDateTime::default(),
+      visibility: visibility.to_string(),
+      password: None,
+      latitude: None,
+      longitude: None,
+      storage_dir: storage_dir.to_string(),
+    }
+  }
+
+  #[test]
+  fn hides_draft_and_password_albums() {
+    assert_eq!(new_album_dir("trip", "public"), "trip");
+    assert_eq!(new_album_dir("trip", "unlisted"), "trip");
+
+    for visibility in ["draft", "password"] {
+      let dir = new_album_dir("trip", visibility);
+      assert!(dir.starts_with("trip-"));
+      assert_eq!(dir.len(), "trip-".len() + 32);
+      assert_ne!(dir, new_album_dir("trip", visibility));
+    }
+  }
+
+  #[test]
+  fn relocates_only_when_visibility_and_dir_disagree() {
+    assert_eq!(relocation_dir(&album("trip", "trip", "public")), None);
+    assert_eq!(relocation_dir(&album("trip", "trip-x", "draft")), None);
+    assert_eq!(
+      relocation_dir(&album("trip", "trip-x", "unlisted")).as_deref(),
+      Some("trip")
+    );
+
+    let dir = relocation_dir(&album("trip", "trip", "password")).unwrap();
+    assert!(dir.starts_with("trip-"));
+  }
+}
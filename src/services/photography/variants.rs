@@ -0,0 +1,229 @@
+use actix_web::web;
+use envconfig::Envconfig;
+use sqlx::types::Json;
+
+use crate::{
+  config::Config,
+  helpers::images::{
+    decode_oriented_image, generate_photo_variants, generate_placeholders,
+    Placeholders, PHOTO_SIZES,
+  },
+  services::photography::storage::album_dir,
+  structs::photography::PhotoVariant,
+  ServerState,
+};
+
+/// S3 key of one of a photo's variants, in the album stored at `dir`.
+pub(crate) fn variant_path(dir: &str, size: &str, name: &str) -> String {
+  format!("gallery/albums/{}/variants/{}/{}.webp", dir, size, name)
+}
+
+/// Public URL of the variants of the album stored at `dir`.
+pub(crate) fn variants_url(dir: &str) -> String {
+  let config = Config::init_from_env().unwrap();
+  format!(
+    "{}/{}/variants",
+    config.photography_url.trim_end_matches('/'),
+    dir
+  )
+}
+
+/// Public URL of one of a photo's variants.
+fn variant_url(dir: &str, size: &str, name: &str) -> String {
+  format!("{}/{}/{}.webp", variants_url(dir), size, name)
+}
+
+/// What was derived from a photo's original: its dimensions, loading
+/// placeholders and, when requested, the stored variants.
+pub(crate) struct ProcessedPhoto {
+  pub width: u32,
+  pub height: u32,
+  pub placeholders: Option<Placeholders>,
+  pub variants: Option<Vec<PhotoVariant>>,
+}
+
+impl ProcessedPhoto {
+  pub fn aspect_ratio(&self) -> f64 {
+    let aspect_ratio = self.width as f64 / self.height.max(1) as f64;
+    (aspect_ratio * 10_000.0).round() / 10_000.0
+  }
+}
+
+/// Decode a photo once, off the async workers, and derive everything
+/// stored about it. With `with_variants`, its variants are generated and
+/// uploaded next to the original. Returns `None` only if the file can't be
+/// decoded; if a variant fails to upload the variants are left out so a
+/// later backfill can try again.
+pub(crate) async fn process_photo(
+  state: &ServerState,
+  slug: &str,
+  name: &str,
+  data: bytes::Bytes,
+  with_variants: bool,
+) -> Option<ProcessedPhoto> {
+  let (width, height, placeholders, variants) = web::block(move || {
+    decode_oriented_image(&data).map(|image| {
+      let variants = if with_variants {
+        generate_photo_variants(&image)
+      } else {
+        Vec::new()
+      };
+
+      (
+        image.width(),
+        image.height(),
+        generate_placeholders(&image),
+        variants,
+      )
+    })
+  })
+  .await
+  .ok()
+  .flatten()?;
+
+  let dir = match album_dir(&state.db, slug).await {
+    Ok(dir) => dir,
+    Err(error) => {
+      eprintln!("failed to get album from database {:?}", error);
+      return None;
+    }
+  };
+
+  let mut stored = Vec::new();
+  for (size, variant) in variants {
+    let path = variant_path(&dir, size, name);
+    let uploaded = state
+      .s3
+      .cdn_bucket
+      .put_object_with_content_type(&path, &variant.data, variant.mime)
+      .await
+      .is_ok_and(|response| response.status_code() == 200);
+
+    if !uploaded {
+      tracing::warn!("Failed to upload photo variant {}", path);
+      stored.clear();
+      break;
+    }
+
+    stored.push(PhotoVariant {
+      size: size.to_string(),
+      url: variant_url(&dir, size, name),
+      width: variant.width,
+      height: variant.height,
+    });
+  }
+
+  Some(ProcessedPhoto {
+    width,
+    height,
+    placeholders,
+    variants: (!stored.is_empty()).then_some(stored),
+  })
+}
+
+/// Remove every variant of a photo from S3. Missing ones are ignored.
+pub(crate) async fn delete_photo_variants(
+  state: &ServerState,
+  slug: &str,
+  name: &str,
+) {
+  let dir = match album_dir(&state.db, slug).await {
+    Ok(dir) => dir,
+    Err(error) => {
+      eprintln!("failed to get album from database {:?}", error);
+      return;
+    }
+  };
+
+  for (size, _) in PHOTO_SIZES {
+    let path = variant_path(&dir, size, name);
+    if let Err(error) = state.s3.cdn_bucket.delete_object(&path).await {
+      tracing::warn!("Failed to delete photo variant {}: {}", path, error);
+    }
+  }
+}
+
+/// Copy a photo's variants to another album or name with server-side S3
+/// copies, returning them as they should be stored on the new row. If any
+/// copy fails none are returned, so a later backfill can regenerate them.
+pub(crate) async fn copy_photo_variants(
+  state: &ServerState,
+  from: (&str, &str),
+  to: (&str, &str),
+  variants: &[PhotoVariant],
+) -> Vec<PhotoVariant> {
+  let (from_slug, from_name) = from;
+  let (to_slug, to_name) = to;
+
+  let dirs = (
+    album_dir(&state.db, from_slug).await,
+    album_dir(&state.db, to_slug).await,
+  );
+  let (from_dir, to_dir) = match dirs {
+    (Ok(from_dir), Ok(to_dir)) => (from_dir, to_dir),
+    (Err(error), _) | (_, Err(error)) => {
+      eprintln!("failed to get album from database {:?}", error);
+      return Vec::new();
+    }
+  };
+
+  let mut copied = Vec::new();
+  for variant in variants {
+    let from_path = variant_path(&from_dir, &variant.size, from_name);
+    let to_path = variant_path(&to_dir, &variant.size, to_name);
+    let copied_ok = state
+      .s3
+      .cdn_bucket
+      .copy_object_internal(&from_path, &to_path)
+      .await
+      .is_ok_and(|status| status == 200);
+
+    if !copied_ok {
+      tracing::warn!("Failed to copy photo variant {}", from_path);
+      return Vec::new();
+    }
+
+    copied.push(PhotoVariant {
+      url: variant_url(&to_dir, &variant.size, to_name),
+      ..variant.clone()
+    });
+  }
+
+  copied
+}
+
+/// Store what was derived from a photo on its row. Placeholders and
+/// variants that weren't generated this time keep their current values.
+pub(crate) async fn save_processed_photo(
+  state: &ServerState,
+  slug: &str,
+  name: &str,
+  photo: ProcessedPhoto,
+) -> bool {
+  let aspect_ratio = photo.aspect_ratio();
+  let (blurhash, lqip) = match photo.placeholders {
+    Some(placeholders) => {
+      (Some(placeholders.blurhash), Some(placeholders.lqip))
+    }
+    None => (None, None),
+  };
+
+  sqlx::query(
+    "UPDATE photography_photos SET \
+       width = $1, height = $2, aspect_ratio = $3, \
+       blurhash = COALESCE($4, blurhash), lqip = COALESCE($5, lqip), \
+       variants = COALESCE($6, variants) \
+     WHERE album_slug = $7 AND name = $8",
+  )
+  .bind(photo.width as i32)
+  .bind(photo.height as i32)
+  .bind(aspect_ratio)
+  .bind(blurhash)
+  .bind(lqip)
+  .bind(photo.variants.map(Json))
+  .bind(slug)
+  .bind(name)
+  .execute(&state.db)
+  .await
+  .is_ok_and(|result| result.rows_affected() > 0)
+}
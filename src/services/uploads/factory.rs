@@ -14,5 +14,7 @@ pub fn factory() -> Scope<
 > {
   web::scope("/uploads")
     .wrap(from_fn(services::middleware::auth_middleware))
+    // Ahead of `/{type}`, which would otherwise match `/resumable`.
+    .service(services::uploads::resumable::factory())
     .service(services::uploads::routes::upload_to_cdn)
 }
@@ -53,6 +53,31 @@ pub struct AssetType {
   pub ext: String,
 }
 
+/// Sniff the type of a file from its contents, falling back to plain text
+/// when it isn't recognised.
+pub fn detect_type(data: &[u8]) -> AssetType {
+  match infer::get(data) {
+    Some(kind) => AssetType {
+      mime: kind.mime_type().to_string(),
+      ext: kind.extension().to_string(),
+    },
+    None => AssetType {
+      mime: "text/plain".to_string(),
+      ext: "txt".to_string(),
+    },
+  }
+}
+
+/// Whether a sniffed mime type may be uploaded as `asset_type`.
+pub fn is_allowed_mime(mime: &str, asset_type: &str) -> bool {
+  (if asset_type == "images" {
+    &ALLOWED_IMAGES
+  } else {
+    &ALLOWED_FILES
+  })
+  .contains(&mime)
+}
+
 pub fn is_allowed_type(
   file: &bytes::Bytes,
   asset_type: String,
@@ -61,12 +86,7 @@ pub fn is_allowed_type(
 
   match kind {
     Some(kind) => (
-      (if asset_type == "images" {
-        &ALLOWED_IMAGES
-      } else {
-        &ALLOWED_FILES
-      })
-      .contains(&kind.mime_type()),
+      is_allowed_mime(kind.mime_type(), &asset_type),
       AssetType {
         mime: (&kind.mime_type()).to_string(),
         ext: (&kind.extension()).to_string(),
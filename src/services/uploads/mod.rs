@@ -1,3 +1,4 @@
 pub mod factory;
 pub mod helpers;
+pub mod resumable;
 pub mod routes;
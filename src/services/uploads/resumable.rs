@@ -0,0 +1,1175 @@
+use actix_web::{
+  delete, error::ErrorInternalServerError, patch, post, route, web, Error,
+  HttpMessage, HttpRequest, HttpResponse, Scope,
+};
+use bytes::{Bytes, BytesMut};
+use envconfig::Envconfig;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use rand::{distributions::Alphanumeric, Rng};
+use s3::{error::S3Error, serde_types::Part};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use std::{collections::HashSet, sync::Mutex};
+
+use crate::{
+  config::Config,
+  helpers::exif::strip_gps,
+  services::{
+    blog::{
+      assets::{asset_json, record_asset},
+      posts::find_editable_post,
+    },
+    photography::{
+      routes::{find_album, insert_photo, process_added_photo},
+      storage::original_path,
+    },
+    uploads::helpers::{detect_type, is_allowed_mime, AssetType},
+  },
+  structs::{
+    blog::{BlogAdminUser, BlogAsset},
+    uploads::{
+      CreateResumableUploadPayload, ResumableUpload,
+      ResumableUploadResponse, RESUMABLE_UPLOAD_KINDS,
+    },
+  },
+  ServerState,
+};
+
+/// S3 rejects multipart parts under 5 MiB, other than the last one.
+const MIN_CHUNK_SIZE: usize = 5 * 1024 * 1024;
+/// S3 caps a multipart upload at 10,000 parts.
+const MAX_CHUNKS: usize = 10_000;
+/// How long a request gets to complete an upload before another one can
+/// take over.
+const COMPLETE_TIMEOUT_MINUTES: i32 = 15;
+
+/// Uploads a chunk is currently being read for on this instance.
+static RECEIVING: Lazy<Mutex<HashSet<String>>> =
+  Lazy::new(Default::default);
+
+/// Marks an upload as having a chunk read for it until dropped.
+struct ReceivingChunk(String);
+
+impl ReceivingChunk {
+  fn start(id: &str) -> Option<Self> {
+    let mut receiving = RECEIVING.lock().unwrap();
+    receiving
+      .insert(id.to_string())
+      .then(|| Self(id.to_string()))
+  }
+}
+
+impl Drop for ReceivingChunk {
+  fn drop(&mut self) {
+    RECEIVING.lock().unwrap().remove(&self.0);
+  }
+}
+
+/// Header carrying the offset a chunk starts at, and the bytes received so
+/// far in responses.
+const OFFSET_HEADER: &str = "upload-offset";
+const LENGTH_HEADER: &str = "upload-length";
+
+/// S3 key an upload is assembled at before it is moved into place.
+fn staging_path(id: &str) -> String {
+  format!("uploads/pending/{}", id)
+}
+
+/// The blog admin making the request, when it came in through the blog
+/// admin scope. Everywhere else these routes sit behind management auth.
+fn blog_user(req: &HttpRequest) -> Option<BlogAdminUser> {
+  req.extensions().get::<BlogAdminUser>().cloned()
+}
+
+/// Fetch an upload the requester started. Blog admins only see their own
+/// blog asset uploads, and management only sees its own.
+async fn find_upload<'e>(
+  db: impl sqlx::PgExecutor<'e>,
+  id: &str,
+  owner_id: Option<&str>,
+) -> Result<Option<ResumableUpload>, sqlx::Error> {
+  sqlx::query_as::<_, ResumableUpload>(
+    "SELECT * FROM resumable_uploads \
+     WHERE id = $1 AND owner_id IS NOT DISTINCT FROM $2",
+  )
+  .bind(id)
+  .bind(owner_id)
+  .fetch_optional(db)
+  .await
+}
+
+/// Like `find_upload`, but holds the row for the rest of the transaction.
+/// Fails straight away rather than waiting when another request holds it.
+async fn lock_upload(
+  tx: &mut sqlx::PgConnection,
+  id: &str,
+  owner_id: Option<&str>,
+) -> Result<Option<ResumableUpload>, HttpResponse> {
+  let upload = sqlx::query_as::<_, ResumableUpload>(
+    "SELECT * FROM resumable_uploads \
+     WHERE id = $1 AND owner_id IS NOT DISTINCT FROM $2 \
+     FOR UPDATE NOWAIT",
+  )
+  .bind(id)
+  .bind(owner_id)
+  .fetch_optional(tx)
+  .await;
+
+  match upload {
+    Ok(upload) => Ok(upload),
+    Err(sqlx::Error::Database(error))
+      if error.code().as_deref() == Some("55P03") =>
+    {
+      Err(HttpResponse::Conflict().json(json!({"code": "upload_busy"})))
+    }
+    Err(error) => {
+      eprintln!("failed to lock upload in database {:?}", error);
+      Err(HttpResponse::InternalServerError().finish())
+    }
+  }
+}
+
+/// Whether a finished upload is read back into memory to be decoded:
+/// photos, and blog assets that are images.
+fn is_decoded(kind: &str, content_type: Option<&str>) -> bool {
+  kind == "photography"
+    || (kind == "blog"
+      && content_type.is_some_and(|mime| mime.starts_with("image/")))
+}
+
+/// Why a chunk of `len` bytes can't be appended to an upload with
+/// `remaining` bytes still to come and `parts` parts so far, if it can't.
+fn chunk_error(
+  len: usize,
+  remaining: usize,
+  parts: usize,
+) -> Option<&'static str> {
+  if len == 0 {
+    Some("empty_chunk")
+  } else if len > remaining {
+    Some("chunk_exceeds_upload_size")
+  } else if len < remaining && len < MIN_CHUNK_SIZE {
+    Some("chunk_too_small")
+  } else if parts >= MAX_CHUNKS {
+    Some("too_many_chunks")
+  } else {
+    None
+  }
+}
+
+/// Whether a failed part took its S3 upload down with it. rust-s3 aborts
+/// the S3 upload when S3 answers a part with an error, but a part that
+/// never got an answer leaves it as it was, so the chunk can be sent again.
+fn part_aborted_upload(error: &S3Error) -> bool {
+  matches!(error, S3Error::Http(..) | S3Error::HttpFail)
+}
+
+fn upload_response(
+  status: &mut actix_web::HttpResponseBuilder,
+  upload: &ResumableUpload,
+) -> HttpResponse {
+  let config = Config::init_from_env().unwrap();
+
+  status
+    .insert_header((OFFSET_HEADER, upload.received.to_string()))
+    .insert_header((LENGTH_HEADER, upload.size.to_string()))
+    .json(ResumableUploadResponse::new(
+      upload,
+      config.upload_abandon_hours,
+    ))
+}
+
+fn upload_conflict(code: &str, upload: &ResumableUpload) -> HttpResponse {
+  HttpResponse::Conflict()
+    .insert_header((OFFSET_HEADER, upload.received.to_string()))
+    .json(json!({"code": code, "received": upload.received}))
+}
+
+/// Remove whatever an unfinished upload left in S3: the multipart upload
+/// while chunks are still coming in, or the assembled file after.
+pub(crate) async fn discard_upload_objects(
+  state: &ServerState,
+  upload: &ResumableUpload,
+) {
+  let path = staging_path(&upload.id);
+
+  match &upload.s3_upload_id {
+    Some(upload_id) => {
+      if let Err(error) =
+        state.s3.cdn_bucket.abort_upload(&path, upload_id).await
+      {
+        tracing::warn!("Failed to abort S3 upload {}: {}", path, error);
+      }
+    }
+    None if upload.received > 0 => {
+      let _ = state.s3.cdn_bucket.delete_object(&path).await;
+    }
+    None => {}
+  }
+}
+
+/// An assembled upload, read back from S3.
+struct AssembledUpload {
+  /// Hex SHA-1 of the contents.
+  hash: String,
+  file_type: AssetType,
+  contents: Option<Bytes>,
+}
+
+/// Read an assembled upload back from S3, hashing it on the way. The
+/// contents are only held on to with `keep`, for files that still need
+/// decoding, and never past `UPLOAD_MAX_DECODE_SIZE`.
+async fn read_upload(
+  state: &ServerState,
+  path: &str,
+  keep: bool,
+) -> Option<AssembledUpload> {
+  let config = Config::init_from_env().unwrap();
+  let mut object = state
+    .s3
+    .cdn_bucket
+    .get_object_stream(path)
+    .await
+    .ok()
+    .filter(|object| object.status_code == 200)?;
+
+  let mut hasher = Sha1::new();
+  let mut contents = BytesMut::new();
+  let mut file_type = None;
+
+  while let Some(chunk) = object.bytes.next().await {
+    if file_type.is_none() {
+      file_type = Some(detect_type(&chunk));
+    }
+    hasher.update(&chunk);
+    if keep {
+      if (contents.len() + chunk.len()) as i64
+        > config.upload_max_decode_size
+      {
+        return None;
+      }
+      contents.extend_from_slice(&chunk);
+    }
+  }
+
+  Some(AssembledUpload {
+    hash: hex::encode(hasher.finalize()),
+    file_type: file_type?,
+    contents: keep.then(|| contents.freeze()),
+  })
+}
+
+/// Move an assembled upload to its final key.
+async fn move_into_place(
+  state: &ServerState,
+  from: &str,
+  to: &str,
+) -> bool {
+  state
+    .s3
+    .cdn_bucket
+    .copy_object_internal(from, to)
+    .await
+    .is_ok_and(|status| status == 200)
+}
+
+fn failed_upload_to_s3() -> HttpResponse {
+  HttpResponse::BadRequest().json(json!({"code": "failed_upload_to_s3"}))
+}
+
+fn too_large_to_decode() -> HttpResponse {
+  let config = Config::init_from_env().unwrap();
+
+  HttpResponse::PayloadTooLarge().json(json!({
+    "code": "file_too_large",
+    "max_size": config.upload_max_decode_size,
+  }))
+}
+
+/// Refuse to read an upload into memory that is over
+/// `UPLOAD_MAX_DECODE_SIZE`, which may have been lowered since it started.
+fn check_decode_size(
+  upload: &ResumableUpload,
+) -> Result<(), HttpResponse> {
+  let config = Config::init_from_env().unwrap();
+
+  if upload.size > config.upload_max_decode_size {
+    return Err(too_large_to_decode());
+  }
+  Ok(())
+}
+
+/// Finish an `images` or `files` upload the way `/uploads/{type}` stores
+/// a file, under the first 16 characters of its SHA-1.
+async fn finish_cdn_upload(
+  state: &ServerState,
+  upload: &ResumableUpload,
+) -> Result<HttpResponse, HttpResponse> {
+  let path = staging_path(&upload.id);
+  let assembled = read_upload(state, &path, false)
+    .await
+    .ok_or_else(failed_upload_to_s3)?;
+
+  let base_dir = if upload.kind == "images" { "i" } else { "u" };
+  let hash = &assembled.hash[..16];
+  let ext = assembled.file_type.ext;
+
+  if !move_into_place(state, &path, &format!("{base_dir}/{hash}.{ext}"))
+    .await
+  {
+    return Err(failed_upload_to_s3());
+  }
+
+  let config = Config::init_from_env().unwrap();
+  let alias_url = if upload.kind == "images" {
+    config.s3_images_alias
+  } else {
+    config.s3_files_alias
+  };
+
+  Ok(
+    HttpResponse::Ok()
+      .json(json!({"data": { "url": format!("https://{alias_url}/{hash}.{ext}") }})),
+  )
+}
+
+/// Finish a `photography` upload by adding the photo to the end of its
+/// album, as `upload_photos` would. The photo's row goes in first, so a
+/// name that is already taken never gets its original overwritten, and
+/// the upload is marked as handed off along with it so a retry carries on
+/// with the photo it already added.
+async fn finish_photo_upload(
+  state: &ServerState,
+  upload: &ResumableUpload,
+) -> Result<HttpResponse, HttpResponse> {
+  let slug = upload.target.as_deref().unwrap_or_default();
+  let name = upload.file_name.as_deref().unwrap_or_default();
+
+  let album = find_album(&state.db, slug).await.map_err(|error| {
+    eprintln!("failed to get album from database {:?}", error);
+    HttpResponse::InternalServerError().finish()
+  })?;
+  let album = match album {
+    Some(album) => album,
+    None => {
+      return Err(
+        HttpResponse::NotFound().json(json!({"code": "album_not_found"})),
+      );
+    }
+  };
+
+  check_decode_size(upload)?;
+  let path = staging_path(&upload.id);
+  let assembled = read_upload(state, &path, true)
+    .await
+    .ok_or_else(failed_upload_to_s3)?;
+  let contents = assembled.contents.unwrap_or_default();
+
+  if !upload.handed_off {
+    set_handed_off(state, upload, true, &contents).await?;
+  }
+
+  // Originals are public, so one with a location is stored stripped
+  // instead of being moved over as uploaded.
+  let original = original_path(&album.storage_dir, name);
+  let stored = match strip_gps(&contents) {
+    Some(stripped) => state
+      .s3
+      .cdn_bucket
+      .put_object_with_content_type(
+        &original,
+        &stripped,
+        upload.content_type.as_deref().unwrap_or_default(),
+      )
+      .await
+      .is_ok_and(|response| response.status_code() == 200),
+    None => move_into_place(state, &path, &original).await,
+  };
+  if !stored {
+    let _ = set_handed_off(state, upload, false, &contents).await;
+    return Err(failed_upload_to_s3());
+  }
+
+  process_added_photo(state, slug, name, contents).await;
+  Ok(HttpResponse::Ok().json(json!({"album": slug, "uploaded": name})))
+}
+
+/// Add or take back the photo a `photography` upload becomes, in the same
+/// transaction as marking the upload as handed off or not.
+async fn set_handed_off(
+  state: &ServerState,
+  upload: &ResumableUpload,
+  handed_off: bool,
+  contents: &[u8],
+) -> Result<(), HttpResponse> {
+  let slug = upload.target.as_deref().unwrap_or_default();
+  let name = upload.file_name.as_deref().unwrap_or_default();
+
+  let internal_error = |error: sqlx::Error| {
+    eprintln!("failed to hand off photo in database {:?}", error);
+    HttpResponse::InternalServerError().finish()
+  };
+
+  let mut tx = state.db.begin().await.map_err(internal_error)?;
+
+  if handed_off {
+    let inserted = insert_photo(&mut *tx, slug, name, contents)
+      .await
+      .map_err(internal_error)?;
+    if !inserted {
+      return Err(
+        HttpResponse::Conflict()
+          .json(json!({"code": "photo_already_exists"})),
+      );
+    }
+  } else {
+    sqlx::query(
+      "DELETE FROM photography_photos WHERE album_slug = $1 AND name = $2",
+    )
+    .bind(slug)
+    .bind(name)
+    .execute(&mut *tx)
+    .await
+    .map_err(internal_error)?;
+  }
+
+  sqlx::query(
+    "UPDATE resumable_uploads SET handed_off = $1 WHERE id = $2",
+  )
+  .bind(handed_off)
+  .bind(&upload.id)
+  .execute(&mut *tx)
+  .await
+  .map_err(internal_error)?;
+
+  tx.commit().await.map_err(internal_error)
+}
+
+/// Finish a `blog` upload as an asset of its post. Only images are read
+/// back into memory, for their variants.
+async fn finish_blog_upload(
+  state: &ServerState,
+  user: &BlogAdminUser,
+  upload: &ResumableUpload,
+) -> Result<HttpResponse, HttpResponse> {
+  let post_id = upload.target.as_deref().unwrap_or_default();
+  find_editable_post(state, user, post_id).await?;
+
+  let path = staging_path(&upload.id);
+  let is_image = is_decoded(&upload.kind, upload.content_type.as_deref());
+  if is_image {
+    check_decode_size(upload)?;
+  }
+  let assembled = read_upload(state, &path, is_image)
+    .await
+    .ok_or_else(failed_upload_to_s3)?;
+
+  let original =
+    format!("blog/assets/{}.{}", assembled.hash, assembled.file_type.ext);
+  if !move_into_place(state, &path, &original).await {
+    return Err(failed_upload_to_s3());
+  }
+
+  // A retry after the asset was recorded, but before the upload was
+  // cleared away, gets the same asset back.
+  let recorded = sqlx::query_as::<_, BlogAsset>(
+    "SELECT * FROM blog_assets WHERE hash = $1 AND post_id = $2 LIMIT 1",
+  )
+  .bind(&assembled.hash)
+  .bind(post_id)
+  .fetch_optional(&state.db)
+  .await
+  .map_err(|error| {
+    eprintln!("failed to get asset from database {:?}", error);
+    HttpResponse::InternalServerError().finish()
+  })?;
+  if let Some(asset) = recorded {
+    return Ok(
+      HttpResponse::Ok().json(json!({"asset": asset_json(&asset)})),
+    );
+  }
+
+  match record_asset(
+    state,
+    post_id,
+    assembled.hash,
+    assembled.file_type,
+    upload.size as i32,
+    assembled.contents,
+  )
+  .await
+  {
+    Ok(asset) => {
+      Ok(HttpResponse::Ok().json(json!({"asset": asset_json(&asset)})))
+    }
+    Err(code) => {
+      Err(HttpResponse::BadRequest().json(json!({"code": code})))
+    }
+  }
+}
+
+/// Start a resumable upload. Chunks are then sent with `PATCH`, and the
+/// upload finished with `POST /{id}/complete`. Blog admins can only
+/// start `blog` uploads, and management everything else.
+#[post("")]
+async fn create_upload(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+  payload: web::Json<CreateResumableUploadPayload>,
+) -> Result<HttpResponse, Error> {
+  let config = Config::init_from_env().unwrap();
+  let user = blog_user(&req);
+
+  if !RESUMABLE_UPLOAD_KINDS.contains(&payload.kind.as_str())
+    || (payload.kind == "blog") != user.is_some()
+  {
+    return Ok(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "invalid_upload_kind"})),
+    );
+  }
+
+  if payload.size <= 0 {
+    return Ok(
+      HttpResponse::BadRequest()
+        .json(json!({"code": "invalid_upload_size"})),
+    );
+  }
+  // Blog assets record their size as a 32-bit integer, and photos are
+  // decoded in memory once complete.
+  let max_size = match payload.kind.as_str() {
+    "blog" => config.upload_max_size.min(i32::MAX as i64),
+    "photography" => {
+      config.upload_max_size.min(config.upload_max_decode_size)
+    }
+    _ => config.upload_max_size,
+  };
+  if payload.size > max_size {
+    return Ok(
+      HttpResponse::PayloadTooLarge()
+        .json(json!({"code": "file_too_large", "max_size": max_size})),
+    );
+  }
+
+  let target = match (payload.kind.as_str(), &payload.target) {
+    ("photography" | "blog", None) => {
+      return Ok(
+        HttpResponse::BadRequest().json(json!({"code": "missing_target"})),
+      );
+    }
+    ("photography" | "blog", Some(target)) => Some(target.clone()),
+    _ => None,
+  };
+
+  if payload.kind == "photography" {
+    let slug = target.as_deref().unwrap_or_default();
+    let name = payload.file_name.as_deref().unwrap_or_default();
+
+    if name.is_empty() || name.contains('/') {
+      return Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "invalid_photo_name"})),
+      );
+    }
+
+    let album = find_album(&state.db, slug).await.map_err(|error| {
+      eprintln!("failed to get album from database {:?}", error);
+      ErrorInternalServerError(error.to_string())
+    })?;
+    if album.is_none() {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "album_not_found"})),
+      );
+    }
+  }
+
+  if let Some(user) = &user {
+    let post_id = target.as_deref().unwrap_or_default();
+    if let Err(response) = find_editable_post(&state, user, post_id).await
+    {
+      return Ok(response);
+    }
+  }
+
+  let id: String = rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(32)
+    .map(char::from)
+    .collect();
+
+  let upload = sqlx::query_as::<_, ResumableUpload>(
+    "INSERT INTO resumable_uploads \
+       (id, kind, target, file_name, owner_id, size) \
+     VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+  )
+  .bind(&id)
+  .bind(&payload.kind)
+  .bind(target)
+  .bind(&payload.file_name)
+  .bind(user.map(|user| user.id))
+  .bind(payload.size)
+  .fetch_one(&state.db)
+  .await
+  .map_err(|error| {
+    eprintln!("failed to create upload in database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  Ok(upload_response(&mut HttpResponse::Created(), &upload))
+}
+
+/// How much of an upload has been received, so a client can pick up where
+/// it left off. Also answers `HEAD`, with just the `Upload-Offset` and
+/// `Upload-Length` headers.
+#[route("/{id}", method = "GET", method = "HEAD")]
+async fn get_upload(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+  id: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+  let owner_id = blog_user(&req).map(|user| user.id);
+
+  let upload = find_upload(&state.db, &id, owner_id.as_deref())
+    .await
+    .map_err(|error| {
+      eprintln!("failed to get upload from database {:?}", error);
+      ErrorInternalServerError(error.to_string())
+    })?;
+
+  match upload {
+    Some(upload) => Ok(upload_response(&mut HttpResponse::Ok(), &upload)),
+    None => Ok(
+      HttpResponse::NotFound().json(json!({"code": "upload_not_found"})),
+    ),
+  }
+}
+
+/// Append a chunk, sent as the raw request body, starting at the
+/// `Upload-Offset` header. Every chunk but the last has to be at least
+/// 5 MiB. A chunk that doesn't make it to S3 can simply be sent again,
+/// unless S3 rejected it, which ends the upload.
+#[patch("/{id}")]
+async fn append_chunk(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+  id: web::Path<String>,
+  mut body: web::Payload,
+) -> Result<HttpResponse, Error> {
+  let config = Config::init_from_env().unwrap();
+  let owner_id = blog_user(&req).map(|user| user.id);
+
+  let offset = match req
+    .headers()
+    .get(OFFSET_HEADER)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.parse::<i64>().ok())
+  {
+    Some(offset) => offset,
+    None => {
+      return Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "missing_upload_offset"})),
+      );
+    }
+  };
+
+  let upload = find_upload(&state.db, &id, owner_id.as_deref())
+    .await
+    .map_err(|error| {
+      eprintln!("failed to get upload from database {:?}", error);
+      ErrorInternalServerError(error.to_string())
+    })?;
+
+  let upload = match upload {
+    Some(upload) if upload.received == offset => upload,
+    Some(upload) => {
+      return Ok(upload_conflict("offset_mismatch", &upload));
+    }
+    None => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "upload_not_found"})),
+      );
+    }
+  };
+
+  // Only one chunk of an upload is read at a time, so a client retrying
+  // too eagerly doesn't have the same chunk buffered twice.
+  let Some(_receiving) = ReceivingChunk::start(&upload.id) else {
+    return Ok(
+      HttpResponse::Conflict().json(json!({"code": "upload_busy"})),
+    );
+  };
+
+  let remaining = (upload.size - upload.received) as usize;
+  let mut chunk: Vec<u8> = Vec::new();
+  while let Some(bytes) = body.next().await {
+    let bytes = bytes?;
+    if chunk.len() + bytes.len() > config.upload_max_chunk_size {
+      return Ok(HttpResponse::PayloadTooLarge().json(json!({
+        "code": "chunk_too_large",
+        "max_size": config.upload_max_chunk_size,
+      })));
+    }
+    if chunk.len() + bytes.len() > remaining {
+      return Ok(
+        HttpResponse::BadRequest()
+          .json(json!({"code": "chunk_exceeds_upload_size"})),
+      );
+    }
+    chunk.extend_from_slice(&bytes);
+  }
+
+  if let Some(code) =
+    chunk_error(chunk.len(), remaining, upload.etags.len())
+  {
+    let mut body = json!({"code": code});
+    if code == "chunk_too_small" {
+      body["min_size"] = json!(MIN_CHUNK_SIZE);
+    }
+    return Ok(HttpResponse::BadRequest().json(body));
+  }
+
+  let mut tx = state.db.begin().await.map_err(|error| {
+    eprintln!("failed to start transaction {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  // Another request may have appended the same chunk while this one was
+  // being read.
+  let upload = match lock_upload(&mut tx, &id, owner_id.as_deref()).await {
+    Ok(Some(upload)) if upload.received == offset => upload,
+    Ok(Some(upload)) => {
+      return Ok(upload_conflict("offset_mismatch", &upload));
+    }
+    Ok(None) => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "upload_not_found"})),
+      );
+    }
+    Err(response) => return Ok(response),
+  };
+
+  let path = staging_path(&upload.id);
+
+  // The S3 upload only starts with the first chunk, once the file's type
+  // can be sniffed.
+  let (upload_id, content_type) =
+    match (upload.s3_upload_id.clone(), upload.content_type.clone()) {
+      (Some(upload_id), Some(content_type)) => (upload_id, content_type),
+      _ => {
+        let file_type = detect_type(&chunk);
+        let images_only =
+          upload.kind == "images" || upload.kind == "photography";
+        if images_only && !is_allowed_mime(&file_type.mime, "images") {
+          return Ok(
+            HttpResponse::BadRequest()
+              .json(json!({"code": "prohibited_file_type"})),
+          );
+        }
+        // Blog assets only turn out to be images here.
+        if is_decoded(&upload.kind, Some(&file_type.mime))
+          && upload.size > config.upload_max_decode_size
+        {
+          return Ok(too_large_to_decode());
+        }
+
+        match state
+          .s3
+          .cdn_bucket
+          .initiate_multipart_upload(&path, &file_type.mime)
+          .await
+        {
+          Ok(started) => (started.upload_id, file_type.mime),
+          Err(error) => {
+            tracing::warn!(
+              "Failed to start S3 upload {}: {}",
+              path,
+              error
+            );
+            return Ok(failed_upload_to_s3());
+          }
+        }
+      }
+    };
+
+  let chunk_size = chunk.len() as i64;
+  let part_number = upload.etags.len() as u32 + 1;
+  let part = state
+    .s3
+    .cdn_bucket
+    .put_multipart_chunk(
+      chunk,
+      &path,
+      part_number,
+      &upload_id,
+      &content_type,
+    )
+    .await;
+
+  let part = match part {
+    Ok(part) => part,
+    Err(error) if !part_aborted_upload(&error) => {
+      // The S3 upload is still there for the chunk to be sent again, so
+      // hold on to it in case this was the first.
+      tracing::warn!("Failed to upload part of {}: {}", path, error);
+      sqlx::query(
+        "UPDATE resumable_uploads \
+         SET content_type = $1, s3_upload_id = $2, updated_at = now() \
+         WHERE id = $3",
+      )
+      .bind(&content_type)
+      .bind(&upload_id)
+      .bind(&upload.id)
+      .execute(&mut *tx)
+      .await
+      .map_err(|error| {
+        eprintln!("failed to update upload in database {:?}", error);
+        ErrorInternalServerError(error.to_string())
+      })?;
+      tx.commit().await.map_err(|error| {
+        eprintln!("failed to commit transaction {:?}", error);
+        ErrorInternalServerError(error.to_string())
+      })?;
+
+      return Ok(
+        HttpResponse::BadGateway().json(json!({"code": "chunk_failed"})),
+      );
+    }
+    Err(error) => {
+      // With the S3 upload aborted there is nothing left to resume. It is
+      // aborted again in case rust-s3's own abort didn't go through.
+      tracing::warn!("Failed to upload part of {}: {}", path, error);
+      let _ = state.s3.cdn_bucket.abort_upload(&path, &upload_id).await;
+      sqlx::query("DELETE FROM resumable_uploads WHERE id = $1")
+        .bind(&upload.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|error| {
+          eprintln!("failed to delete upload in database {:?}", error);
+          ErrorInternalServerError(error.to_string())
+        })?;
+      tx.commit().await.map_err(|error| {
+        eprintln!("failed to commit transaction {:?}", error);
+        ErrorInternalServerError(error.to_string())
+      })?;
+
+      return Ok(
+        HttpResponse::BadGateway().json(json!({"code": "upload_aborted"})),
+      );
+    }
+  };
+
+  let upload = sqlx::query_as::<_, ResumableUpload>(
+    "UPDATE resumable_uploads \
+     SET received = received + $1, content_type = $2, s3_upload_id = $3, \
+       etags = array_append(etags, $4), updated_at = now() \
+     WHERE id = $5 RETURNING *",
+  )
+  .bind(chunk_size)
+  .bind(&content_type)
+  .bind(&upload_id)
+  .bind(&part.etag)
+  .bind(&upload.id)
+  .fetch_one(&mut *tx)
+  .await
+  .map_err(|error| {
+    eprintln!("failed to update upload in database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  tx.commit().await.map_err(|error| {
+    eprintln!("failed to commit transaction {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  Ok(upload_response(&mut HttpResponse::Ok(), &upload))
+}
+
+/// Claim a fully received upload for completing, so only one request
+/// works on it at a time. A claim left behind by a request that died is
+/// taken over once it is `COMPLETE_TIMEOUT_MINUTES` old.
+async fn claim_upload(
+  state: &ServerState,
+  id: &str,
+  owner_id: Option<&str>,
+) -> Result<ResumableUpload, HttpResponse> {
+  let internal_error = |error: sqlx::Error| {
+    eprintln!("failed to claim upload in database {:?}", error);
+    HttpResponse::InternalServerError().finish()
+  };
+
+  let mut tx = state.db.begin().await.map_err(internal_error)?;
+
+  let upload = match lock_upload(&mut tx, id, owner_id).await? {
+    Some(upload) if upload.received == upload.size => upload,
+    Some(upload) => {
+      return Err(upload_conflict("upload_incomplete", &upload))
+    }
+    None => {
+      return Err(
+        HttpResponse::NotFound().json(json!({"code": "upload_not_found"})),
+      );
+    }
+  };
+
+  let claimed = sqlx::query_as::<_, ResumableUpload>(
+    "UPDATE resumable_uploads SET state = 'completing', updated_at = now() \
+     WHERE id = $1 AND (state = 'receiving' \
+       OR updated_at < now() - make_interval(mins => $2)) \
+     RETURNING *",
+  )
+  .bind(&upload.id)
+  .bind(COMPLETE_TIMEOUT_MINUTES)
+  .fetch_optional(&mut *tx)
+  .await
+  .map_err(internal_error)?;
+
+  tx.commit().await.map_err(internal_error)?;
+
+  claimed.ok_or_else(|| {
+    HttpResponse::Conflict().json(json!({"code": "upload_busy"}))
+  })
+}
+
+/// Let go of a claimed upload whose completion failed, so it can be
+/// retried.
+async fn release_upload(state: &ServerState, upload: &ResumableUpload) {
+  let released = sqlx::query(
+    "UPDATE resumable_uploads SET state = 'receiving', updated_at = now() \
+     WHERE id = $1",
+  )
+  .bind(&upload.id)
+  .execute(&state.db)
+  .await;
+
+  if let Err(error) = released {
+    eprintln!("failed to release upload in database {:?}", error);
+  }
+}
+
+/// Assemble a fully received upload and hand it to wherever it was meant
+/// for. If that last step fails, the upload is kept so it can be retried.
+/// While one request is completing an upload, others get `upload_busy`.
+#[post("/{id}/complete")]
+async fn complete_upload(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+  id: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+  let user = blog_user(&req);
+  let owner_id = user.as_ref().map(|user| user.id.as_str());
+
+  let mut upload = match claim_upload(&state, &id, owner_id).await {
+    Ok(upload) => upload,
+    Err(response) => return Ok(response),
+  };
+
+  // Once assembled, the S3 upload id is cleared, so a retry after a failed
+  // hand-off skips straight to it.
+  if let Some(upload_id) = &upload.s3_upload_id {
+    let parts = upload
+      .etags
+      .iter()
+      .enumerate()
+      .map(|(index, etag)| Part {
+        part_number: index as u32 + 1,
+        etag: etag.clone(),
+      })
+      .collect();
+
+    let completed = state
+      .s3
+      .cdn_bucket
+      .complete_multipart_upload(
+        &staging_path(&upload.id),
+        upload_id,
+        parts,
+      )
+      .await
+      .is_ok_and(|response| response.status_code() == 200);
+    if !completed {
+      release_upload(&state, &upload).await;
+      return Ok(failed_upload_to_s3());
+    }
+
+    let cleared = sqlx::query(
+      "UPDATE resumable_uploads SET s3_upload_id = NULL WHERE id = $1",
+    )
+    .bind(&upload.id)
+    .execute(&state.db)
+    .await;
+    if let Err(error) = cleared {
+      eprintln!("failed to update upload in database {:?}", error);
+      release_upload(&state, &upload).await;
+      return Err(ErrorInternalServerError(error.to_string()));
+    }
+    upload.s3_upload_id = None;
+  }
+
+  let finished = match (upload.kind.as_str(), &user) {
+    ("images" | "files", _) => finish_cdn_upload(&state, &upload).await,
+    ("photography", _) => finish_photo_upload(&state, &upload).await,
+    ("blog", Some(user)) => {
+      finish_blog_upload(&state, user, &upload).await
+    }
+    _ => Err(
+      HttpResponse::NotFound().json(json!({"code": "upload_not_found"})),
+    ),
+  };
+
+  let response = match finished {
+    Ok(response) => response,
+    Err(response) => {
+      release_upload(&state, &upload).await;
+      return Ok(response);
+    }
+  };
+
+  // Every hand-off can be run again, so if this fails a retry just ends up
+  // back here.
+  let deleted = sqlx::query("DELETE FROM resumable_uploads WHERE id = $1")
+    .bind(&upload.id)
+    .execute(&state.db)
+    .await;
+  if let Err(error) = deleted {
+    eprintln!("failed to delete upload in database {:?}", error);
+    release_upload(&state, &upload).await;
+    return Err(ErrorInternalServerError(error.to_string()));
+  }
+
+  let _ = state
+    .s3
+    .cdn_bucket
+    .delete_object(staging_path(&upload.id))
+    .await;
+
+  Ok(response)
+}
+
+/// Give up on an upload, throwing away everything received so far.
+#[delete("/{id}")]
+async fn cancel_upload(
+  req: HttpRequest,
+  state: web::Data<ServerState>,
+  id: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+  let owner_id = blog_user(&req).map(|user| user.id);
+
+  let mut tx = state.db.begin().await.map_err(|error| {
+    eprintln!("failed to start transaction {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  let upload = match lock_upload(&mut tx, &id, owner_id.as_deref()).await {
+    Ok(Some(upload)) => upload,
+    Ok(None) => {
+      return Ok(
+        HttpResponse::NotFound().json(json!({"code": "upload_not_found"})),
+      );
+    }
+    Err(response) => return Ok(response),
+  };
+
+  // An upload that is being completed can't be pulled out from under it.
+  let deleted = sqlx::query(
+    "DELETE FROM resumable_uploads WHERE id = $1 AND (state = 'receiving' \
+       OR updated_at < now() - make_interval(mins => $2))",
+  )
+  .bind(&upload.id)
+  .bind(COMPLETE_TIMEOUT_MINUTES)
+  .execute(&mut *tx)
+  .await
+  .map_err(|error| {
+    eprintln!("failed to delete upload in database {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+  if deleted.rows_affected() == 0 {
+    return Ok(
+      HttpResponse::Conflict().json(json!({"code": "upload_busy"})),
+    );
+  }
+
+  tx.commit().await.map_err(|error| {
+    eprintln!("failed to commit transaction {:?}", error);
+    ErrorInternalServerError(error.to_string())
+  })?;
+
+  discard_upload_objects(&state, &upload).await;
+
+  Ok(HttpResponse::NoContent().finish())
+}
+
+/// Resumable upload routes. Mounted behind management auth under
+/// `/uploads`, and behind blog admin auth for blog assets.
+pub fn factory() -> Scope {
+  web::scope("/resumable")
+    .service(create_upload)
+    .service(get_upload)
+    .service(append_chunk)
+    .service(complete_upload)
+    .service(cancel_upload)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn only_photos_and_blog_images_are_decoded() {
+    assert!(is_decoded("photography", None));
+    assert!(is_decoded("blog", Some("image/jpeg")));
+    assert!(!is_decoded("blog", Some("application/pdf")));
+    assert!(!is_decoded("blog", None));
+    assert!(!is_decoded("images", Some("image/png")));
+    assert!(!is_decoded("files", Some("image/png")));
+  }
+
+  #[test]
+  fn checks_chunks_against_what_is_left() {
+    let remaining = 3 * MIN_CHUNK_SIZE;
+
+    assert_eq!(chunk_error(MIN_CHUNK_SIZE, remaining, 0), None);
+    assert_eq!(chunk_error(remaining, remaining, 0), None);
+    // The last chunk can be any size.
+    assert_eq!(chunk_error(10, 10, 2), None);
+
+    assert_eq!(chunk_error(0, remaining, 0), Some("empty_chunk"));
+    assert_eq!(
+      chunk_error(remaining + 1, remaining, 0),
+      Some("chunk_exceeds_upload_size")
+    );
+    assert_eq!(
+      chunk_error(MIN_CHUNK_SIZE - 1, remaining, 0),
+      Some("chunk_too_small")
+    );
+    assert_eq!(
+      chunk_error(MIN_CHUNK_SIZE, remaining, MAX_CHUNKS),
+      Some("too_many_chunks")
+    );
+  }
+
+  #[test]
+  fn reads_one_chunk_per_upload_at_a_time() {
+    let first = ReceivingChunk::start("upload-a");
+    assert!(first.is_some());
+    assert!(ReceivingChunk::start("upload-a").is_none());
+    assert!(ReceivingChunk::start("upload-b").is_some());
+
+    drop(first);
+    assert!(ReceivingChunk::start("upload-a").is_some());
+  }
+
+  #[test]
+  fn only_s3_rejections_end_an_upload() {
+    assert!(part_aborted_upload(&S3Error::Http(
+      400,
+      "EntityTooSmall".to_string()
+    )));
+    assert!(part_aborted_upload(&S3Error::HttpFail));
+
+    let dropped =
+      std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+    assert!(!part_aborted_upload(&S3Error::Io(dropped)));
+  }
+}
@@ -1,8 +1,13 @@
 use actix_multipart::form::{bytes::Bytes, MultipartForm};
 use chrono::NaiveDateTime;
+use optional_field::{serde_optional_fields, Field};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use sqlx::FromRow;
+use std::str::FromStr;
+use webauthn_rs::prelude::{
+  PasskeyAuthentication, PublicKeyCredential, RegisterPublicKeyCredential,
+};
 extern crate serde_json;
 
 #[derive(Deserialize, Debug)]
@@ -11,6 +16,28 @@ pub struct BlogPostsQuery {
   pub offset: Option<i64>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct BlogPostQuery {
+  pub preview: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BlogPreviewCreate {
+  /// Lifetime of the preview link in seconds.
+  pub expires_in: Option<i64>,
+}
+
+/// Preview token record stored at `blog_preview/{id}`. The shareable token
+/// is the id itself.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlogPreviewToken {
+  pub id: String,
+  pub post_id: String,
+  pub created_by: String,
+  pub created_at: NaiveDateTime,
+  pub expires_at: NaiveDateTime,
+}
+
 #[serde_as]
 #[allow(dead_code)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -31,10 +58,85 @@ pub struct BlogLoginRequest {
   pub password: String,
 }
 
+/// Second login step for users with TOTP enrolled. One of `code` or
+/// `recovery_code` must be provided.
+#[derive(Deserialize)]
+pub struct BlogLoginChallengeRequest {
+  pub challenge: String,
+  pub code: Option<String>,
+  pub recovery_code: Option<String>,
+}
+
+/// Pending login stored at `blog_admin_login_challenge/{token}` between the
+/// password and TOTP steps.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlogLoginChallenge {
+  pub user_id: String,
+}
+
 #[allow(dead_code)]
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Debug, Clone, FromRow)]
+pub struct BlogAdminPasskey {
+  pub id: String,
+  pub user_id: String,
+  pub name: Option<String>,
+  pub passkey: serde_json::Value,
+  pub created_at: NaiveDateTime,
+  pub last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize)]
+pub struct BlogPasskeyRegisterFinish {
+  pub name: Option<String>,
+  pub credential: RegisterPublicKeyCredential,
+}
+
+#[derive(Deserialize)]
+pub struct BlogPasskeyLoginStart {
+  pub username: String,
+}
+
+#[derive(Deserialize)]
+pub struct BlogPasskeyLoginFinish {
+  pub challenge: String,
+  pub credential: PublicKeyCredential,
+}
+
+/// Authentication ceremony state stored at
+/// `blog_admin_passkey_login/{challenge}`. Logins for unknown users and
+/// users without passkeys get a decoy challenge with neither a user nor a
+/// ceremony, which can never complete.
+#[derive(Serialize, Deserialize)]
+pub struct BlogPasskeyLoginState {
+  pub username: String,
+  pub user_id: Option<String>,
+  pub state: Option<PasskeyAuthentication>,
+}
+
+#[derive(Deserialize)]
+pub struct BlogTotpConfirm {
+  pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct BlogTotpDisable {
+  pub password: String,
+}
+
+/// Session record stored at `blog_admin_session/{token}`. Sessions created
+/// before expiry tracking only carry `user_id`.
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BlogAdminSession {
   pub user_id: String,
+  #[serde(default)]
+  pub user_agent: Option<String>,
+  #[serde(default)]
+  pub ip: Option<String>,
+  #[serde(default)]
+  pub created_at: Option<NaiveDateTime>,
+  #[serde(default)]
+  pub last_seen: Option<NaiveDateTime>,
 }
 
 #[allow(dead_code)]
@@ -51,16 +153,92 @@ pub struct BlogAdminUser {
   pub username: String,
   pub display_name: Option<String>,
   pub password: String,
+  pub role: String,
+  pub disabled: bool,
+  pub created_at: NaiveDateTime,
+  pub totp_secret: Option<String>,
+  pub totp_enabled: bool,
+  pub totp_recovery_codes: Vec<String>,
+}
+
+impl BlogAdminUser {
+  /// Unknown roles in the database fall back to the least privileged one.
+  pub fn role(&self) -> BlogAdminRole {
+    BlogAdminRole::from_str(&self.role).unwrap_or(BlogAdminRole::Author)
+  }
+}
+
+/// Admin roles, ordered from least to most privileged so they can be
+/// compared directly (`user.role() >= BlogAdminRole::Editor`).
+#[derive(
+  Serialize,
+  Deserialize,
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum BlogAdminRole {
+  Author,
+  Editor,
+  Owner,
+}
+
+impl BlogAdminRole {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      BlogAdminRole::Author => "author",
+      BlogAdminRole::Editor => "editor",
+      BlogAdminRole::Owner => "owner",
+    }
+  }
+}
+
+impl FromStr for BlogAdminRole {
+  type Err = ();
+
+  fn from_str(role: &str) -> Result<Self, Self::Err> {
+    match role {
+      "author" => Ok(BlogAdminRole::Author),
+      "editor" => Ok(BlogAdminRole::Editor),
+      "owner" => Ok(BlogAdminRole::Owner),
+      _ => Err(()),
+    }
+  }
+}
+
+#[derive(Deserialize)]
+pub struct BlogUserCreate {
+  pub username: String,
+  pub password: String,
+  pub display_name: Option<String>,
+  pub role: Option<BlogAdminRole>,
+}
+
+#[derive(Deserialize)]
+pub struct BlogUserAdminMutate {
+  pub display_name: Option<String>,
+  pub role: Option<BlogAdminRole>,
+  pub disabled: Option<bool>,
 }
 
 #[serde_as]
+#[serde_optional_fields]
 #[allow(dead_code)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BlogPostMutate {
   pub slug: Option<String>,
   pub title: Option<String>,
   pub description: Option<String>,
-  pub image: Option<String>,
+  /// Cover image URL; `null` clears it.
+  pub image: Field<String>,
+  /// Hash of one of the post's own assets to use as the cover, in place of
+  /// a raw `image` URL.
+  pub cover_asset: Option<String>,
   pub visibility: Option<String>,
   pub tags: Option<Vec<String>>,
   pub body: Option<String>,
@@ -80,6 +258,22 @@ pub struct BlogPost {
   pub body: Option<String>,
   pub created_at: NaiveDateTime,
   pub published_at: Option<NaiveDateTime>,
+  pub author_id: Option<String>,
+  pub deleted_at: Option<NaiveDateTime>,
+}
+
+/// A post alongside its view total, for listings and rankings.
+#[derive(Debug, Clone, FromRow)]
+pub struct BlogPostWithViews {
+  #[sqlx(flatten)]
+  pub post: BlogPost,
+  pub views: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BlogPopularQuery {
+  pub days: Option<i32>,
+  pub limit: Option<i64>,
 }
 
 #[allow(dead_code)]
@@ -90,6 +284,93 @@ pub struct BlogAsset {
   pub file_type: String,
   pub file_size: i32,
   pub upload_date: NaiveDateTime,
+  pub width: Option<i32>,
+  pub height: Option<i32>,
+  pub variants: Vec<i32>,
+}
+
+/// One of a trashed post's assets, with whether anything else still needs
+/// its file.
+#[derive(Debug, Clone, FromRow)]
+pub struct BlogPurgedAsset {
+  #[sqlx(flatten)]
+  pub asset: BlogAsset,
+  /// Another post's body or cover points at the file.
+  pub used_elsewhere: bool,
+  /// Another post has an asset row of its own for the same file.
+  pub covered: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+pub struct BlogComment {
+  pub id: String,
+  pub post_id: String,
+  pub parent_id: Option<String>,
+  pub author_name: String,
+  pub author_email: Option<String>,
+  pub body: String,
+  pub status: String,
+  pub ip: Option<String>,
+  pub user_agent: Option<String>,
+  pub created_at: NaiveDateTime,
+  pub moderated_at: Option<NaiveDateTime>,
+  pub moderated_by: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct BlogCommentCreate {
+  pub author_name: String,
+  pub author_email: Option<String>,
+  pub body: String,
+  pub parent_id: Option<String>,
+  /// Honeypot. Hidden from readers by the frontend, so anything filled in
+  /// here came from a bot.
+  pub website: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BlogCommentsQuery {
+  pub status: Option<String>,
+  pub limit: Option<i64>,
+  pub offset: Option<i64>,
+}
+
+/// Visibilities a post can be saved with. `deleted` is only ever set by
+/// moving a post to the trash.
+pub const POST_VISIBILITIES: [&str; 3] = ["draft", "unlisted", "public"];
+
+/// Slugs a post can't take because other routes under `/posts/` already
+/// answer to them.
+pub const RESERVED_POST_SLUGS: [&str; 1] = ["popular"];
+
+/// YAML front matter for a post in a Markdown bundle. `assets` lists the
+/// bundle's `assets/` files that belong to the post.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlogPostFrontMatter {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<String>,
+  pub slug: String,
+  pub title: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub image: Option<String>,
+  #[serde(default)]
+  pub tags: Vec<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub visibility: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub created_at: Option<NaiveDateTime>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub published_at: Option<NaiveDateTime>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub assets: Vec<String>,
+}
+
+#[derive(Debug, MultipartForm)]
+pub struct BlogBundleUpload {
+  #[multipart(rename = "file")]
+  pub file: Bytes,
 }
 
 #[derive(Debug, MultipartForm)]
@@ -97,3 +378,24 @@ pub struct BlogAssetUpload {
   #[multipart(rename = "file")]
   pub files: Vec<Bytes>,
 }
+
+#[derive(Deserialize, Debug)]
+pub struct BlogWebFingerQuery {
+  pub resource: String,
+}
+
+/// The blog actor's signing key pair, PEM encoded. There is only ever one
+/// row; it is generated the first time it is needed.
+#[derive(Debug, Clone, FromRow)]
+pub struct BlogActivityPubKey {
+  pub private_key: String,
+  pub public_key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+pub struct BlogFollower {
+  pub actor: String,
+  pub inbox: String,
+  pub shared_inbox: Option<String>,
+  pub created_at: NaiveDateTime,
+}
@@ -1,10 +1,21 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
 use optional_field::{serde_optional_fields, Field};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use sqlx::FromRow;
+use sqlx::{types::Json, FromRow};
 
-/// Row of the `photography_albums` table. Photos live inline in `items`.
+use crate::helpers::geo::round_coordinate;
+
+/// Who can see an album. `draft` albums are only visible to management,
+/// `unlisted` ones only by slug, and `password` ones only once unlocked.
+///
+/// The CDN serves every album's files to anyone, so `draft` and `password`
+/// albums keep theirs under a directory with a random suffix rather than
+/// at `{PHOTOGRAPHY_URL}/{slug}/...`.
+pub const ALBUM_VISIBILITIES: [&str; 4] =
+  ["draft", "unlisted", "public", "password"];
+
+/// Row of the `photography_albums` table. Its photos are rows of
+/// `photography_photos`.
 #[allow(dead_code)]
 #[derive(Debug, Clone, FromRow)]
 pub struct Album {
@@ -13,8 +24,40 @@ pub struct Album {
   pub location: Option<String>,
   pub description: Option<String>,
   pub cover: Option<String>,
-  pub items: Value,
   pub date: NaiveDateTime,
+  pub visibility: String,
+  /// Argon2 hash, set only on `password` albums.
+  pub password: Option<String>,
+  pub latitude: Option<f64>,
+  pub longitude: Option<f64>,
+  /// Directory under `gallery/albums/` the album's files are stored in.
+  pub storage_dir: String,
+}
+
+/// Row of the `photography_photos` table, ordered within its album by
+/// `position`.
+#[allow(dead_code)]
+#[derive(Debug, Clone, FromRow)]
+pub struct Photo {
+  pub album_slug: String,
+  pub name: String,
+  pub position: i32,
+  pub caption: Option<String>,
+  pub instagram: Option<String>,
+  pub frame: Option<Json<Frame>>,
+  pub exif: Option<Json<PhotoExif>>,
+  pub width: Option<i32>,
+  pub height: Option<i32>,
+  pub aspect_ratio: Option<f64>,
+  pub variants: Json<Vec<PhotoVariant>>,
+  pub blurhash: Option<String>,
+  pub lqip: Option<String>,
+  pub uploaded_at: NaiveDateTime,
+  pub keywords: Vec<String>,
+  pub tags: Vec<String>,
+  /// From EXIF GPS, unless set by hand.
+  pub latitude: Option<f64>,
+  pub longitude: Option<f64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,6 +77,9 @@ pub struct CreateAlbumPayload {
   pub cover: Option<String>,
   pub location: Option<String>,
   pub description: Option<String>,
+  pub visibility: Option<String>,
+  pub password: Option<String>,
+  pub coordinates: Option<Coordinates>,
 }
 
 #[serde_optional_fields]
@@ -43,6 +89,16 @@ pub struct EditAlbumPayload {
   pub cover: Field<String>,
   pub location: Field<String>,
   pub description: Field<String>,
+  pub visibility: Option<String>,
+  /// Sets a new password; required when switching to `password` and
+  /// ignored otherwise.
+  pub password: Option<String>,
+  pub coordinates: Field<Coordinates>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnlockAlbumPayload {
+  pub password: String,
 }
 
 #[serde_optional_fields]
@@ -51,6 +107,9 @@ pub struct EditPhotoPayload {
   pub caption: Field<String>,
   pub instagram: Field<String>,
   pub frame: Field<Frame>,
+  pub keywords: Field<Vec<String>>,
+  pub tags: Field<Vec<String>>,
+  pub coordinates: Field<Coordinates>,
 }
 
 /// Fields that can be applied across many photos at once. Each uses the
@@ -64,6 +123,10 @@ pub struct BulkPhotoFields {
   pub instagram: Field<String>,
   #[serde(default)]
   pub frame: Field<Frame>,
+  #[serde(default)]
+  pub keywords: Field<Vec<String>>,
+  #[serde(default)]
+  pub tags: Field<Vec<String>>,
 }
 
 /// A single targeted photo update inside a bulk request.
@@ -76,6 +139,10 @@ pub struct BulkPhotoItem {
   pub instagram: Field<String>,
   #[serde(default)]
   pub frame: Field<Frame>,
+  #[serde(default)]
+  pub keywords: Field<Vec<String>>,
+  #[serde(default)]
+  pub tags: Field<Vec<String>>,
 }
 
 /// Bulk update payload. `apply_to_all` is applied first to every photo in the
@@ -96,6 +163,50 @@ pub struct ReorderPhotosPayload {
   pub order: Vec<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RenamePhotoPayload {
+  pub name: String,
+}
+
+/// Payload for moving or copying a photo into another album. `name`
+/// renames it on the way; it keeps its current name when omitted.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferPhotoPayload {
+  pub album: String,
+  pub name: Option<String>,
+}
+
+/// Filters for `/photography/search`; all optional and combined with AND.
+/// `keyword` matches a photo's keywords or tags exactly, `q` its caption
+/// and `camera` its EXIF camera as substrings. `from` and `to` bound the
+/// day it was taken (or uploaded, without EXIF), inclusively.
+#[derive(Debug, Deserialize)]
+pub struct PhotoSearchQuery {
+  pub keyword: Option<String>,
+  pub q: Option<String>,
+  pub camera: Option<String>,
+  pub from: Option<NaiveDate>,
+  pub to: Option<NaiveDate>,
+  pub limit: Option<i64>,
+  pub offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PhotoSearchResult {
+  pub album: String,
+  pub name: String,
+  pub caption: Option<String>,
+  pub keywords: Vec<String>,
+  pub tags: Vec<String>,
+  pub taken_at: Option<NaiveDateTime>,
+  pub variants: Vec<PhotoVariant>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PhotoSearchResponse {
+  pub photos: Vec<PhotoSearchResult>,
+}
+
 /// Result of a single file within a multi-photo upload that was not stored.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SkippedUpload {
@@ -103,6 +214,14 @@ pub struct SkippedUpload {
   pub reason: String,
 }
 
+/// Response for re-reading EXIF data from the stored originals.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshExifResponse {
+  pub album: PublicAlbum,
+  pub updated: Vec<String>,
+  pub skipped: Vec<SkippedUpload>,
+}
+
 /// Response for a multi-photo upload: the updated album plus a per-file
 /// breakdown of what was stored and what was skipped.
 #[derive(Debug, Serialize, Deserialize)]
@@ -112,6 +231,14 @@ pub struct UploadPhotosResponse {
   pub skipped: Vec<SkippedUpload>,
 }
 
+/// Response for renaming, moving or copying a photo: the album it came
+/// from and the album it ended up in (the same one for a rename).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferPhotoResponse {
+  pub source: PublicAlbum,
+  pub target: PublicAlbum,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PublicAlbum {
   pub slug: String,
@@ -119,7 +246,9 @@ pub struct PublicAlbum {
   pub cover: Option<String>,
   pub description: Option<String>,
   pub location: Option<String>,
-  pub items: Value,
+  pub coordinates: Option<Coordinates>,
+  pub visibility: String,
+  pub items: Vec<AlbumItem>,
 }
 
 /// Focal point for a photo, as `object-position` percentages (0–100).
@@ -131,6 +260,85 @@ pub struct Frame {
   pub y: f64,
 }
 
+/// A point in decimal degrees.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Coordinates {
+  pub latitude: f64,
+  pub longitude: f64,
+}
+
+impl Coordinates {
+  pub fn from_columns(
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+  ) -> Option<Self> {
+    Some(Self {
+      latitude: latitude?,
+      longitude: longitude?,
+    })
+  }
+
+  pub fn is_valid(&self) -> bool {
+    (-90.0..=90.0).contains(&self.latitude)
+      && (-180.0..=180.0).contains(&self.longitude)
+  }
+
+  /// Rounded to the configured public precision.
+  pub fn rounded(&self) -> Self {
+    Self {
+      latitude: round_coordinate(self.latitude),
+      longitude: round_coordinate(self.longitude),
+    }
+  }
+}
+
+/// Where a photo was taken, in decimal degrees. Altitude is in metres.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpsPosition {
+  pub latitude: f64,
+  pub longitude: f64,
+  pub altitude: Option<f64>,
+}
+
+/// Camera settings read from a photo's EXIF data. Any field the file
+/// doesn't carry is `None`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PhotoExif {
+  pub camera: Option<String>,
+  pub lens: Option<String>,
+  /// In millimetres.
+  pub focal_length: Option<f64>,
+  /// As an f-number.
+  pub aperture: Option<f64>,
+  /// Formatted the way cameras show it, e.g. `1/250` or `2s`.
+  pub shutter: Option<String>,
+  pub iso: Option<u32>,
+  pub taken_at: Option<NaiveDateTime>,
+  pub gps: Option<GpsPosition>,
+}
+
+impl PhotoExif {
+  /// The EXIF data as it's shown publicly: the GPS position rounded to the
+  /// configured precision and its altitude dropped.
+  pub fn public(mut self) -> Self {
+    if let Some(gps) = self.gps.as_mut() {
+      gps.latitude = round_coordinate(gps.latitude);
+      gps.longitude = round_coordinate(gps.longitude);
+      gps.altitude = None;
+    }
+    self
+  }
+}
+
+/// A resized WebP copy of a photo, one per entry in `PHOTO_SIZES`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhotoVariant {
+  pub size: String,
+  pub url: String,
+  pub width: u32,
+  pub height: u32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AlbumItem {
   pub name: String,
@@ -140,36 +348,83 @@ pub struct AlbumItem {
   // missing `Option` fields to `None`). Serialized as `null` when centered.
   #[serde(default)]
   pub frame: Option<Frame>,
+  /// `None` for files without EXIF data and for photos uploaded before it
+  /// was extracted.
+  #[serde(default)]
+  pub exif: Option<PhotoExif>,
+  /// Pixel dimensions of the original, upright. Absent, along with the
+  /// variants, until they have been generated.
+  #[serde(default)]
+  pub width: Option<u32>,
+  #[serde(default)]
+  pub height: Option<u32>,
+  #[serde(default)]
+  pub aspect_ratio: Option<f64>,
+  #[serde(default)]
+  pub variants: Vec<PhotoVariant>,
+  #[serde(default)]
+  pub blurhash: Option<String>,
+  /// Tiny WebP preview as a `data:` URI.
+  #[serde(default)]
+  pub lqip: Option<String>,
+  #[serde(default)]
+  pub keywords: Vec<String>,
+  #[serde(default)]
+  pub tags: Vec<String>,
+  /// Rounded to the configured precision, as is the EXIF GPS position,
+  /// whose altitude is left out.
+  #[serde(default)]
+  pub coordinates: Option<Coordinates>,
 }
 
-impl From<Album> for PublicAlbum {
-  fn from(albums: Album) -> Self {
+impl From<Photo> for AlbumItem {
+  fn from(photo: Photo) -> Self {
+    let coordinates =
+      Coordinates::from_columns(photo.latitude, photo.longitude)
+        .map(|coordinates| coordinates.rounded());
+    let exif = photo.exif.map(|exif| exif.0.public());
+
     Self {
-      slug: albums.slug,
-      name: albums.name,
-      cover: albums.cover,
-      description: albums.description,
-      location: albums.location,
-      items: albums.items,
+      name: photo.name,
+      caption: photo.caption,
+      instagram: photo.instagram,
+      frame: photo.frame.map(|frame| frame.0),
+      exif,
+      width: photo.width.map(|width| width as u32),
+      height: photo.height.map(|height| height as u32),
+      aspect_ratio: photo.aspect_ratio,
+      variants: photo.variants.0,
+      blurhash: photo.blurhash,
+      lqip: photo.lqip,
+      keywords: photo.keywords,
+      tags: photo.tags,
+      coordinates,
     }
   }
 }
 
-impl From<Vec<Album>> for GetAlbumsResponse {
-  fn from(albums: Vec<Album>) -> Self {
+impl PublicAlbum {
+  /// `photos` are expected in album order.
+  pub fn new(album: Album, photos: Vec<Photo>) -> Self {
     Self {
-      albums: albums
-        .into_iter()
-        .map(|album| PublicAlbum::from(album))
-        .collect(),
+      slug: album.slug,
+      name: album.name,
+      cover: album.cover,
+      description: album.description,
+      location: album.location,
+      coordinates: Coordinates::from_columns(
+        album.latitude,
+        album.longitude,
+      )
+      .map(|coordinates| coordinates.rounded()),
+      visibility: album.visibility,
+      items: photos.into_iter().map(AlbumItem::from).collect(),
     }
   }
 }
 
-impl From<Album> for GetAlbumResponse {
-  fn from(album: Album) -> Self {
-    Self {
-      album: PublicAlbum::from(album),
-    }
+impl From<PublicAlbum> for GetAlbumResponse {
+  fn from(album: PublicAlbum) -> Self {
+    Self { album }
   }
 }
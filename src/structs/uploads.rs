@@ -1,7 +1,69 @@
 use actix_multipart::form::{bytes::Bytes, MultipartForm};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 
 #[derive(Debug, MultipartForm)]
 pub struct CdnUpload {
   #[multipart(rename = "file")]
   pub files: Vec<Bytes>,
 }
+
+/// What a resumable upload becomes once complete.
+pub const RESUMABLE_UPLOAD_KINDS: [&str; 4] =
+  ["images", "files", "photography", "blog"];
+
+/// Row of the `resumable_uploads` table.
+#[allow(dead_code)]
+#[derive(Debug, Clone, FromRow)]
+pub struct ResumableUpload {
+  pub id: String,
+  pub kind: String,
+  pub target: Option<String>,
+  pub file_name: Option<String>,
+  pub owner_id: Option<String>,
+  pub size: i64,
+  pub received: i64,
+  pub content_type: Option<String>,
+  pub s3_upload_id: Option<String>,
+  pub etags: Vec<String>,
+  /// `receiving` until a request starts completing the upload.
+  pub state: String,
+  /// Whether the photo the upload becomes has been added to its album.
+  pub handed_off: bool,
+  pub created_at: NaiveDateTime,
+  pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateResumableUploadPayload {
+  pub kind: String,
+  /// Total size of the file in bytes.
+  pub size: i64,
+  /// Album slug for `photography`, post id for `blog`.
+  pub target: Option<String>,
+  /// Required for `photography`, where it becomes the photo's name.
+  pub file_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResumableUploadResponse {
+  pub id: String,
+  pub kind: String,
+  pub size: i64,
+  pub received: i64,
+  pub expires_at: NaiveDateTime,
+}
+
+impl ResumableUploadResponse {
+  pub fn new(upload: &ResumableUpload, abandon_hours: i32) -> Self {
+    Self {
+      id: upload.id.clone(),
+      kind: upload.kind.clone(),
+      size: upload.size,
+      received: upload.received,
+      expires_at: upload.updated_at
+        + chrono::Duration::hours(abandon_hours as i64),
+    }
+  }
+}